@@ -0,0 +1,62 @@
+//! Measures allocations and time per event written inside a span.
+//!
+//! ```bash
+//! cargo bench --bench event_encoding
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use tracing_subscriber::prelude::*;
+
+const EVENTS: u64 = 200_000;
+
+/// Counts allocations made through the system allocator.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_ndjson::builder().with_writer(std::io::sink).layer());
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("request", id = 42, route = "/users/:id");
+        let _enter = span.enter();
+        // Warm up
+        for i in 0..EVENTS / 10 {
+            tracing::info!(attempt = i, ok = true, "handled");
+        }
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for i in 0..EVENTS {
+            tracing::info!(attempt = i, ok = true, "handled");
+        }
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        println!(
+            "{:>6.2} allocations/event {:>8.1} ns/event",
+            allocations as f64 / EVENTS as f64,
+            elapsed.as_nanos() as f64 / EVENTS as f64
+        );
+    });
+}
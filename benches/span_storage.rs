@@ -0,0 +1,81 @@
+//! Compares allocations and time per short-lived span with and without span storage pooling.
+//!
+//! ```bash
+//! cargo bench --bench span_storage
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use tracing_subscriber::prelude::*;
+
+const SPANS: u64 = 200_000;
+
+/// Counts allocations made through the system allocator.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn bench(name: &str, storage_pooling: bool) {
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_ndjson::builder()
+            .with_storage_pooling(storage_pooling)
+            .with_writer(std::io::sink)
+            .layer(),
+    );
+    tracing::subscriber::with_default(subscriber, || {
+        // Warm up
+        for i in 0..SPANS / 10 {
+            black_box(tracing::info_span!(
+                "request",
+                id = i,
+                route = "/users/:id",
+                ok = true
+            ));
+        }
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for i in 0..SPANS {
+            black_box(tracing::info_span!(
+                "request",
+                id = i,
+                route = "/users/:id",
+                ok = true
+            ));
+        }
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        println!(
+            "{:<24} {:>6.2} allocations/span {:>8.1} ns/span",
+            name,
+            allocations as f64 / SPANS as f64,
+            elapsed.as_nanos() as f64 / SPANS as f64
+        );
+    });
+}
+
+fn main() {
+    bench("without pooling", false);
+    bench("with pooling", true);
+}
@@ -0,0 +1,52 @@
+//! Compares the cost of rendering each timestamp format through the layer. RFC3339 at seconds
+//! precision is cached per second, while RFC3339 with nanoseconds is rendered for every event.
+//!
+//! ```bash
+//! cargo bench --bench timestamp
+//! ```
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tracing_ndjson::TimestampFormat;
+use tracing_subscriber::prelude::*;
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn bench(name: &str, format: TimestampFormat) -> Duration {
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_ndjson::builder()
+            .with_timestamp_format(format)
+            .with_writer(std::io::sink)
+            .layer(),
+    );
+    tracing::subscriber::with_default(subscriber, || {
+        // Warm up
+        for _ in 0..ITERATIONS / 10 {
+            tracing::info!(value = black_box(1), "event");
+        }
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            tracing::info!(value = black_box(1), "event");
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{:<24} {:>8.1} ns/iter",
+            name,
+            elapsed.as_nanos() as f64 / ITERATIONS as f64
+        );
+        elapsed
+    })
+}
+
+fn main() {
+    let millis = bench("unix millis", TimestampFormat::UnixMillis);
+    let cached = bench("rfc3339 (cached)", TimestampFormat::Rfc3339);
+    let uncached = bench("rfc3339 nanos", TimestampFormat::Rfc3339Nanos);
+
+    println!(
+        "cached rendering costs {:.1} ns over unix millis, uncached {:.1} ns",
+        (cached.as_nanos() as f64 - millis.as_nanos() as f64) / ITERATIONS as f64,
+        (uncached.as_nanos() as f64 - millis.as_nanos() as f64) / ITERATIONS as f64
+    );
+}
@@ -0,0 +1,286 @@
+//! Field adapters rewrite the fields emitted by well-known libraries into a consistent schema.
+//!
+//! Adapters are applied to event fields and span fields before they are added to a record,
+//! and are registered using `Builder::with_field_adapter`.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// Rewrites fields recorded by events and spans.
+pub trait FieldAdapter: Send + Sync {
+    /// Rewrite a single field recorded by an event or span with the given target.
+    /// Fields that are not handled by the adapter should be returned unchanged.
+    /// Returning `None` drops the field.
+    fn adapt(&self, target: &str, name: String, value: Value) -> Option<(String, Value)>;
+}
+
+/// Returns true if `target` is `prefix` or a module below it.
+pub(crate) fn target_matches(target: &str, prefix: &str) -> bool {
+    target == prefix || (target.starts_with(prefix) && target[prefix.len()..].starts_with("::"))
+}
+
+/// Maps the fields emitted by the spans and events of `tower_http::trace::TraceLayer`
+/// with its default `MakeSpan`, `OnResponse` and `OnFailure` implementations.
+///
+/// ```rust
+/// let builder = tracing_ndjson::builder()
+///     .with_field_adapter(tracing_ndjson::adapters::TowerHttpAdapter::ecs());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TowerHttpAdapter {
+    names: HashMap<String, String>,
+    /// Convert values to their ECS representation, e.g. latency in nanoseconds.
+    convert_values: bool,
+}
+
+impl TowerHttpAdapter {
+    /// Map fields to [Elastic Common Schema](https://www.elastic.co/guide/en/ecs/current/ecs-http.html)
+    /// HTTP fields. The latency is converted to nanoseconds and emitted as `event.duration`.
+    pub fn ecs() -> Self {
+        Self {
+            names: [
+                ("method", "http.request.method"),
+                ("uri", "url.original"),
+                ("version", "http.version"),
+                ("status", "http.response.status_code"),
+                ("latency", "event.duration"),
+                ("classification", "error.message"),
+            ]
+            .into_iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect(),
+            convert_values: true,
+        }
+    }
+
+    /// Rename fields using a custom mapping from TraceLayer field names (`method`, `uri`, `version`,
+    /// `status`, `latency`, `classification`) to field names of your choice.
+    /// Values are kept as recorded.
+    pub fn custom<I, K, V>(names: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Self {
+            names: names
+                .into_iter()
+                .map(|(from, to)| (from.into(), to.into()))
+                .collect(),
+            convert_values: false,
+        }
+    }
+}
+
+impl FieldAdapter for TowerHttpAdapter {
+    fn adapt(&self, target: &str, name: String, value: Value) -> Option<(String, Value)> {
+        if !target_matches(target, "tower_http::trace") {
+            return Some((name, value));
+        }
+        let value = match (name.as_str(), value) {
+            ("latency", Value::String(latency)) if self.convert_values => {
+                parse_latency_nanos(&latency).map_or(Value::String(latency), Value::from)
+            }
+            ("version", Value::String(version)) if self.convert_values => Value::String(
+                version
+                    .strip_prefix("HTTP/")
+                    .map(str::to_string)
+                    .unwrap_or(version),
+            ),
+            (_, value) => value,
+        };
+        match self.names.get(&name) {
+            Some(renamed) => Some((renamed.clone(), value)),
+            None => Some((name, value)),
+        }
+    }
+}
+
+/// Parse a latency rendered by tower-http, e.g. `12 ms`, into nanoseconds.
+fn parse_latency_nanos(latency: &str) -> Option<u64> {
+    let (amount, unit) = latency.split_once(' ')?;
+    let amount: f64 = amount.parse().ok()?;
+    let nanos = match unit {
+        "s" => amount * 1e9,
+        "ms" => amount * 1e6,
+        "μs" | "us" => amount * 1e3,
+        "ns" => amount,
+        _ => return None,
+    };
+    Some(nanos as u64)
+}
+
+/// Maps the query fields emitted by database clients (`db.statement`, `rows_affected`,
+/// `rows_returned`, `elapsed`, `elapsed_secs`) into a single namespace, e.g. `db.statement`,
+/// `db.rows_affected` and `db.duration_ms`. Statements can optionally be normalized, replacing
+/// literals with `?`, and truncated to keep query logs safe and uniform.
+///
+/// `db.statement` is recognized for all targets, the other fields only for events and spans of
+/// database client targets (`sqlx`, `diesel`, `sea_orm` and `tokio_postgres` by default).
+///
+/// ```rust
+/// let builder = tracing_ndjson::builder().with_field_adapter(
+///     tracing_ndjson::adapters::DbAdapter::new()
+///         .with_normalize_statements(true)
+///         .with_max_statement_len(1024),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct DbAdapter {
+    namespace: String,
+    targets: Vec<String>,
+    normalize_statements: bool,
+    max_statement_len: Option<usize>,
+}
+
+impl DbAdapter {
+    pub fn new() -> Self {
+        Self {
+            namespace: "db".to_string(),
+            targets: ["sqlx", "diesel", "sea_orm", "tokio_postgres"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            normalize_statements: false,
+            max_statement_len: None,
+        }
+    }
+
+    /// Set the namespace fields are emitted under.
+    /// The default is "db".
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Set the targets whose fields are treated as database client fields.
+    pub fn with_targets<I, T>(mut self, targets: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.targets = targets.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set whether to replace string and numeric literals in statements with `?`.
+    /// The default is false.
+    pub fn with_normalize_statements(mut self, normalize_statements: bool) -> Self {
+        self.normalize_statements = normalize_statements;
+        self
+    }
+
+    /// Truncate statements to at most `max_statement_len` bytes, appending `...` when truncated.
+    /// The default is no limit.
+    pub fn with_max_statement_len(mut self, max_statement_len: usize) -> Self {
+        self.max_statement_len = Some(max_statement_len);
+        self
+    }
+
+    fn statement(&self, statement: &str) -> String {
+        let mut statement = if self.normalize_statements {
+            normalize_statement(statement)
+        } else {
+            statement.to_string()
+        };
+        if let Some(max) = self.max_statement_len {
+            if statement.len() > max {
+                let mut end = max;
+                while !statement.is_char_boundary(end) {
+                    end -= 1;
+                }
+                statement.truncate(end);
+                statement.push_str("...");
+            }
+        }
+        statement
+    }
+}
+
+impl Default for DbAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FieldAdapter for DbAdapter {
+    fn adapt(&self, target: &str, name: String, value: Value) -> Option<(String, Value)> {
+        if name == "db.statement" {
+            let value = match value {
+                Value::String(statement) => Value::String(self.statement(&statement)),
+                other => other,
+            };
+            return Some((format!("{}.statement", self.namespace), value));
+        }
+        if !self.targets.iter().any(|t| target_matches(target, t)) {
+            return Some((name, value));
+        }
+        match (name.as_str(), &value) {
+            ("rows_affected" | "rows_returned", _) => {
+                Some((format!("{}.{}", self.namespace, name), value))
+            }
+            ("elapsed", Value::String(elapsed)) => match parse_duration_millis(elapsed) {
+                Some(ms) => Some((format!("{}.duration_ms", self.namespace), Value::from(ms))),
+                None => Some((name, value)),
+            },
+            ("elapsed_secs", Value::Number(secs)) => Some((
+                format!("{}.duration_ms", self.namespace),
+                Value::from(secs.as_f64().unwrap_or_default() * 1e3),
+            )),
+            _ => Some((name, value)),
+        }
+    }
+}
+
+/// Replace quoted string literals and numeric literals in a SQL statement with `?`.
+fn normalize_statement(statement: &str) -> String {
+    let mut out = String::with_capacity(statement.len());
+    let mut chars = statement.chars().peekable();
+    let mut prev: Option<char> = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // Skip to the closing quote, treating '' as an escaped quote
+                while let Some(c) = chars.next() {
+                    if c == '\'' {
+                        if chars.peek() == Some(&'\'') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                out.push('?');
+            }
+            c if c.is_ascii_digit()
+                && !prev.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '$') =>
+            {
+                while chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    chars.next();
+                }
+                out.push('?');
+            }
+            c => out.push(c),
+        }
+        prev = Some(c);
+    }
+    out
+}
+
+/// Parse a duration rendered with `Debug`, e.g. `1.5ms`, into milliseconds.
+pub(crate) fn parse_duration_millis(duration: &str) -> Option<f64> {
+    let split = duration.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (amount, unit) = duration.split_at(split);
+    let amount: f64 = amount.parse().ok()?;
+    match unit {
+        "s" => Some(amount * 1e3),
+        "ms" => Some(amount),
+        "µs" | "μs" | "us" => Some(amount / 1e3),
+        "ns" => Some(amount / 1e6),
+        _ => None,
+    }
+}
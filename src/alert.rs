@@ -0,0 +1,493 @@
+//! Secondary sinks that send alerts for records matching a rule, for environments without an
+//! alerting pipeline.
+//!
+//! ```rust,no_run
+//! use tracing_core::Level;
+//! use tracing_ndjson::alert::{AlertRule, WebhookSink};
+//!
+//! let sink = WebhookSink::new(
+//!     "http://alerts.internal/hooks/payments",
+//!     AlertRule::new(Level::ERROR).with_target("payments"),
+//!     serde_json::json!({"text": "{{level}} in {{target}}: {{message}}"}),
+//! )
+//! .with_rate_limit(5, std::time::Duration::from_secs(60));
+//! let builder = tracing_ndjson::builder().with_webhook(sink);
+//! ```
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::background::{Background, Flush};
+
+use serde_json::{Map, Value};
+use tracing_core::{Level, Metadata};
+
+use crate::forward::{Forward, SpanContext};
+use crate::FieldNames;
+
+type Predicate = Arc<dyn Fn(&Map<String, Value>) -> bool + Send + Sync>;
+
+/// Selects the records an alert is sent for.
+#[derive(Clone)]
+pub struct AlertRule {
+    min_level: Level,
+    targets: Vec<String>,
+    predicate: Option<Predicate>,
+}
+
+impl std::fmt::Debug for AlertRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AlertRule")
+            .field("min_level", &self.min_level)
+            .field("targets", &self.targets)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+impl AlertRule {
+    /// Match records at `min_level` or a more severe level, e.g. Level::ERROR.
+    pub fn new(min_level: Level) -> Self {
+        Self {
+            min_level,
+            targets: Vec::new(),
+            predicate: None,
+        }
+    }
+
+    /// Only match records whose target is `target` or a module below it. Can be called multiple
+    /// times to match any of several targets.
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Only match records for which `predicate` returns true, given the record's fields.
+    pub fn with_predicate(
+        mut self,
+        predicate: impl Fn(&Map<String, Value>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    pub(crate) fn matches(&self, metadata: &Metadata<'_>, record: &Map<String, Value>) -> bool {
+        *metadata.level() <= self.min_level
+            && (self.targets.is_empty()
+                || self
+                    .targets
+                    .iter()
+                    .any(|t| crate::adapters::target_matches(metadata.target(), t)))
+            && self
+                .predicate
+                .as_ref()
+                .is_none_or(|predicate| predicate(record))
+    }
+}
+
+/// Allows at most `max` alerts per `period`, counting the alerts suppressed in between.
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    max: u32,
+    period: Duration,
+    /// The start of the current period, the alerts sent in it, and the alerts suppressed since
+    /// the last alert was sent.
+    state: Mutex<(Instant, u32, u64)>,
+}
+
+impl RateLimit {
+    pub(crate) fn new(max: u32, period: Duration) -> Self {
+        Self {
+            max,
+            period,
+            state: Mutex::new((Instant::now(), 0, 0)),
+        }
+    }
+
+    /// Returns the number of alerts suppressed since the last one if an alert may be sent now.
+    pub(crate) fn acquire(&self) -> Option<u64> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (start, sent, suppressed) = &mut *state;
+        if start.elapsed() >= self.period {
+            *start = Instant::now();
+            *sent = 0;
+        }
+        if *sent >= self.max {
+            *suppressed += 1;
+            return None;
+        }
+        *sent += 1;
+        Some(std::mem::take(suppressed))
+    }
+}
+
+/// Render a template by replacing `{{name}}` placeholders with the values of record fields.
+/// A string that is exactly one placeholder is replaced with the value itself, keeping its type.
+/// The built-in fields are available as `{{level}}`, `{{target}}`, `{{message}}` and
+/// `{{timestamp}}` regardless of their configured names.
+pub(crate) fn render_template(
+    template: &Value,
+    names: &FieldNames<'_>,
+    record: &Map<String, Value>,
+    extra: &Map<String, Value>,
+) -> Value {
+    let lookup = |name: &str| -> Option<&Value> {
+        let name = match name {
+            "level" => names.level,
+            "target" => names.target,
+            "message" => names.message,
+            "timestamp" => names.timestamp,
+            name => name,
+        };
+        extra.get(name).or_else(|| record.get(name))
+    };
+    match template {
+        Value::String(s) => {
+            if let Some(name) = s.strip_prefix("{{").and_then(|s| s.strip_suffix("}}")) {
+                if !name.contains("{{") {
+                    return lookup(name.trim()).cloned().unwrap_or(Value::Null);
+                }
+            }
+            let mut out = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("{{") {
+                let Some(end) = rest[start..].find("}}") else {
+                    break;
+                };
+                out.push_str(&rest[..start]);
+                match lookup(rest[start + 2..start + end].trim()) {
+                    Some(Value::String(value)) => out.push_str(value),
+                    Some(value) => out.push_str(&value.to_string()),
+                    None => {}
+                }
+                rest = &rest[start + end + 2..];
+            }
+            out.push_str(rest);
+            Value::String(out)
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render_template(value, names, record, extra))
+                .collect(),
+        ),
+        Value::Object(values) => Value::Object(
+            values
+                .iter()
+                .map(|(k, v)| (k.clone(), render_template(v, names, record, extra)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+type Transport = Arc<dyn Fn(&str, &[u8]) -> std::io::Result<()> + Send + Sync>;
+
+/// Sends a JSON payload rendered from a template to an HTTP webhook, e.g. a Slack incoming
+/// webhook, for records matching a rule. Alerts are rate limited, and sent on a background
+/// thread so they never block the application.
+///
+/// The template is any JSON value, where `{{name}}` placeholders in strings are replaced with
+/// the values of record fields, and `{{suppressed}}` with the number of alerts suppressed by the
+/// rate limit since the last alert.
+///
+/// At most 10,000 alerts are queued; further alerts are dropped until the queue drains.
+/// Alerts still queued when the layer is dropped are sent before the drop returns.
+///
+/// The built-in transport only supports `http://` URLs. Use `with_transport` to send alerts
+/// with an HTTP client of your choice, e.g. for HTTPS.
+#[derive(Clone)]
+pub struct WebhookSink {
+    url: String,
+    rule: AlertRule,
+    template: Value,
+    rate_limit: Arc<RateLimit>,
+    transport: Transport,
+    queue: Arc<Background<Vec<u8>>>,
+}
+
+impl std::fmt::Debug for WebhookSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookSink")
+            .field("url", &self.url)
+            .field("rule", &self.rule)
+            .field("template", &self.template)
+            .field("rate_limit", &self.rate_limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebhookSink {
+    /// Post the rendered template to `url` for records matching `rule`.
+    /// The default rate limit is 10 alerts per minute.
+    pub fn new(url: impl Into<String>, rule: AlertRule, template: Value) -> Self {
+        Self {
+            url: url.into(),
+            rule,
+            template,
+            rate_limit: Arc::new(RateLimit::new(10, Duration::from_secs(60))),
+            transport: Arc::new(crate::transport::post),
+            queue: Arc::new(Background::new("tracing-ndjson-webhook")),
+        }
+    }
+
+    /// Send at most `max` alerts per `period`.
+    pub fn with_rate_limit(mut self, max: u32, period: Duration) -> Self {
+        self.rate_limit = Arc::new(RateLimit::new(max, period));
+        self
+    }
+
+    /// Send alerts using a custom transport, called with the URL and the JSON payload.
+    pub fn with_transport(
+        mut self,
+        transport: impl Fn(&str, &[u8]) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Returns the number of alerts dropped because too many were queued.
+    pub fn dropped_alerts(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    /// Queue a payload for the background thread, starting it on first use.
+    fn send(&self, payload: Vec<u8>) {
+        self.queue.send(payload, Flush::each(), || {
+            let url = self.url.clone();
+            let transport = self.transport.clone();
+            Box::new(move |payloads| {
+                for payload in payloads {
+                    let _ = transport(&url, &payload);
+                }
+            })
+        });
+    }
+}
+
+impl Forward for WebhookSink {
+    fn forward(
+        &self,
+        metadata: &Metadata<'_>,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        _spans: &[SpanContext],
+    ) {
+        if !self.rule.matches(metadata, record) {
+            return;
+        }
+        let Some(suppressed) = self.rate_limit.acquire() else {
+            return;
+        };
+        let mut extra = Map::new();
+        extra.insert("suppressed".to_string(), Value::from(suppressed));
+        let payload = render_template(&self.template, names, record, &extra);
+        if let Ok(payload) = serde_json::to_vec(&payload) {
+            self.send(payload);
+        }
+    }
+}
+
+/// An email digest of the records matched by an `EmailDigest` sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    /// The matched records, one JSON object per line.
+    pub body: String,
+}
+
+type MailTransport = Arc<dyn Fn(&str, &Email) -> std::io::Result<()> + Send + Sync>;
+
+/// Batches records matching a rule and sends them as an email digest over SMTP, at most once
+/// per interval. Records are collected and sent on a background thread; any records still
+/// pending when the layer is dropped are sent before the drop returns. At most 10,000 records
+/// are queued; further records are dropped until the queue drains.
+///
+/// The built-in transport speaks plain SMTP without authentication or TLS, as offered by a
+/// local relay. Use `with_transport` to send the digest some other way.
+#[derive(Clone)]
+pub struct EmailDigest {
+    server: String,
+    from: String,
+    to: Vec<String>,
+    subject: String,
+    rule: AlertRule,
+    interval: Duration,
+    transport: MailTransport,
+    queue: Arc<Background<String>>,
+}
+
+impl std::fmt::Debug for EmailDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailDigest")
+            .field("server", &self.server)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("subject", &self.subject)
+            .field("rule", &self.rule)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EmailDigest {
+    /// Mail records matching `rule` from `from` to each address in `to`, through the SMTP
+    /// server at `server` (`host:port`). The default interval is 5 minutes.
+    pub fn new(
+        server: impl Into<String>,
+        from: impl Into<String>,
+        to: impl IntoIterator<Item = impl Into<String>>,
+        rule: AlertRule,
+    ) -> Self {
+        Self {
+            server: server.into(),
+            from: from.into(),
+            to: to.into_iter().map(Into::into).collect(),
+            subject: "Critical log records".to_string(),
+            rule,
+            interval: Duration::from_secs(300),
+            transport: Arc::new(send_mail),
+            queue: Arc::new(Background::new("tracing-ndjson-email")),
+        }
+    }
+
+    /// Send at most one digest per `interval`.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the subject of the digest. The number of records is appended, e.g. "(3 records)".
+    pub fn with_subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    /// Send digests using a custom transport, called with the server and the email.
+    pub fn with_transport(
+        mut self,
+        transport: impl Fn(&str, &Email) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Returns the number of records dropped because too many were queued.
+    pub fn dropped_records(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    /// Queue a record for the next digest, starting the background thread on first use. The
+    /// thread only owns the settings it needs, so the queue closes when the last clone of the
+    /// digest is dropped.
+    fn send(&self, line: String) {
+        let flush = Flush {
+            size: usize::MAX,
+            interval: Some(self.interval),
+        };
+        self.queue.send(line, flush, || {
+            let server = self.server.clone();
+            let from = self.from.clone();
+            let to = self.to.clone();
+            let subject = self.subject.clone();
+            let transport = self.transport.clone();
+            Box::new(move |lines| {
+                let email = Email {
+                    from: from.clone(),
+                    to: to.clone(),
+                    subject: format!(
+                        "{} ({} record{})",
+                        subject,
+                        lines.len(),
+                        if lines.len() == 1 { "" } else { "s" }
+                    ),
+                    body: lines.join("\n"),
+                };
+                let _ = transport(&server, &email);
+            })
+        });
+    }
+}
+
+impl Forward for EmailDigest {
+    fn forward(
+        &self,
+        metadata: &Metadata<'_>,
+        _names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        _spans: &[SpanContext],
+    ) {
+        if !self.rule.matches(metadata, record) {
+            return;
+        }
+        if let Ok(line) = serde_json::to_string(record) {
+            self.send(line);
+        }
+    }
+}
+
+/// Send an email over plain SMTP.
+fn send_mail(server: &str, email: &Email) -> std::io::Result<()> {
+    let stream = TcpStream::connect(server)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    // Read a possibly multi-line reply and check its status code
+    let mut expect = |code: &str| -> std::io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || !line.starts_with(code) {
+                return Err(std::io::Error::other(format!(
+                    "unexpected SMTP reply: {}",
+                    line.trim_end()
+                )));
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    };
+    let hostname = crate::file::hostname();
+
+    expect("220")?;
+    write!(writer, "HELO {}\r\n", hostname)?;
+    expect("250")?;
+    write!(writer, "MAIL FROM:<{}>\r\n", email.from)?;
+    expect("250")?;
+    for to in &email.to {
+        write!(writer, "RCPT TO:<{}>\r\n", to)?;
+        expect("25")?;
+    }
+    write!(writer, "DATA\r\n")?;
+    expect("354")?;
+    write!(
+        writer,
+        "From: <{}>\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        email.from,
+        email
+            .to
+            .iter()
+            .map(|to| format!("<{}>", to))
+            .collect::<Vec<_>>()
+            .join(", "),
+        email.subject
+    )?;
+    for line in email.body.lines() {
+        // Dot-stuff lines so they aren't read as the end of the message
+        if line.starts_with('.') {
+            writer.write_all(b".")?;
+        }
+        write!(writer, "{}\r\n", line)?;
+    }
+    write!(writer, ".\r\n")?;
+    expect("250")?;
+    write!(writer, "QUIT\r\n")?;
+    let _ = expect("221");
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! A bounded queue drained in batches by a background thread, shared by the sinks that send
+//! records over the network so a slow or unreachable endpoint never blocks the application.
+//!
+//! The queue holds at most `QUEUE_CAPACITY` items. When it is full, new items are dropped and
+//! counted instead of blocking the caller. Dropping the queue closes it and waits for the thread
+//! to deliver the items still queued.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// The maximum number of items queued for a background thread.
+pub(crate) const QUEUE_CAPACITY: usize = 10_000;
+
+/// Delivers a batch of items, e.g. by posting it to an endpoint.
+pub(crate) type Deliver<T> = Box<dyn FnMut(Vec<T>) + Send>;
+
+/// When queued items are delivered: as soon as `size` items are pending, or `interval` after the
+/// last delivery if set.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Flush {
+    pub(crate) size: usize,
+    pub(crate) interval: Option<Duration>,
+}
+
+impl Flush {
+    /// Deliver each item on its own as soon as it is queued.
+    #[cfg(feature = "alert")]
+    pub(crate) fn each() -> Self {
+        Self {
+            size: 1,
+            interval: None,
+        }
+    }
+}
+
+/// Queues items for a background thread that delivers them in batches.
+#[derive(Debug)]
+pub(crate) struct Background<T> {
+    name: &'static str,
+    sender: Mutex<Option<SyncSender<T>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    dropped: AtomicU64,
+}
+
+impl<T: Send + 'static> Background<T> {
+    /// Create a queue whose thread, started on first use, has the given name.
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            sender: Mutex::new(None),
+            worker: Mutex::new(None),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of items dropped because the queue was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue an item, starting the background thread that delivers batches with the function
+    /// returned by `deliver` on first use. The item is dropped if the queue is full.
+    pub(crate) fn send(&self, item: T, flush: Flush, deliver: impl FnOnce() -> Deliver<T>) {
+        let mut sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        if sender.is_none() {
+            let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+            let mut deliver = deliver();
+            let worker = std::thread::Builder::new()
+                .name(self.name.to_string())
+                .spawn(move || {
+                    let mut pending = Vec::new();
+                    let mut deadline = flush.interval.map(|interval| Instant::now() + interval);
+                    loop {
+                        let received = match deadline {
+                            Some(deadline) => {
+                                rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                            }
+                            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                        };
+                        let disconnected = match received {
+                            Ok(item) => {
+                                pending.push(item);
+                                if pending.len() < flush.size {
+                                    continue;
+                                }
+                                false
+                            }
+                            Err(RecvTimeoutError::Timeout) => false,
+                            Err(RecvTimeoutError::Disconnected) => true,
+                        };
+                        if !pending.is_empty() {
+                            deliver(std::mem::take(&mut pending));
+                        }
+                        if disconnected {
+                            return;
+                        }
+                        deadline = flush.interval.map(|interval| Instant::now() + interval);
+                    }
+                });
+            let Ok(worker) = worker else {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            };
+            *self.worker.lock().unwrap_or_else(|e| e.into_inner()) = Some(worker);
+            *sender = Some(tx);
+        }
+        if sender
+            .as_ref()
+            .is_some_and(|sender| sender.try_send(item).is_err())
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T> Drop for Background<T> {
+    /// Close the queue and wait for the thread to deliver the items still queued.
+    fn drop(&mut self) {
+        self.sender
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(worker) = self
+            .worker
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            let _ = worker.join();
+        }
+    }
+}
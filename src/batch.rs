@@ -0,0 +1,141 @@
+//! Summarizing the errors of a batch operation in a single record, instead of writing a record
+//! per error.
+//!
+//! An `ErrorBatch` accumulates errors, e.g. the rows of an import that failed to parse, and writes
+//! one ERROR record when it is finished or dropped, with the first errors in an `errors` array,
+//! the number of errors in `errors.total` and their number by kind in `errors.by_kind`. The record
+//! is written with the field names, encoder and output of the layer.
+//!
+//! ```rust
+//! let builder = tracing_ndjson::builder();
+//! let batches = builder.error_batches();
+//!
+//! let batch = batches.start("import users");
+//! for (row, line) in ["1,jane", "two,john"].iter().enumerate() {
+//!     if let Err(e) = line.split(',').next().unwrap().parse::<u32>() {
+//!         batch.push_with_fields("parse", e, [("row", serde_json::json!(row))]);
+//!     }
+//! }
+//! batch.finish();
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde_json::{json, Map, Value};
+
+use crate::JsonFormattingLayer;
+
+/// The number of errors included in the record by default.
+const DEFAULT_MAX_ERRORS: usize = 100;
+
+/// Starts error batches whose records are written by layers created by a builder, retrieved
+/// using `Builder::error_batches`.
+#[derive(Debug, Clone)]
+pub struct ErrorBatches {
+    pub(crate) layer: JsonFormattingLayer,
+}
+
+impl ErrorBatches {
+    /// Start accumulating the errors of an operation, e.g. `import users`.
+    pub fn start(&self, operation: impl Into<String>) -> ErrorBatch {
+        ErrorBatch {
+            layer: self.layer.clone(),
+            operation: operation.into(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            errors: Mutex::new(Errors::default()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Errors {
+    errors: Vec<Value>,
+    total: u64,
+    by_kind: BTreeMap<String, u64>,
+}
+
+/// Accumulates the errors of a batch operation, writing a summary record when finished or
+/// dropped if any errors were pushed. Errors can be pushed from several threads.
+#[derive(Debug)]
+pub struct ErrorBatch {
+    layer: JsonFormattingLayer,
+    operation: String,
+    max_errors: usize,
+    errors: Mutex<Errors>,
+}
+
+impl ErrorBatch {
+    /// Set the number of errors included in the `errors` array. Later errors are only counted.
+    /// The default is 100.
+    pub fn with_max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Add an error of a kind, e.g. `parse` or `duplicate`.
+    pub fn push(&self, kind: &str, error: impl std::fmt::Display) {
+        self.push_with_fields(kind, error, std::iter::empty::<(&str, Value)>());
+    }
+
+    /// Add an error of a kind with fields describing it, e.g. the row it occurred in.
+    pub fn push_with_fields<K: Into<String>>(
+        &self,
+        kind: &str,
+        error: impl std::fmt::Display,
+        fields: impl IntoIterator<Item = (K, Value)>,
+    ) {
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        errors.total += 1;
+        *errors.by_kind.entry(kind.to_string()).or_default() += 1;
+        if errors.errors.len() < self.max_errors {
+            let mut entry = Map::new();
+            entry.insert("kind".to_string(), json!(kind));
+            entry.insert(
+                self.layer.message_name.to_string(),
+                json!(error.to_string()),
+            );
+            for (name, value) in fields {
+                entry.insert(name.into(), value);
+            }
+            errors.errors.push(Value::Object(entry));
+        }
+    }
+
+    /// Returns the number of errors pushed.
+    pub fn len(&self) -> u64 {
+        self.errors.lock().unwrap_or_else(|e| e.into_inner()).total
+    }
+
+    /// Returns whether no errors were pushed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the summary record if any errors were pushed.
+    pub fn finish(self) {}
+}
+
+impl Drop for ErrorBatch {
+    fn drop(&mut self) {
+        let errors = std::mem::take(self.errors.get_mut().unwrap_or_else(|e| e.into_inner()));
+        if errors.total == 0 {
+            return;
+        }
+        let layer = &self.layer;
+        let mut record = layer.notice_record(
+            tracing_core::Level::ERROR,
+            "tracing_ndjson::batch",
+            &format!("{} finished with {} errors", self.operation, errors.total),
+        );
+        record.insert("batch.operation".to_string(), json!(self.operation));
+        record.insert("errors.total".to_string(), json!(errors.total));
+        record.insert("errors.by_kind".to_string(), json!(errors.by_kind));
+        let omitted = errors.total - errors.errors.len() as u64;
+        if omitted > 0 {
+            record.insert("errors.omitted".to_string(), json!(omitted));
+        }
+        record.insert("errors".to_string(), Value::Array(errors.errors));
+        layer.write_record(&record, tracing_core::Level::ERROR);
+    }
+}
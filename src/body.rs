@@ -0,0 +1,176 @@
+//! Helpers for logging HTTP and gRPC request/response bodies as fields.
+//!
+//! Bodies are limited to a size budget, rendered according to their content type, and embedded
+//! as a JSON object rather than a double-escaped string when the body is itself JSON.
+//!
+//! ```rust
+//! let payload = br#"{"user":"cole","admin":false}"#;
+//! tracing::info!(
+//!     http.request.body = %tracing_ndjson::body::capture(payload, Some("application/json")),
+//!     "received request",
+//! );
+//! // {"http.request.body":{"admin":false,"user":"cole"},"message":"received request",...}
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+
+/// The default maximum number of body bytes captured.
+pub const DEFAULT_BUDGET: usize = 4096;
+
+thread_local! {
+    /// The JSON bodies rendered while values are formatted by `render_debug` on this thread, one
+    /// slot per nested call with the innermost last, so a body is only embedded by the visitor
+    /// that formatted it.
+    static RENDERING: RefCell<Vec<Option<String>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How a captured body is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    /// The body is JSON and is embedded as JSON.
+    Json,
+    /// The body is text and is rendered as a string.
+    Text,
+    /// The body is binary and only its size is rendered.
+    Binary,
+}
+
+/// A request or response body to be recorded as a field using `%` (Display).
+#[derive(Debug, Clone, Copy)]
+pub struct Body<'a> {
+    bytes: &'a [u8],
+    kind: BodyKind,
+    budget: usize,
+}
+
+/// Capture a body for logging, detecting how to render it from the content type.
+/// When no content type is given, the body is sniffed.
+pub fn capture<'a>(bytes: &'a [u8], content_type: Option<&str>) -> Body<'a> {
+    Body {
+        bytes,
+        kind: detect(bytes, content_type),
+        budget: DEFAULT_BUDGET,
+    }
+}
+
+impl Body<'_> {
+    /// Set the maximum number of body bytes captured. Text bodies are truncated, and JSON bodies
+    /// larger than the budget are rendered as truncated text.
+    /// The default is `DEFAULT_BUDGET`.
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Returns how the body will be rendered.
+    pub fn kind(&self) -> BodyKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            BodyKind::Json if self.bytes.len() <= self.budget => {
+                let json = String::from_utf8_lossy(self.bytes);
+                RENDERING.with(|rendering| {
+                    if let Some(slot) = rendering.borrow_mut().last_mut() {
+                        *slot = Some(json.to_string());
+                    }
+                });
+                f.write_str(&json)
+            }
+            BodyKind::Json | BodyKind::Text => {
+                let len = self.bytes.len().min(self.budget);
+                let text = match std::str::from_utf8(&self.bytes[..len]) {
+                    Ok(text) => text,
+                    // Don't cut a multi-byte character in half
+                    Err(e) => std::str::from_utf8(&self.bytes[..e.valid_up_to()]).expect("valid"),
+                };
+                f.write_str(text)?;
+                if text.len() < self.bytes.len() {
+                    write!(f, "...[{} bytes truncated]", self.bytes.len() - text.len())?;
+                }
+                Ok(())
+            }
+            BodyKind::Binary => write!(f, "[{} bytes of binary data]", self.bytes.len()),
+        }
+    }
+}
+
+/// Removes the slot of a `render_debug` call when it returns or unwinds.
+struct Slot;
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let _ = RENDERING.try_with(|rendering| rendering.borrow_mut().pop());
+    }
+}
+
+/// Append a value rendered using its `Debug` implementation to `rendered`, returning the parsed
+/// body if the value is a JSON `Body`, e.g. recorded using `%`.
+pub(crate) fn render_debug(
+    rendered: &mut String,
+    value: &dyn fmt::Debug,
+) -> Option<serde_json::Value> {
+    RENDERING.with(|rendering| rendering.borrow_mut().push(None));
+    let slot = Slot;
+    let start = rendered.len();
+    let _ = fmt::Write::write_fmt(rendered, format_args!("{:?}", value));
+    let embedded = RENDERING.with(|rendering| rendering.borrow_mut().last_mut()?.take());
+    drop(slot);
+    // A body rendered by the value, but not as the whole value, is not embedded
+    let embedded = embedded.filter(|embedded| *embedded == rendered[start..])?;
+    serde_json::from_str(&embedded).ok()
+}
+
+fn detect(bytes: &[u8], content_type: Option<&str>) -> BodyKind {
+    let content_type = content_type.map(|ct| {
+        ct.split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    });
+    match content_type.as_deref() {
+        Some(ct) if ct.ends_with("json") => {
+            if serde_json::from_slice::<serde_json::Value>(bytes).is_ok() {
+                BodyKind::Json
+            } else {
+                BodyKind::Text
+            }
+        }
+        Some(ct)
+            if ct.starts_with("text/")
+                || ct.ends_with("xml")
+                || ct.ends_with("javascript")
+                || ct == "application/x-www-form-urlencoded" =>
+        {
+            BodyKind::Text
+        }
+        Some(ct) if ct.starts_with("application/grpc") || ct == "application/octet-stream" => {
+            BodyKind::Binary
+        }
+        _ => sniff(bytes),
+    }
+}
+
+fn sniff(bytes: &[u8]) -> BodyKind {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return BodyKind::Binary;
+    };
+    let trimmed = text.trim_start();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        BodyKind::Json
+    } else if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+    {
+        BodyKind::Binary
+    } else {
+        BodyKind::Text
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing_core::Level;
+
+use crate::EventFields;
+
+/// Temporarily emits verbose events for a correlation key after an ERROR event with that key.
+#[derive(Debug)]
+pub(crate) struct VerbosityBoost {
+    field: String,
+    window: Duration,
+    level: Level,
+    boosted: Mutex<HashMap<String, Instant>>,
+}
+
+impl VerbosityBoost {
+    pub(crate) fn new(field: String, window: Duration, level: Level) -> Self {
+        Self {
+            field,
+            window,
+            level,
+            boosted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub(crate) fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Returns whether an event is emitted. INFO and less verbose events are always emitted, and
+    /// more verbose events only while their correlation key is boosted. ERROR events start or
+    /// extend the boost window of their correlation key.
+    pub(crate) fn allow(&self, level: Level, fields: &EventFields<'_>) -> bool {
+        if level == Level::ERROR {
+            if let Some(key) = self.key(fields) {
+                let now = Instant::now();
+                let mut boosted = self.boosted.lock().unwrap_or_else(|e| e.into_inner());
+                boosted.retain(|_, until| *until > now);
+                boosted.insert(key, now + self.window);
+            }
+            return true;
+        }
+        if level <= Level::INFO {
+            return true;
+        }
+        if level > self.level {
+            return false;
+        }
+        let Some(key) = self.key(fields) else {
+            return false;
+        };
+        let boosted = self.boosted.lock().unwrap_or_else(|e| e.into_inner());
+        boosted
+            .get(&key)
+            .is_some_and(|until| *until > Instant::now())
+    }
+
+    fn key(&self, fields: &EventFields<'_>) -> Option<String> {
+        match fields.get(&self.field)? {
+            serde_json::Value::String(key) => Some(key.clone()),
+            serde_json::Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tracing_core::callsite::Identifier;
+
+/// Symbol prefixes that belong to the standard library, the tracing ecosystem or this crate,
+/// and should therefore never be reported as the caller of an event.
+const SKIPPED_PREFIXES: &[&str] = &[
+    "std::",
+    "core::",
+    "alloc::",
+    "backtrace::",
+    "tracing::",
+    "tracing_core::",
+    "tracing_subscriber::",
+    "tracing_ndjson::caller::",
+    "tracing_ndjson::layer::",
+];
+
+/// Resolves the function path of the code that emitted an event.
+/// Symbolication is expensive, so the result is cached per callsite.
+#[derive(Debug, Default)]
+pub(crate) struct CallerCache {
+    callers: Mutex<HashMap<Identifier, Option<String>>>,
+}
+
+impl Clone for CallerCache {
+    fn clone(&self) -> Self {
+        let callers = self.callers.lock().unwrap_or_else(|e| e.into_inner());
+        Self {
+            callers: Mutex::new(callers.clone()),
+        }
+    }
+}
+
+impl CallerCache {
+    pub(crate) fn caller(&self, callsite: Identifier) -> Option<String> {
+        if let Some(caller) = self
+            .callers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&callsite)
+        {
+            return caller.clone();
+        }
+        // Capture and symbolicate without holding the lock, so other threads keep logging. Threads
+        // racing on the same callsite resolve the same caller, and the first one is kept.
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        let caller = find_caller(&backtrace.to_string());
+        self.callers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(callsite)
+            .or_insert(caller)
+            .clone()
+    }
+}
+
+/// Find the first frame in a rendered backtrace that is not part of std, tracing or this crate.
+pub(crate) fn find_caller(backtrace: &str) -> Option<String> {
+    backtrace
+        .lines()
+        .filter_map(|line| {
+            let (index, symbol) = line.trim().split_once(": ")?;
+            index.parse::<usize>().ok()?;
+            Some(symbol)
+        })
+        .map(strip_hash)
+        .find(|symbol| {
+            let path = symbol.trim_start_matches('<');
+            !path.starts_with("__")
+                && !path.starts_with("rust_")
+                && !SKIPPED_PREFIXES
+                    .iter()
+                    .any(|prefix| path.starts_with(prefix))
+        })
+        .map(|symbol| symbol.to_string())
+}
+
+/// Remove the trailing `::h<hash>` that rustc appends to legacy-mangled symbols.
+fn strip_hash(symbol: &str) -> &str {
+    match symbol.rsplit_once("::h") {
+        Some((path, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            path
+        }
+        _ => symbol,
+    }
+}
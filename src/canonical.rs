@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use serde_json::{Map, Value};
+use tracing_core::Level;
+
+/// How a field recorded by several events of a canonical log line is merged, set using
+/// `Builder::with_canonical_log_line`.
+/// The default is to keep the last value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalConflict {
+    /// Keep the value of the last event.
+    #[default]
+    Last,
+    /// Keep the value of the first event.
+    First,
+    /// Collect the values of all events into an array, in order.
+    Collect,
+}
+
+/// Aggregates the child spans of canonical log line spans whose names match a pattern, e.g. the
+/// database queries of a request, into fields of the canonical log line. Added using
+/// `Builder::with_canonical_rollup`.
+///
+/// ```rust
+/// use tracing_ndjson::{CanonicalConflict, CanonicalRollup};
+///
+/// let builder = tracing_ndjson::builder()
+///     .with_canonical_log_line("request", CanonicalConflict::Last)
+///     .with_canonical_rollup(
+///         CanonicalRollup::new("db.*")
+///             .with_count("db.query_count")
+///             .with_total_ms("db.total_ms"),
+///     )
+///     .with_canonical_rollup(CanonicalRollup::new("http.client").with_count("http.client.calls"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CanonicalRollup {
+    pub(crate) pattern: String,
+    pub(crate) count_field: Option<String>,
+    pub(crate) total_ms_field: Option<String>,
+}
+
+impl CanonicalRollup {
+    /// Aggregate child spans whose name matches `pattern`, where `*` matches any sequence of
+    /// characters, e.g. `db.*`.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            count_field: None,
+            total_ms_field: None,
+        }
+    }
+
+    /// Write the number of matching spans in the given field.
+    pub fn with_count(mut self, field: impl Into<String>) -> Self {
+        self.count_field = Some(field.into());
+        self
+    }
+
+    /// Write the total duration of matching spans in milliseconds, from creation to close, in
+    /// the given field.
+    pub fn with_total_ms(mut self, field: impl Into<String>) -> Self {
+        self.total_ms_field = Some(field.into());
+        self
+    }
+}
+
+/// The fields of the events within a canonical log line span, stored in its extensions until
+/// it closes.
+#[derive(Debug)]
+pub(crate) struct CanonicalLine {
+    fields: Map<String, Value>,
+    /// Fields whose values were collected into an array.
+    collected: HashSet<String>,
+    /// The most severe level observed.
+    level: Level,
+    events: u64,
+    /// The aggregates of child spans, added after the fields of events.
+    rollups: Map<String, Value>,
+}
+
+impl CanonicalLine {
+    /// Start a canonical log line at the level of its span.
+    pub(crate) fn new(level: Level) -> Self {
+        Self {
+            fields: Map::new(),
+            collected: HashSet::new(),
+            level,
+            events: 0,
+            rollups: Map::new(),
+        }
+    }
+
+    /// Merge the fields of an event into the line.
+    pub(crate) fn absorb(
+        &mut self,
+        fields: Map<String, Value>,
+        level: Level,
+        conflict: CanonicalConflict,
+    ) {
+        // More severe levels compare as less
+        self.level = self.level.min(level);
+        self.events += 1;
+        for (name, value) in fields {
+            let Some(existing) = self.fields.get_mut(&name) else {
+                self.fields.insert(name, value);
+                continue;
+            };
+            match conflict {
+                CanonicalConflict::Last => *existing = value,
+                CanonicalConflict::First => {}
+                CanonicalConflict::Collect => {
+                    if self.collected.contains(&name) {
+                        if let Value::Array(values) = existing {
+                            values.push(value);
+                        }
+                    } else {
+                        *existing = Value::Array(vec![existing.take(), value]);
+                        self.collected.insert(name);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Count a closed child span matching a rollup, with its duration in milliseconds.
+    pub(crate) fn roll_up(&mut self, rollup: &CanonicalRollup, duration_ms: f64) {
+        if let Some(field) = &rollup.count_field {
+            let count = self.rollups.get(field).and_then(Value::as_u64).unwrap_or(0);
+            self.rollups.insert(field.clone(), Value::from(count + 1));
+        }
+        if let Some(field) = &rollup.total_ms_field {
+            let total = self
+                .rollups
+                .get(field)
+                .and_then(Value::as_f64)
+                .unwrap_or(0.0);
+            self.rollups
+                .insert(field.clone(), Value::from(total + duration_ms));
+        }
+    }
+
+    /// Returns the merged fields with the rollups, which are zero if no child spans matched, the
+    /// most severe level observed and the number of events.
+    pub(crate) fn finish(
+        mut self,
+        rollups: &[CanonicalRollup],
+    ) -> (Map<String, Value>, Level, u64) {
+        for rollup in rollups {
+            if let Some(field) = &rollup.count_field {
+                self.rollups.entry(field.clone()).or_insert(Value::from(0));
+            }
+            if let Some(field) = &rollup.total_ms_field {
+                self.rollups
+                    .entry(field.clone())
+                    .or_insert(Value::from(0.0));
+            }
+        }
+        self.fields.extend(self.rollups);
+        (self.fields, self.level, self.events)
+    }
+}
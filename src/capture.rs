@@ -0,0 +1,68 @@
+/// How a field value was captured, i.e. with the `%` (Display) or `?` (Debug) sigil.
+/// Fields recorded as strings, numbers or booleans have no capture mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptureMode {
+    /// Captured with `%`, e.g. `user = %user`.
+    Display,
+    /// Captured with `?`, e.g. `request = ?request`.
+    Debug,
+}
+
+impl CaptureMode {
+    /// Returns the prefix of fields captured in this mode under `CapturePolicy::Prefix`,
+    /// e.g. `debug.`.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            CaptureMode::Display => "display.",
+            CaptureMode::Debug => "debug.",
+        }
+    }
+}
+
+/// How fields captured in a mode are written, set using `Builder::with_capture_policy`.
+/// The default is to write them unchanged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CapturePolicy {
+    /// Write the field unchanged.
+    #[default]
+    Keep,
+    /// Prefix the field name with the capture mode, e.g. `debug.request`.
+    Prefix,
+    /// Drop the field.
+    Drop,
+}
+
+/// Classify how a value recorded using `record_debug` was captured, from the rendering that is
+/// written to the record, so the value is formatted only once.
+///
+/// `tracing` records both `%` and `?` captures using `record_debug`, while values recorded using
+/// `record_str` and the other typed methods have no capture mode. Renderings in Debug syntax are
+/// considered Debug captures: quoted strings, and collections, structs, tuples and enum variants
+/// with fields, e.g. `[1.5]` or `Config { ratio: 0.5 }`. Anything else, e.g. numbers, unit
+/// variants and unquoted text, is considered a Display capture.
+pub(crate) fn detect(rendered: &str) -> CaptureMode {
+    let quoted = rendered.len() >= 2 && rendered.starts_with('"') && rendered.ends_with('"');
+    if quoted || is_debug_syntax(rendered) {
+        CaptureMode::Debug
+    } else {
+        CaptureMode::Display
+    }
+}
+
+/// Returns whether a rendering is a collection, or a struct, tuple or enum variant with fields,
+/// i.e. an optional type path followed by a bracketed body, e.g. `Point(1.5, 2.5)`.
+fn is_debug_syntax(rendered: &str) -> bool {
+    let Some(open) = rendered.find(['(', '[', '{']) else {
+        return false;
+    };
+    let close = match rendered.as_bytes()[open] {
+        b'(' => ')',
+        b'[' => ']',
+        _ => '}',
+    };
+    let path = rendered[..open].trim_end();
+    rendered.ends_with(close)
+        && path
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '<' | '>'))
+}
@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// The clock used to timestamp records.
+/// The default is to read the system clock for every event.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// Read the system clock for every event.
+    #[default]
+    Precise,
+    /// Read a cached time that a background thread refreshes at the given interval.
+    /// Timestamps may lag behind the actual time by up to the interval, in exchange for
+    /// avoiding a clock read per event at very high event rates.
+    Coarse(Duration),
+    /// Derive the time from a monotonic clock anchored to the system clock when the layer is
+    /// created, so steps of the system clock (e.g. an NTP step or a VM pause) never produce
+    /// out-of-order timestamps within the process. Timestamps are strictly increasing at
+    /// nanosecond precision. The time drifts from the system clock as long as the process runs.
+    Monotonic,
+}
+
+/// The runtime state of a `Clock`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ClockSource {
+    clock: Clock,
+    /// Nanoseconds since UNIX_EPOCH, refreshed by a background thread for coarse clocks.
+    cached: Option<Arc<AtomicI64>>,
+    monotonic: Option<Arc<Monotonic>>,
+}
+
+/// A monotonic clock anchored to the system clock.
+#[derive(Debug)]
+struct Monotonic {
+    /// Nanoseconds since UNIX_EPOCH at `start`.
+    anchor: i64,
+    start: Instant,
+    /// The last timestamp handed out, in nanoseconds since UNIX_EPOCH.
+    last: AtomicI64,
+}
+
+impl Monotonic {
+    fn new() -> Self {
+        let anchor = now_nanos();
+        Self {
+            anchor,
+            start: Instant::now(),
+            last: AtomicI64::new(anchor - 1),
+        }
+    }
+
+    fn now(&self) -> i64 {
+        let elapsed = self.start.elapsed().as_nanos() as i64;
+        let now = self.anchor.saturating_add(elapsed);
+        // Never hand out the same timestamp twice, even to events on different threads
+        let last = self
+            .last
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(now);
+        now.max(last + 1)
+    }
+}
+
+impl ClockSource {
+    pub(crate) fn new(clock: Clock) -> Self {
+        match clock {
+            Clock::Precise => Self {
+                clock,
+                cached: None,
+                monotonic: None,
+            },
+            Clock::Monotonic => Self {
+                clock,
+                cached: None,
+                monotonic: Some(Arc::new(Monotonic::new())),
+            },
+            Clock::Coarse(interval) => {
+                let cached = Arc::new(AtomicI64::new(now_nanos()));
+                let weak = Arc::downgrade(&cached);
+                std::thread::Builder::new()
+                    .name("tracing-ndjson-clock".to_string())
+                    .spawn(move || tick(weak, interval))
+                    .expect("failed to spawn clock thread");
+                Self {
+                    clock,
+                    cached: Some(cached),
+                    monotonic: None,
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Returns the current time in nanoseconds since UNIX_EPOCH.
+    pub(crate) fn now(&self) -> i64 {
+        if let Some(monotonic) = &self.monotonic {
+            return monotonic.now();
+        }
+        match &self.cached {
+            Some(cached) => cached.load(Ordering::Relaxed),
+            None => now_nanos(),
+        }
+    }
+}
+
+/// Refresh the cached time until the owning layer is dropped.
+fn tick(cached: Weak<AtomicI64>, interval: Duration) {
+    while let Some(cached) = cached.upgrade() {
+        cached.store(now_nanos(), Ordering::Relaxed);
+        drop(cached);
+        std::thread::sleep(interval);
+    }
+}
+
+/// Render a time in nanoseconds since UNIX_EPOCH as an RFC3339 timestamp in UTC, e.g.
+/// `2023-10-08T03:30:52Z`, optionally with nanoseconds, e.g. `2023-10-08T03:30:52.123456789Z`.
+pub(crate) fn rfc3339(nanos: i64, with_nanos: bool) -> String {
+    let secs = nanos.div_euclid(1_000_000_000);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+    let mut rendered = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+    if with_nanos {
+        rendered.push_str(&format!(".{:09}", nanos.rem_euclid(1_000_000_000)));
+    }
+    rendered.push('Z');
+    rendered
+}
+
+/// Returns the year, month and day of a number of days since UNIX_EPOCH in the proleptic
+/// Gregorian calendar, using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months starting from March, so the leap day is the last day of the year
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Returns the system time in nanoseconds since UNIX_EPOCH.
+pub(crate) fn now_nanos() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as i64)
+        .unwrap_or_default()
+}
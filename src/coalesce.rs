@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+use tracing_core::{callsite::Identifier, Level, Metadata};
+
+use crate::{JsonFormattingLayer, Stats};
+
+/// Coalesces the records of progress callsites, i.e. callsites with the progress field, writing
+/// at most one record per callsite per window: the first record immediately, and the latest
+/// record when the window ends.
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+    field: String,
+    window: Duration,
+    callsites: Mutex<HashMap<Identifier, Pending>>,
+    has_pending: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Pending {
+    /// When the current window started, if a record was written.
+    start: Option<Instant>,
+    /// The latest record of the window, not yet written.
+    record: Option<(Map<String, Value>, Level)>,
+    /// The number of records replaced by a later record since the last record written.
+    coalesced: u64,
+}
+
+impl Coalescer {
+    pub(crate) fn new(field: String, window: Duration) -> Self {
+        Self {
+            field,
+            window,
+            callsites: Mutex::new(HashMap::new()),
+            has_pending: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns whether events of the callsite are coalesced.
+    pub(crate) fn matches(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.fields().field(&self.field).is_some()
+    }
+
+    /// Offer a record of a progress callsite, returning the record if it is written now.
+    /// Otherwise it is retained until the window ends, replacing the previously retained record,
+    /// which is counted as dropped.
+    pub(crate) fn offer(
+        &self,
+        metadata: &Metadata<'_>,
+        mut record: Map<String, Value>,
+        level: Level,
+        stats: &Stats,
+    ) -> Option<Map<String, Value>> {
+        let now = Instant::now();
+        let mut callsites = self.callsites.lock().unwrap_or_else(|e| e.into_inner());
+        let pending = callsites
+            .entry(metadata.callsite())
+            .or_insert_with(|| Pending {
+                start: None,
+                record: None,
+                coalesced: 0,
+            });
+        if pending.record.take().is_some() {
+            pending.coalesced += 1;
+            stats.record_drop();
+        }
+        if pending
+            .start
+            .is_some_and(|start| now.duration_since(start) < self.window)
+        {
+            pending.record = Some((record, level));
+            self.has_pending.store(true, Ordering::Relaxed);
+            return None;
+        }
+        if pending.coalesced > 0 {
+            record.insert("coalesced".to_string(), json!(pending.coalesced));
+        }
+        pending.start = Some(now);
+        pending.coalesced = 0;
+        Some(record)
+    }
+
+    /// Returns the retained records whose window has ended, or all retained records if `all`,
+    /// with the number of records each replaced in a `coalesced` field.
+    pub(crate) fn take_due(&self, all: bool) -> Vec<(Map<String, Value>, Level)> {
+        if !self.has_pending.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let now = Instant::now();
+        let mut callsites = self.callsites.lock().unwrap_or_else(|e| e.into_inner());
+        let mut due = Vec::new();
+        let mut has_pending = false;
+        for pending in callsites.values_mut() {
+            if pending.record.is_none() {
+                continue;
+            }
+            if !all
+                && pending
+                    .start
+                    .is_some_and(|start| now.duration_since(start) < self.window)
+            {
+                has_pending = true;
+                continue;
+            }
+            let (mut record, level) = pending.record.take().expect("checked above");
+            if pending.coalesced > 0 {
+                record.insert("coalesced".to_string(), json!(pending.coalesced));
+            }
+            pending.start = Some(now);
+            pending.coalesced = 0;
+            due.push((record, level));
+        }
+        self.has_pending.store(has_pending, Ordering::Relaxed);
+        due
+    }
+}
+
+/// Writes the retained records of progress callsites when the last clone of the layer is
+/// dropped, so the final state is always written.
+#[derive(Debug)]
+pub(crate) struct CoalesceFlush {
+    pub(crate) layer: JsonFormattingLayer,
+}
+
+impl Drop for CoalesceFlush {
+    fn drop(&mut self) {
+        if let Some(coalescer) = &self.layer.coalescer {
+            for (record, level) in coalescer.take_due(true) {
+                self.layer.write_record(&record, level);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Compressing each record with zstd before it is written, optionally with a dictionary trained
+//! on a sample of records.
+//!
+//! Records are short and similar, so compressing them one at a time gains little without a
+//! dictionary, while a dictionary trained with `train_dictionary` captures their shared field
+//! names and values. `CompressedWriter` compresses each record as a separate zstd frame, and
+//! `CompressedReader` decompresses them again, with the same dictionary. Each compressed record
+//! is written as its length as a big-endian `u32`, followed by the frame.
+//!
+//! ```rust
+//! use tracing_subscriber::fmt::MakeWriter;
+//! use std::io::Write;
+//!
+//! // e.g. records read from an existing log file
+//! let samples: Vec<String> = (0..1000)
+//!     .map(|i| format!("{{\"level\":\"info\",\"message\":\"request {i} handled\",\"status\":200}}\n"))
+//!     .collect();
+//! let dictionary = tracing_ndjson::compress::train_dictionary(&samples, 4096)?;
+//!
+//! let path = std::env::temp_dir().join("tracing-ndjson-doc.ndjson.zst");
+//! let writer = tracing_ndjson::compress::CompressedWriter::new(std::fs::File::create(&path)?, 3)
+//!     .with_dictionary(&dictionary)?;
+//! writer.make_writer().write_all(samples[0].as_bytes())?;
+//!
+//! let reader = tracing_ndjson::compress::CompressedReader::new(std::fs::File::open(&path)?)
+//!     .with_dictionary(&dictionary)?;
+//! for record in reader {
+//!     print!("{}", String::from_utf8_lossy(&record?));
+//! }
+//! # std::fs::remove_file(&path)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use tracing_core::Level;
+use tracing_subscriber::fmt::MakeWriter;
+use zstd::bulk::{Compressor, Decompressor};
+
+use crate::sink::{RecordMeta, RecordSink};
+
+/// The largest decompressed record accepted by `CompressedReader`.
+const MAX_RECORD_LEN: u64 = 64 * 1024 * 1024;
+
+/// Train a zstd dictionary of up to `max_size` bytes from a sample of records, e.g. a few
+/// thousand records read from an existing log file. Export it by writing the returned bytes to
+/// a file, and configure writers and readers with it using `with_dictionary`.
+/// Training fails if the sample is too small for the dictionary size.
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+}
+
+/// Compresses each record before appending it to an underlying writer, e.g. a file or a socket.
+/// Clones write to the same writer.
+///
+/// Use it with `Builder::with_compressed_output`, or with any layer that accepts a `MakeWriter`,
+/// in which case everything written to a writer it makes is compressed as a single record when
+/// the writer is dropped.
+#[derive(Clone)]
+pub struct CompressedWriter {
+    compressor: Arc<Mutex<Compressor<'static>>>,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    level: i32,
+}
+
+impl std::fmt::Debug for CompressedWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressedWriter")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CompressedWriter {
+    /// Compress records at the given zstd level, e.g. 3, before writing them to `writer`.
+    pub fn new(writer: impl Write + Send + 'static, level: i32) -> Self {
+        Self {
+            compressor: Arc::new(Mutex::new(
+                Compressor::new(level).expect("creating a zstd context"),
+            )),
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            level,
+        }
+    }
+
+    /// Compress records with a dictionary trained using `train_dictionary`.
+    /// Records must be decompressed with the same dictionary.
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> io::Result<Self> {
+        self.compressor = Arc::new(Mutex::new(Compressor::with_dictionary(
+            self.level, dictionary,
+        )?));
+        Ok(self)
+    }
+}
+
+impl RecordSink for CompressedWriter {
+    /// Compress and write a complete encoded and framed record.
+    fn write_record(&self, line: &[u8], _meta: &RecordMeta) -> io::Result<()> {
+        let frame = self
+            .compressor
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .compress(line)?;
+        let len = u32::try_from(frame.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+        let mut block = Vec::with_capacity(4 + frame.len());
+        block.extend_from_slice(&len.to_be_bytes());
+        block.extend_from_slice(&frame);
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.write_all(&block)?;
+        writer.flush()
+    }
+}
+
+/// A writer made by `CompressedWriter`, buffering a record until it is dropped.
+#[derive(Debug)]
+pub struct CompressedRecordWriter<'a> {
+    writer: &'a CompressedWriter,
+    buf: Vec<u8>,
+}
+
+impl Write for CompressedRecordWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for CompressedRecordWriter<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let _ = self
+                .writer
+                .write_record(&self.buf, &RecordMeta::new(Level::INFO));
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for CompressedWriter {
+    type Writer = CompressedRecordWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        CompressedRecordWriter {
+            writer: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Decompresses records written by `CompressedWriter`, yielding each record as it was written,
+/// including its framing.
+pub struct CompressedReader<R> {
+    decompressor: Decompressor<'static>,
+    reader: R,
+}
+
+impl<R> std::fmt::Debug for CompressedReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressedReader").finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> CompressedReader<R> {
+    /// Decompress records read from `reader` that were compressed without a dictionary.
+    pub fn new(reader: R) -> Self {
+        Self {
+            decompressor: Decompressor::new().expect("creating a zstd context"),
+            reader,
+        }
+    }
+
+    /// Decompress records with the dictionary they were compressed with.
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> io::Result<Self> {
+        self.decompressor = Decompressor::with_dictionary(dictionary)?;
+        Ok(self)
+    }
+
+    /// Read and decompress the next record, or None at the end of the input.
+    /// Corrupt records, or records compressed with another dictionary, are an
+    /// `io::ErrorKind::InvalidData` error.
+    pub fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut frame = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.reader.read_exact(&mut frame)?;
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let capacity = match zstd::zstd_safe::get_frame_content_size(&frame) {
+            Ok(Some(size)) if size <= MAX_RECORD_LEN => size as usize,
+            Ok(Some(_)) => return Err(invalid("compressed record too large")),
+            Ok(None) | Err(_) => return Err(invalid("invalid compressed record")),
+        };
+        self.decompressor
+            .decompress(&frame, capacity)
+            .map(Some)
+            .map_err(|_| invalid("decompression failed"))
+    }
+}
+
+impl<R: Read> Iterator for CompressedReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+
+/// Replaces large field values that were already emitted within a window with a reference to
+/// the first emission.
+#[derive(Debug)]
+pub(crate) struct ValueDedup {
+    min_len: usize,
+    window: Duration,
+    seen: Mutex<HashMap<u64, Instant>>,
+}
+
+impl ValueDedup {
+    pub(crate) fn new(min_len: usize, window: Duration) -> Self {
+        Self {
+            min_len,
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn min_len(&self) -> usize {
+        self.min_len
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Deduplicate the large values of a set of fields. The first emission of a value is kept
+    /// and accompanied by a `<name>.value_ref` field, and repeats within the window are replaced
+    /// with `{"value_ref": "<hash>"}`. The message is never deduplicated.
+    pub(crate) fn apply(&self, fields: &mut Map<String, Value>, message_name: &str) {
+        let mut refs = Vec::new();
+        for (name, value) in fields.iter_mut() {
+            if name == message_name
+                || matches!(value, Value::Null | Value::Bool(_) | Value::Number(_))
+            {
+                continue;
+            }
+            let rendered = value.to_string();
+            if rendered.len() < self.min_len {
+                continue;
+            }
+            let hash = format!("{:016x}", hash(&rendered));
+            if self.seen(&rendered) {
+                *value = json!({ "value_ref": hash });
+            } else {
+                refs.push((format!("{}.value_ref", name), hash));
+            }
+        }
+        for (name, hash) in refs {
+            fields.insert(name, json!(hash));
+        }
+    }
+
+    /// Returns whether a value was seen within the window, recording it as seen otherwise.
+    fn seen(&self, rendered: &str) -> bool {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let key = hash(rendered);
+        if seen.get(&key).is_some_and(|until| *until > now) {
+            return true;
+        }
+        seen.retain(|_, until| *until > now);
+        seen.insert(key, now + self.window);
+        false
+    }
+}
+
+fn hash(rendered: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    rendered.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// The number of events after which nested spans without a single span created are reported.
+const UNUSED_SPANS_EVENTS: u64 = 1000;
+
+/// A common misconfiguration that leaves fields of records empty or missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Misconfiguration {
+    /// The layer was added to a subscriber other than a `Registry`.
+    NoRegistry,
+    /// A span had no field storage, because it was created before the layer was added.
+    MissingSpanStorage,
+    /// Spans are nested, but no span was created after many events.
+    UnusedSpans,
+}
+
+impl Misconfiguration {
+    const ALL: [Misconfiguration; 3] = [
+        Misconfiguration::NoRegistry,
+        Misconfiguration::MissingSpanStorage,
+        Misconfiguration::UnusedSpans,
+    ];
+
+    /// Returns the value of the `diagnostic` field of the warning record.
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            Misconfiguration::NoRegistry => "no_registry",
+            Misconfiguration::MissingSpanStorage => "missing_span_storage",
+            Misconfiguration::UnusedSpans => "unused_spans",
+        }
+    }
+
+    /// Returns the message of the warning record.
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            Misconfiguration::NoRegistry => {
+                "layer added to a subscriber other than tracing_subscriber::Registry, span fields may be missing"
+            }
+            Misconfiguration::MissingSpanStorage => {
+                "span fields not found, the span was created before this layer was added"
+            }
+            Misconfiguration::UnusedSpans => {
+                "spans are nested under the spans field, but no span was created"
+            }
+        }
+    }
+
+    fn index(self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|kind| *kind == self)
+            .expect("listed in ALL")
+    }
+}
+
+/// Detects common misconfigurations at runtime, reporting each at most once.
+#[derive(Debug, Default)]
+pub(crate) struct Diagnostics {
+    events: AtomicU64,
+    spans_created: AtomicBool,
+    reported: [AtomicBool; 3],
+}
+
+impl Diagnostics {
+    /// Returns whether the misconfiguration should be reported, i.e. the first time it is found.
+    pub(crate) fn report(&self, kind: Misconfiguration) -> bool {
+        !self.reported[kind.index()].swap(true, Ordering::Relaxed)
+    }
+
+    pub(crate) fn span_created(&self) {
+        self.spans_created.store(true, Ordering::Relaxed);
+    }
+
+    /// Count an event, returning whether spans are unused if they are nested.
+    pub(crate) fn event_recorded(&self) -> bool {
+        let events = self.events.fetch_add(1, Ordering::Relaxed) + 1;
+        events == UNUSED_SPANS_EVENTS && !self.spans_created.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,111 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing_core::Level;
+
+use crate::output::Output;
+
+/// Raises the minimum level of written events while the file system of the output has less
+/// than a threshold of free space, checking it at most once per interval.
+#[derive(Debug)]
+pub(crate) struct DiskSpaceGuard {
+    min_free: u64,
+    level: Level,
+    interval: Duration,
+    last_check: Mutex<Option<Instant>>,
+    low: AtomicBool,
+}
+
+impl DiskSpaceGuard {
+    pub(crate) fn new(min_free: u64, level: Level, interval: Duration) -> Self {
+        Self {
+            min_free,
+            level,
+            interval,
+            last_check: Mutex::new(None),
+            low: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn min_free(&self) -> u64 {
+        self.min_free
+    }
+
+    pub(crate) fn level(&self) -> Level {
+        self.level
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns whether events less severe than the level are dropped.
+    pub(crate) fn is_low(&self) -> bool {
+        self.low.load(Ordering::Relaxed)
+    }
+
+    /// Returns the free space of the file system the output writes to, if the interval has
+    /// passed since the last check. Outputs that don't write to files are never checked.
+    pub(crate) fn check(&self, output: &Output) -> Option<u64> {
+        let now = Instant::now();
+        {
+            // Another thread is already checking
+            let mut last_check = self.last_check.try_lock().ok()?;
+            if last_check.is_some_and(|last| now.duration_since(last) < self.interval) {
+                return None;
+            }
+            *last_check = Some(now);
+        }
+        available_space(output.directory()?).ok()
+    }
+
+    /// Update the state from the free space, returning whether space is low if it changed.
+    pub(crate) fn update(&self, available: u64) -> Option<bool> {
+        let low = available < self.min_free;
+        (self.low.swap(low, Ordering::Relaxed) != low).then_some(low)
+    }
+}
+
+/// Returns the space available to unprivileged users on the file system containing the path.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::raw::{c_char, c_int, c_ulong};
+    use std::os::unix::ffi::OsStrExt;
+
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        f_blocks: u64,
+        f_bfree: u64,
+        f_bavail: u64,
+        // The remaining fields differ between C libraries and are not read
+        _rest: [u64; 16],
+    }
+
+    extern "C" {
+        fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut buf = std::mem::MaybeUninit::<StatVfs>::zeroed();
+    // SAFETY: the path is NUL-terminated and the buffer is larger than `struct statvfs`.
+    if unsafe { statvfs(path.as_ptr(), buf.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded, so it initialized the buffer.
+    let buf = unsafe { buf.assume_init() };
+    Ok(buf.f_bavail.saturating_mul(buf.f_frsize))
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space checks are not supported on this platform",
+    ))
+}
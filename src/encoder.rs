@@ -0,0 +1,312 @@
+use std::io::Write;
+
+use serde_json::{Map, Value};
+
+/// The configured names of the built-in fields of a record.
+/// Encoders can use these to give the built-in fields special treatment, e.g. to render them first.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldNames<'a> {
+    pub level: &'a str,
+    pub message: &'a str,
+    pub target: &'a str,
+    pub timestamp: &'a str,
+}
+
+/// Encodes a single record into bytes.
+///
+/// A record is the fully assembled set of fields of an event, including the built-in fields,
+/// span fields and event fields. Encoders must not write a record terminator, as that is the
+/// responsibility of the layer.
+///
+/// Implement this trait to provide a custom output format.
+pub trait Encoder: Send + Sync {
+    fn encode(
+        &self,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()>;
+}
+
+/// Encodes records as a single line of JSON. This is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NdjsonEncoder;
+
+impl Encoder for NdjsonEncoder {
+    fn encode(
+        &self,
+        _names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        serde_json::to_writer(buf, record)?;
+        Ok(())
+    }
+}
+
+/// Encodes records in [logfmt](https://brandur.org/logfmt) format, e.g. `level=info msg="Hello, world!"`.
+/// Built-in fields are written first, followed by all other fields. Nested values are written as JSON.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogfmtEncoder;
+
+impl Encoder for LogfmtEncoder {
+    fn encode(
+        &self,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        let mut first = true;
+        for (key, value) in ordered(names, record) {
+            if !first {
+                buf.push(b' ');
+            }
+            first = false;
+            write_logfmt_key(buf, key);
+            buf.push(b'=');
+            write_logfmt_value(buf, &render(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes records in a human-readable format, e.g.
+/// `2023-10-08T03:30:52Z  INFO my_app: Hello, world! life=42`.
+/// The level is colored using ANSI escape codes unless disabled.
+#[derive(Debug, Clone, Copy)]
+pub struct PrettyEncoder {
+    ansi: bool,
+}
+
+impl PrettyEncoder {
+    pub fn new() -> Self {
+        Self { ansi: true }
+    }
+
+    /// Set whether to color the level using ANSI escape codes.
+    /// The default is true.
+    pub fn with_ansi(mut self, ansi: bool) -> Self {
+        self.ansi = ansi;
+        self
+    }
+}
+
+impl Default for PrettyEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder for PrettyEncoder {
+    fn encode(
+        &self,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        if let Some(timestamp) = record.get(names.timestamp) {
+            write!(buf, "{} ", render(timestamp))?;
+        }
+        if let Some(level) = record.get(names.level) {
+            let level = render(level);
+            match (self.ansi, level_color(&level)) {
+                (true, Some(color)) => write!(buf, "\x1b[{}m{:>5}\x1b[0m ", color, level)?,
+                _ => write!(buf, "{:>5} ", level)?,
+            }
+        }
+        if let Some(target) = record.get(names.target) {
+            write!(buf, "{}: ", render(target))?;
+        }
+        if let Some(message) = record.get(names.message) {
+            write!(buf, "{}", render(message))?;
+        }
+        for (key, value) in record {
+            if is_builtin(names, key) {
+                continue;
+            }
+            buf.push(b' ');
+            write_logfmt_key(buf, key);
+            buf.push(b'=');
+            write_logfmt_value(buf, &render(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes records as [MessagePack](https://msgpack.org) maps, a compact binary format for
+/// shipping records over the network. The encoded records may contain any byte, including `\n`,
+/// so use a length-prefixed framing such as `Framing::OctetCounting` rather than a terminator.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MsgpackEncoder;
+
+impl Encoder for MsgpackEncoder {
+    fn encode(
+        &self,
+        _names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        write_msgpack_map(buf, record);
+        Ok(())
+    }
+}
+
+fn write_msgpack_map(buf: &mut Vec<u8>, map: &Map<String, Value>) {
+    write_msgpack_len(buf, map.len(), 0x80, 0xde);
+    for (key, value) in map {
+        write_msgpack_str(buf, key);
+        write_msgpack_value(buf, value);
+    }
+}
+
+fn write_msgpack_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buf.push(0xc0),
+        Value::Bool(false) => buf.push(0xc2),
+        Value::Bool(true) => buf.push(0xc3),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                match n {
+                    0..=0x7f => buf.push(n as u8),
+                    0x80..=0xff => buf.extend_from_slice(&[0xcc, n as u8]),
+                    0x100..=0xffff => {
+                        buf.push(0xcd);
+                        buf.extend_from_slice(&(n as u16).to_be_bytes());
+                    }
+                    0x1_0000..=0xffff_ffff => {
+                        buf.push(0xce);
+                        buf.extend_from_slice(&(n as u32).to_be_bytes());
+                    }
+                    _ => {
+                        buf.push(0xcf);
+                        buf.extend_from_slice(&n.to_be_bytes());
+                    }
+                }
+            } else if let Some(n) = n.as_i64() {
+                // Only negative integers are left
+                match n {
+                    -32..=-1 => buf.push(n as u8),
+                    -0x80..=-33 => buf.extend_from_slice(&[0xd0, n as u8]),
+                    -0x8000..=-0x81 => {
+                        buf.push(0xd1);
+                        buf.extend_from_slice(&(n as i16).to_be_bytes());
+                    }
+                    -0x8000_0000..=-0x8001 => {
+                        buf.push(0xd2);
+                        buf.extend_from_slice(&(n as i32).to_be_bytes());
+                    }
+                    _ => {
+                        buf.push(0xd3);
+                        buf.extend_from_slice(&n.to_be_bytes());
+                    }
+                }
+            } else {
+                buf.push(0xcb);
+                buf.extend_from_slice(&n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(s) => write_msgpack_str(buf, s),
+        Value::Array(values) => {
+            write_msgpack_len(buf, values.len(), 0x90, 0xdc);
+            for value in values {
+                write_msgpack_value(buf, value);
+            }
+        }
+        Value::Object(map) => write_msgpack_map(buf, map),
+    }
+}
+
+fn write_msgpack_str(buf: &mut Vec<u8>, s: &str) {
+    match s.len() {
+        len @ 0..=31 => buf.push(0xa0 | len as u8),
+        len @ 32..=0xff => buf.extend_from_slice(&[0xd9, len as u8]),
+        len @ 0x100..=0xffff => {
+            buf.push(0xda);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            buf.push(0xdb);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Write the length of an array or map, as a fix type for up to 15 elements, or with the 16 or
+/// 32-bit type following `long`.
+fn write_msgpack_len(buf: &mut Vec<u8>, len: usize, fix: u8, long: u8) {
+    match len {
+        0..=15 => buf.push(fix | len as u8),
+        16..=0xffff => {
+            buf.push(long);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            buf.push(long + 1);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn is_builtin(names: &FieldNames<'_>, key: &str) -> bool {
+    key == names.timestamp || key == names.level || key == names.target || key == names.message
+}
+
+/// Iterate over the fields of a record with the built-in fields first.
+fn ordered<'a>(
+    names: &'a FieldNames<'_>,
+    record: &'a Map<String, Value>,
+) -> impl Iterator<Item = (&'a str, &'a Value)> {
+    let builtins = [names.timestamp, names.level, names.target, names.message]
+        .into_iter()
+        .filter_map(|key| record.get_key_value(key))
+        .map(|(k, v)| (k.as_str(), v));
+    let rest = record
+        .iter()
+        .filter(|(k, _)| !is_builtin(names, k))
+        .map(|(k, v)| (k.as_str(), v));
+    builtins.chain(rest)
+}
+
+/// Render a value as plain text. Strings are written without quotes, everything else as JSON.
+fn render(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn write_logfmt_key(buf: &mut Vec<u8>, key: &str) {
+    for c in key.chars() {
+        if c == ' ' || c == '=' || c == '"' || c.is_control() {
+            buf.push(b'_');
+        } else {
+            let mut tmp = [0; 4];
+            buf.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes());
+        }
+    }
+}
+
+fn write_logfmt_value(buf: &mut Vec<u8>, value: &str) -> std::io::Result<()> {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+    if needs_quotes {
+        serde_json::to_writer(buf, value)?;
+    } else {
+        buf.extend_from_slice(value.as_bytes());
+    }
+    Ok(())
+}
+
+fn level_color(level: &str) -> Option<&'static str> {
+    match level.to_ascii_lowercase().as_str() {
+        "trace" => Some("35"),
+        "debug" => Some("34"),
+        "info" => Some("32"),
+        "warn" => Some("33"),
+        "error" => Some("31"),
+        _ => None,
+    }
+}
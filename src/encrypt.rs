@@ -0,0 +1,266 @@
+//! Encrypting records before they are written, for environments where logs at rest must be
+//! encrypted even on the host.
+//!
+//! `EncryptedWriter` encrypts each record with AES-256-GCM, and `EncryptedReader` decrypts them
+//! again. Each encrypted record is written as its length as a big-endian `u32`, followed by the
+//! 12 byte nonce and the ciphertext with its 16 byte tag.
+//!
+//! Nonces are never random. Each writer picks a random 8 byte stream ID and numbers its records
+//! from 0. The nonce is the stream ID followed by the record number as a big-endian `u32`. The
+//! record number is also authenticated as associated data. When the numbers run out, the writer
+//! starts a new stream. The reader checks that the records of each stream are numbered without
+//! gaps, so records that were removed, reordered or replayed are an error. Records removed from
+//! the end of a stream can't be detected.
+//!
+//! ```rust
+//! use std::io::Write;
+//! use tracing_subscriber::fmt::MakeWriter;
+//!
+//! let key = [7u8; 32];
+//! let path = std::env::temp_dir().join("tracing-ndjson-doc.ndjson.enc");
+//! let writer = tracing_ndjson::encrypt::EncryptedWriter::new(std::fs::File::create(&path)?, &key);
+//! writer.make_writer().write_all(b"{\"message\":\"hello\"}\n")?;
+//!
+//! let reader = tracing_ndjson::encrypt::EncryptedReader::new(std::fs::File::open(&path)?, &key);
+//! for record in reader {
+//!     print!("{}", String::from_utf8_lossy(&record?));
+//! }
+//! # std::fs::remove_file(&path)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use tracing_core::Level;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::sink::{RecordMeta, RecordSink};
+
+const NONCE_LEN: usize = 12;
+const STREAM_ID_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// The largest record that can be encrypted. Larger lengths are rejected by the reader without
+/// allocating them.
+pub const MAX_RECORD_LEN: usize = 64 << 20;
+
+/// The stream ID and number of the next record of a writer.
+struct Sequence {
+    stream_id: [u8; STREAM_ID_LEN],
+    next: u32,
+}
+
+impl Sequence {
+    fn new() -> Self {
+        let mut stream_id = [0; STREAM_ID_LEN];
+        OsRng.fill_bytes(&mut stream_id);
+        Self { stream_id, next: 0 }
+    }
+
+    /// Returns the nonce and number of the next record, starting a new stream when the numbers
+    /// run out.
+    fn advance(&mut self) -> ([u8; NONCE_LEN], u32) {
+        if self.next == u32::MAX {
+            *self = Self::new();
+        }
+        let number = self.next;
+        self.next += 1;
+        (nonce(&self.stream_id, number), number)
+    }
+}
+
+fn nonce(stream_id: &[u8; STREAM_ID_LEN], number: u32) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..STREAM_ID_LEN].copy_from_slice(stream_id);
+    nonce[STREAM_ID_LEN..].copy_from_slice(&number.to_be_bytes());
+    nonce
+}
+
+/// Encrypts each record before appending it to an underlying writer, e.g. a file or a socket.
+/// Clones write to the same writer.
+///
+/// Use it with `Builder::with_encrypted_output`, or with any layer that accepts a `MakeWriter`,
+/// in which case everything written to a writer it makes is encrypted as a single record when
+/// the writer is dropped.
+#[derive(Clone)]
+pub struct EncryptedWriter {
+    cipher: Arc<Aes256Gcm>,
+    /// Records are numbered under the same lock they are written with, so they are written in
+    /// order.
+    writer: Arc<Mutex<(Box<dyn Write + Send>, Sequence)>>,
+}
+
+impl std::fmt::Debug for EncryptedWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedWriter").finish_non_exhaustive()
+    }
+}
+
+impl EncryptedWriter {
+    /// Encrypt records with a 256 bit key before writing them to `writer`.
+    pub fn new(writer: impl Write + Send + 'static, key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Arc::new(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))),
+            writer: Arc::new(Mutex::new((Box::new(writer), Sequence::new()))),
+        }
+    }
+}
+
+impl RecordSink for EncryptedWriter {
+    /// Encrypt and write a complete encoded and framed record.
+    fn write_record(&self, line: &[u8], _meta: &RecordMeta) -> io::Result<()> {
+        if line.len() > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "record too large",
+            ));
+        }
+        let mut guard = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let (writer, sequence) = &mut *guard;
+        let (nonce, number) = sequence.advance();
+        let aad = number.to_be_bytes();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: line,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        let len = (NONCE_LEN + ciphertext.len()) as u32;
+        let mut block = Vec::with_capacity(4 + len as usize);
+        block.extend_from_slice(&len.to_be_bytes());
+        block.extend_from_slice(&nonce);
+        block.extend_from_slice(&ciphertext);
+        writer.write_all(&block)?;
+        writer.flush()
+    }
+}
+
+/// A writer made by `EncryptedWriter`, buffering a record until it is dropped.
+#[derive(Debug)]
+pub struct EncryptedRecordWriter<'a> {
+    writer: &'a EncryptedWriter,
+    buf: Vec<u8>,
+}
+
+impl Write for EncryptedRecordWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EncryptedRecordWriter<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let _ = self
+                .writer
+                .write_record(&self.buf, &RecordMeta::new(Level::INFO));
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for EncryptedWriter {
+    type Writer = EncryptedRecordWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        EncryptedRecordWriter {
+            writer: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Decrypts records written by `EncryptedWriter`, yielding each record as it was written,
+/// including its framing.
+pub struct EncryptedReader<R> {
+    cipher: Aes256Gcm,
+    reader: R,
+    /// The stream ID and number expected of the next record of the current stream.
+    expected: Option<([u8; STREAM_ID_LEN], u32)>,
+}
+
+impl<R> std::fmt::Debug for EncryptedReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedReader").finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> EncryptedReader<R> {
+    /// Decrypt records read from `reader` with the key they were encrypted with.
+    pub fn new(reader: R, key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+            reader,
+            expected: None,
+        }
+    }
+
+    /// Read and decrypt the next record, or None at the end of the input.
+    /// Records that were tampered with, removed, reordered or encrypted with another key are an
+    /// `io::ErrorKind::InvalidData` error.
+    pub fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len < NONCE_LEN + TAG_LEN {
+            return Err(invalid("truncated encrypted record"));
+        }
+        if len > NONCE_LEN + MAX_RECORD_LEN + TAG_LEN {
+            return Err(invalid("encrypted record too large"));
+        }
+        let mut block = vec![0u8; len];
+        self.reader.read_exact(&mut block)?;
+        let (nonce, ciphertext) = block.split_at(NONCE_LEN);
+        let (stream_id, number) = nonce.split_at(STREAM_ID_LEN);
+        let stream_id: [u8; STREAM_ID_LEN] = stream_id.try_into().unwrap();
+        let number = u32::from_be_bytes(number.try_into().unwrap());
+        // A new stream starts at 0, and the records of a stream are numbered without gaps
+        let expected = match self.expected {
+            Some((current, next)) if current == stream_id => next,
+            _ => 0,
+        };
+        if number != expected {
+            return Err(invalid("encrypted record out of sequence"));
+        }
+        let record = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &number.to_be_bytes(),
+                },
+            )
+            .map_err(|_| invalid("decryption failed"))?;
+        self.expected = Some((stream_id, number + 1));
+        Ok(Some(record))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: Read> Iterator for EncryptedReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
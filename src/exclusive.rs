@@ -0,0 +1,84 @@
+//! Protection against other layers writing to stdout alongside this one, which interleaves
+//! mixed-format output and corrupts NDJSON consumers.
+//!
+//! ```rust
+//! use tracing_subscriber::prelude::*;
+//!
+//! tracing_subscriber::registry()
+//!     .with(tracing_ndjson::builder().with_exclusive_stdout(true).layer())
+//!     // Writes to stderr instead, as this layer owns stdout
+//!     .with(tracing_subscriber::fmt::layer().with_writer(tracing_ndjson::exclusive::SharedStdout))
+//!     .init();
+//!
+//! // Fail fast if another layer still writes to stdout
+//! tracing::dispatcher::get_default(|dispatch| tracing_ndjson::exclusive::check_stdout(dispatch))
+//!     .unwrap();
+//! ```
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STDOUT_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether a layer has claimed exclusive ownership of stdout.
+pub fn stdout_claimed() -> bool {
+    STDOUT_CLAIMED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn claim_stdout() {
+    STDOUT_CLAIMED.store(true, Ordering::Relaxed);
+}
+
+/// A writer for other layers that writes to stdout, unless a layer has claimed exclusive
+/// ownership of stdout, in which case it writes to stderr.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SharedStdout;
+
+/// The writer returned by `SharedStdout`.
+#[derive(Debug)]
+pub enum SharedStdoutWriter {
+    Stdout(std::io::Stdout),
+    Stderr(std::io::Stderr),
+}
+
+impl Write for SharedStdoutWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            SharedStdoutWriter::Stdout(stdout) => stdout.write(buf),
+            SharedStdoutWriter::Stderr(stderr) => stderr.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            SharedStdoutWriter::Stdout(stdout) => stdout.flush(),
+            SharedStdoutWriter::Stderr(stderr) => stderr.flush(),
+        }
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for SharedStdout {
+    type Writer = SharedStdoutWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        if stdout_claimed() {
+            SharedStdoutWriter::Stderr(std::io::stderr())
+        } else {
+            SharedStdoutWriter::Stdout(std::io::stdout())
+        }
+    }
+}
+
+/// Another layer writes to stdout, which a layer has claimed exclusively.
+#[derive(Debug, thiserror::Error)]
+#[error("a tracing_subscriber::fmt layer also writes to stdout, which is claimed exclusively")]
+pub struct StdoutConflict;
+
+/// Check that no `tracing_subscriber::fmt` layer writes to stdout with the default writer if a
+/// layer has claimed exclusive ownership of stdout, e.g. to fail fast at startup.
+pub fn check_stdout(dispatch: &tracing_core::Dispatch) -> Result<(), StdoutConflict> {
+    if stdout_claimed() && dispatch.downcast_ref::<fn() -> std::io::Stdout>().is_some() {
+        return Err(StdoutConflict);
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+//! Well-known field names, so services use identical keys for common concepts.
+//!
+//! The constants can be used as field names with the constant field name syntax of `tracing`,
+//! and are mapped to other schemas by `WellKnownFields`, registered using
+//! `Builder::with_well_known_fields` or the `Preset::Ecs` and `Preset::Gcp` presets.
+//!
+//! ```rust
+//! use tracing_ndjson::fields::http;
+//!
+//! tracing::info!(
+//!     { http::METHOD } = "GET",
+//!     { http::ROUTE } = "/users/:id",
+//!     { http::STATUS } = 200,
+//!     "finished processing request"
+//! );
+//! ```
+
+use serde_json::Value;
+
+use crate::{adapters::FieldAdapter, FieldType};
+
+/// Fields describing an HTTP request and its response.
+pub mod http {
+    /// The request method, e.g. `GET`.
+    pub const METHOD: &str = "http.method";
+    /// The matched route template, e.g. `/users/:id`.
+    pub const ROUTE: &str = "http.route";
+    /// The full request URL.
+    pub const URL: &str = "http.url";
+    /// The response status code.
+    pub const STATUS: &str = "http.status";
+    /// The `User-Agent` request header.
+    pub const USER_AGENT: &str = "http.user_agent";
+    /// The IP address of the client.
+    pub const CLIENT_IP: &str = "http.client_ip";
+}
+
+/// Fields describing a database query, matching the fields written by `adapters::DbAdapter`.
+pub mod db {
+    /// The database system, e.g. `postgresql`.
+    pub const SYSTEM: &str = "db.system";
+    /// The query statement.
+    pub const STATEMENT: &str = "db.statement";
+    /// The operation, e.g. `SELECT`.
+    pub const OPERATION: &str = "db.operation";
+    /// The number of rows affected by the query.
+    pub const ROWS_AFFECTED: &str = "db.rows_affected";
+    /// The duration of the query in milliseconds.
+    pub const DURATION_MS: &str = "db.duration_ms";
+}
+
+/// Fields identifying the request being handled.
+pub mod request {
+    /// The request ID, e.g. from the `x-request-id` header.
+    pub const ID: &str = "request.id";
+}
+
+/// Fields identifying the tenant a request is made on behalf of in a multi-tenant service.
+pub mod tenant {
+    /// The tenant ID, e.g. from the `x-tenant` header.
+    pub const ID: &str = "tenant.id";
+}
+
+/// Fields identifying the distributed trace a request is part of.
+pub mod trace {
+    /// The trace ID, e.g. from the `traceparent` header.
+    pub const ID: &str = "trace.id";
+    /// The ID of the parent span in the calling service.
+    pub const PARENT_ID: &str = "trace.parent_id";
+    /// The ID of the span handling the request in this service.
+    pub const SPAN_ID: &str = "span.id";
+    /// Whether the trace is sampled, from the sampled flag of the `traceparent` header.
+    pub const SAMPLED: &str = "trace.sampled";
+}
+
+/// Fields identifying the service writing the records, written by `Builder::with_service`.
+pub mod service {
+    /// The name of the service, e.g. `checkout`.
+    pub const NAME: &str = "service.name";
+    /// The version of the service, e.g. `1.4.2`.
+    pub const VERSION: &str = "service.version";
+    /// The environment the service runs in, e.g. `production`.
+    pub const ENVIRONMENT: &str = "service.environment";
+}
+
+/// Fields identifying the user a request is made on behalf of.
+pub mod user {
+    /// The user ID.
+    pub const ID: &str = "user.id";
+}
+
+/// Each well-known field with its type and its name in the ECS and GCP schemas.
+const DEFINITIONS: [(&str, FieldType, &str, &str); 18] = [
+    (
+        http::METHOD,
+        FieldType::String,
+        "http.request.method",
+        "httpRequest.requestMethod",
+    ),
+    (http::ROUTE, FieldType::String, "url.path", http::ROUTE),
+    (
+        http::URL,
+        FieldType::String,
+        "url.original",
+        "httpRequest.requestUrl",
+    ),
+    (
+        http::STATUS,
+        FieldType::U16,
+        "http.response.status_code",
+        "httpRequest.status",
+    ),
+    (
+        http::USER_AGENT,
+        FieldType::String,
+        "user_agent.original",
+        "httpRequest.userAgent",
+    ),
+    (
+        http::CLIENT_IP,
+        FieldType::String,
+        "client.ip",
+        "httpRequest.remoteIp",
+    ),
+    (db::SYSTEM, FieldType::String, db::SYSTEM, db::SYSTEM),
+    (
+        db::STATEMENT,
+        FieldType::String,
+        db::STATEMENT,
+        db::STATEMENT,
+    ),
+    (
+        db::OPERATION,
+        FieldType::String,
+        db::OPERATION,
+        db::OPERATION,
+    ),
+    (
+        db::ROWS_AFFECTED,
+        FieldType::U64,
+        db::ROWS_AFFECTED,
+        db::ROWS_AFFECTED,
+    ),
+    (
+        db::DURATION_MS,
+        FieldType::F64,
+        db::DURATION_MS,
+        db::DURATION_MS,
+    ),
+    (
+        request::ID,
+        FieldType::String,
+        "http.request.id",
+        request::ID,
+    ),
+    (tenant::ID, FieldType::String, tenant::ID, tenant::ID),
+    (trace::ID, FieldType::String, trace::ID, trace::ID),
+    (
+        trace::PARENT_ID,
+        FieldType::String,
+        trace::PARENT_ID,
+        trace::PARENT_ID,
+    ),
+    (
+        trace::SPAN_ID,
+        FieldType::String,
+        trace::SPAN_ID,
+        trace::SPAN_ID,
+    ),
+    (
+        trace::SAMPLED,
+        FieldType::Bool,
+        trace::SAMPLED,
+        "logging.googleapis.com/trace_sampled",
+    ),
+    (user::ID, FieldType::String, user::ID, user::ID),
+];
+
+/// The schema well-known fields are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Schema {
+    Native,
+    Ecs,
+    Gcp,
+}
+
+/// Renames the well-known fields in this module to the names of a schema, and declares their
+/// types when registered using `Builder::with_well_known_fields`.
+///
+/// ```rust
+/// let builder = tracing_ndjson::builder()
+///     .with_well_known_fields(tracing_ndjson::fields::WellKnownFields::ecs());
+/// ```
+#[derive(Debug, Clone)]
+pub struct WellKnownFields {
+    schema: Schema,
+}
+
+impl WellKnownFields {
+    /// Keep the names in this module, only declaring their types.
+    pub fn native() -> Self {
+        Self {
+            schema: Schema::Native,
+        }
+    }
+
+    /// Map fields to [Elastic Common Schema](https://www.elastic.co/guide/en/ecs/current/ecs-field-reference.html)
+    /// names, e.g. `http.status` to `http.response.status_code`.
+    pub fn ecs() -> Self {
+        Self {
+            schema: Schema::Ecs,
+        }
+    }
+
+    /// Map HTTP fields to the names of the Google Cloud Logging
+    /// [`HttpRequest`](https://cloud.google.com/logging/docs/reference/v2/rest/v2/LogEntry#HttpRequest)
+    /// fields, e.g. `http.status` to `httpRequest.status`. Names are written as dotted keys.
+    pub fn gcp() -> Self {
+        Self {
+            schema: Schema::Gcp,
+        }
+    }
+
+    fn name(
+        &self,
+        definition: &(&'static str, FieldType, &'static str, &'static str),
+    ) -> &'static str {
+        match self.schema {
+            Schema::Native => definition.0,
+            Schema::Ecs => definition.2,
+            Schema::Gcp => definition.3,
+        }
+    }
+
+    /// Returns the name of each well-known field in this schema with its type.
+    pub fn field_types(&self) -> impl Iterator<Item = (&'static str, FieldType)> + '_ {
+        DEFINITIONS
+            .iter()
+            .map(|definition| (self.name(definition), definition.1))
+    }
+}
+
+impl FieldAdapter for WellKnownFields {
+    fn adapt(&self, _target: &str, name: String, value: Value) -> Option<(String, Value)> {
+        match DEFINITIONS.iter().find(|definition| definition.0 == name) {
+            Some(definition) => Some((self.name(definition).to_string(), value)),
+            None => Some((name, value)),
+        }
+    }
+}
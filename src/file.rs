@@ -0,0 +1,667 @@
+//! Writing records to files.
+//!
+//! ```rust,no_run
+//! use tracing_subscriber::prelude::*;
+//!
+//! let writer = tracing_ndjson::file::FileWriter::open("logs/audit.ndjson")?
+//!     .with_sync_policy(tracing_ndjson::file::SyncPolicy::EveryRecord);
+//! tracing_subscriber::registry()
+//!     .with(tracing_ndjson::builder().with_file(writer).layer())
+//!     .init();
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "chrono")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::sink::{RecordMeta, RecordSink};
+
+/// When written records are synced to disk with `fsync`.
+/// The default is to leave syncing to the operating system.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Never sync explicitly. Records are still written to the operating system immediately.
+    #[default]
+    Never,
+    /// Sync after every record.
+    EveryRecord,
+    /// Sync after a record when at least the given interval has passed since the last sync.
+    Interval(Duration),
+    /// Sync after records at the given level or a more severe level, e.g. Level::ERROR.
+    OnLevel(Level),
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    last_sync: Instant,
+    /// The length of the file, i.e. the offset of the next record.
+    offset: u64,
+    records: u64,
+    index: Option<File>,
+}
+
+/// Appends records to a file, syncing them to disk according to a `SyncPolicy`.
+/// Clones write to the same file.
+///
+/// Use it with `Builder::with_file`, or with any layer that accepts a `MakeWriter`.
+#[derive(Debug, Clone)]
+pub struct FileWriter {
+    path: PathBuf,
+    inner: Arc<Mutex<Inner>>,
+    sync_policy: SyncPolicy,
+    index_every: Option<u64>,
+}
+
+impl FileWriter {
+    /// Open a file for appending, creating it and its parent directories if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let offset = file.metadata()?.len();
+        Ok(Self {
+            path,
+            inner: Arc::new(Mutex::new(Inner {
+                file,
+                last_sync: Instant::now(),
+                offset,
+                records: 0,
+                index: None,
+            })),
+            sync_policy: SyncPolicy::default(),
+            index_every: None,
+        })
+    }
+
+    /// Returns the file descriptor of the file, for writing records from a signal handler.
+    #[cfg(all(feature = "signals", unix))]
+    pub(crate) fn raw_fd(&self) -> std::os::fd::RawFd {
+        use std::os::fd::AsRawFd;
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .file
+            .as_raw_fd()
+    }
+
+    /// Maintain an index file next to the file, named by appending `.idx` to its name, with an
+    /// entry mapping the current time to the byte offset of every `every`th record written.
+    /// Use `Index` to seek into the file by time.
+    /// The default is no index.
+    pub fn with_index(mut self, every: usize) -> io::Result<Self> {
+        let index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(index_path(&self.path))?;
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).index = Some(index);
+        self.index_every = Some(every.max(1) as u64);
+        Ok(self)
+    }
+
+    /// Set when records are synced to disk.
+    /// The default is SyncPolicy::Never.
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns when records are synced to disk.
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    /// Returns the length of the file, including records written before it was opened.
+    #[cfg(feature = "chrono")]
+    pub(crate) fn len(&self) -> u64 {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).offset
+    }
+
+    /// Sync all written records to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.file.sync_data()?;
+        inner.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl RecordSink for FileWriter {
+    /// Write a complete record emitted at the given level, syncing it if the policy requires.
+    fn write_record(&self, record: &[u8], meta: &RecordMeta) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(every) = self.index_every {
+            if inner.records.is_multiple_of(every) {
+                let entry = IndexEntry {
+                    timestamp_ms: crate::clock::now_nanos().div_euclid(1_000_000),
+                    offset: inner.offset,
+                };
+                if let Some(index) = &mut inner.index {
+                    writeln!(index, "{} {}", entry.timestamp_ms, entry.offset)?;
+                }
+            }
+        }
+        inner.file.write_all(record)?;
+        inner.offset += record.len() as u64;
+        inner.records += 1;
+        let sync = match self.sync_policy {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryRecord => true,
+            SyncPolicy::Interval(interval) => inner.last_sync.elapsed() >= interval,
+            SyncPolicy::OnLevel(min) => meta.level <= min,
+        };
+        if sync {
+            inner.file.sync_data()?;
+            inner.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// What happens when writing a record would take a rolling file over its size limit, set using
+/// `RollingFileWriter::with_max_bytes`.
+#[cfg(feature = "chrono")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeLimitPolicy {
+    /// Stop writing to the file until the next rotation, counting the records not written in
+    /// `RollingFileWriter::dropped_records`.
+    Stop,
+    /// Rotate early, continuing in a file with the same name followed by `.1`, `.2` and so on.
+    RotateEarly,
+}
+
+/// How often `RollingFileWriter::rotating` switches to a new file.
+#[cfg(feature = "chrono")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// A file per minute, e.g. `app.2023-10-08-03-30.ndjson`.
+    Minutely,
+    /// A file per hour, e.g. `app.2023-10-08-03.ndjson`.
+    Hourly,
+    /// A file per day, e.g. `app.2023-10-08.ndjson`.
+    Daily,
+    /// A single file, e.g. `app.ndjson`.
+    Never,
+    /// A new file whenever the current file would exceed the given number of bytes, e.g.
+    /// `app.ndjson`, then `app.ndjson.1`, as with `SizeLimitPolicy::RotateEarly`.
+    Size(u64),
+}
+
+#[cfg(feature = "chrono")]
+impl Rotation {
+    /// Returns the date part of file names, as chrono format specifiers.
+    fn date_format(self) -> Option<&'static str> {
+        match self {
+            Rotation::Minutely => Some("%Y-%m-%d-%H-%M"),
+            Rotation::Hourly => Some("%Y-%m-%d-%H"),
+            Rotation::Daily => Some("%Y-%m-%d"),
+            Rotation::Never | Rotation::Size(_) => None,
+        }
+    }
+}
+
+/// The time zone used to render rolling file names, and so where rotation boundaries fall.
+/// The default is UTC.
+#[cfg(feature = "chrono")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    #[default]
+    Utc,
+    /// The local time zone of the host.
+    Local,
+}
+
+/// Writes records to files whose names are rendered from a template using the current time, and
+/// switches to a new file whenever the rendered name changes, e.g. every hour for
+/// `app-%Y%m%d-%H.ndjson`.
+///
+/// Templates use [chrono format specifiers](https://docs.rs/chrono/latest/chrono/format/strftime/index.html),
+/// and `{hostname}` is replaced with the host name. Templates may contain `/` to write files in
+/// subdirectories, which are created as needed, e.g. `%Y/%m/%d/app.ndjson`.
+///
+/// ```rust,no_run
+/// use tracing_ndjson::file::{RollingFileWriter, Timezone};
+///
+/// let writer = RollingFileWriter::new("logs", "app-%Y%m%d-%H.{hostname}.ndjson")
+///     .with_timezone(Timezone::Local);
+/// let builder = tracing_ndjson::builder().with_rolling_writer(writer);
+/// ```
+#[cfg(feature = "chrono")]
+#[derive(Debug, Clone)]
+pub struct RollingFileWriter {
+    directory: PathBuf,
+    template: String,
+    timezone: Timezone,
+    sync_policy: SyncPolicy,
+    index_every: Option<usize>,
+    max_bytes: Option<(u64, SizeLimitPolicy)>,
+    dropped: Arc<AtomicU64>,
+    current: Arc<Mutex<Option<CurrentFile>>>,
+}
+
+#[cfg(feature = "chrono")]
+#[derive(Debug)]
+struct CurrentFile {
+    /// The second the file name was last rendered for.
+    second: i64,
+    name: String,
+    /// The number of early rotations since the file name changed.
+    rotations: u32,
+    file: FileWriter,
+}
+
+#[cfg(feature = "chrono")]
+impl RollingFileWriter {
+    /// Write files in `directory`, named using `template`.
+    pub fn new(directory: impl AsRef<Path>, template: impl Into<String>) -> Self {
+        let template = template.into();
+        let template = if template.contains("{hostname}") {
+            template.replace("{hostname}", &hostname())
+        } else {
+            template
+        };
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            template,
+            timezone: Timezone::default(),
+            sync_policy: SyncPolicy::default(),
+            index_every: None,
+            max_bytes: None,
+            dropped: Arc::new(AtomicU64::new(0)),
+            current: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Write files named after `path` and the current date, rotated as given, e.g. `logs/app`
+    /// with Rotation::Daily writes `logs/app.2023-10-08.ndjson`.
+    pub fn rotating(path: impl AsRef<Path>, rotation: Rotation) -> Self {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let prefix = path
+            .file_name()
+            .map(|name| name.to_string_lossy().replace('%', "%%"))
+            .unwrap_or_default();
+        let template = match rotation.date_format() {
+            Some(date) => format!("{}.{}.ndjson", prefix, date),
+            None => format!("{}.ndjson", prefix),
+        };
+        let writer = Self::new(directory, template);
+        match rotation {
+            Rotation::Size(max_bytes) => {
+                writer.with_max_bytes(max_bytes, SizeLimitPolicy::RotateEarly)
+            }
+            _ => writer,
+        }
+    }
+
+    /// Write files named `file_name` in a directory per day, i.e. `YYYY/MM/DD/<file_name>` in
+    /// `directory`, switching to the next day's directory at midnight, instead of rotating files
+    /// in place.
+    pub fn daily_directories(directory: impl AsRef<Path>, file_name: &str) -> Self {
+        Self::new(
+            directory,
+            format!("%Y/%m/%d/{}", file_name.replace('%', "%%")),
+        )
+    }
+
+    /// Set the time zone used to render file names.
+    /// The default is Timezone::Utc.
+    pub fn with_timezone(mut self, timezone: Timezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Set when records are synced to disk.
+    /// The default is SyncPolicy::Never.
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Maintain an index file next to each file, as described in `FileWriter::with_index`.
+    /// The default is no index.
+    pub fn with_index(mut self, every: usize) -> Self {
+        self.index_every = Some(every);
+        self
+    }
+
+    /// Limit each file to `max_bytes`, e.g. to keep a log storm from filling the disk of a
+    /// small device, applying the policy when a record would take the current file over it.
+    /// The default is no limit.
+    pub fn with_max_bytes(mut self, max_bytes: u64, policy: SizeLimitPolicy) -> Self {
+        self.max_bytes = Some((max_bytes, policy));
+        self
+    }
+
+    /// Returns the size limit of each file and what happens when it is reached.
+    pub fn max_bytes(&self) -> Option<(u64, SizeLimitPolicy)> {
+        self.max_bytes
+    }
+
+    /// Returns the number of records not written because the current file was full, with
+    /// SizeLimitPolicy::Stop.
+    pub fn dropped_records(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the directory files are written in.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns the time zone used to render file names.
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
+
+    /// Returns the name of the file records are written to at the given time.
+    pub fn file_name(&self, now: chrono::DateTime<chrono::Utc>) -> String {
+        match self.timezone {
+            Timezone::Utc => now.format(&self.template).to_string(),
+            Timezone::Local => now
+                .with_timezone(&chrono::Local)
+                .format(&self.template)
+                .to_string(),
+        }
+    }
+
+    fn open(&self, name: &str) -> io::Result<FileWriter> {
+        let mut file =
+            FileWriter::open(self.directory.join(name))?.with_sync_policy(self.sync_policy);
+        if let Some(every) = self.index_every {
+            file = file.with_index(every)?;
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl RecordSink for RollingFileWriter {
+    /// Write a complete record emitted at the given level to the current file.
+    fn write_record(&self, record: &[u8], meta: &RecordMeta) -> io::Result<()> {
+        let now = chrono::Utc::now();
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        // File names change at most once per second, so they are only rendered when it changes
+        let stale = !matches!(&*current, Some(c) if c.second == now.timestamp());
+        if stale {
+            let name = self.file_name(now);
+            match current.as_mut() {
+                Some(c) if c.name == name => c.second = now.timestamp(),
+                _ => {
+                    *current = Some(CurrentFile {
+                        second: now.timestamp(),
+                        file: self.open(&name)?,
+                        name,
+                        rotations: 0,
+                    });
+                }
+            }
+        }
+        let Some(CurrentFile {
+            name,
+            rotations,
+            file,
+            ..
+        }) = current.as_mut()
+        else {
+            return Ok(());
+        };
+        if let Some((max_bytes, policy)) = self.max_bytes {
+            let len = record.len() as u64;
+            match policy {
+                SizeLimitPolicy::Stop if file.len() + len > max_bytes => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                SizeLimitPolicy::Stop => {}
+                SizeLimitPolicy::RotateEarly => {
+                    // Records larger than the limit are written to an empty file of their own
+                    while file.len() > 0 && file.len() + len > max_bytes {
+                        *rotations += 1;
+                        *file = self.open(&format!("{name}.{rotations}"))?;
+                    }
+                }
+            }
+        }
+        file.write_record(record, meta)
+    }
+}
+
+/// Writes records to a separate file per level in a directory, e.g. `error.ndjson`,
+/// `warn.ndjson` and `info.ndjson`. Levels can be grouped into one file by giving them the same
+/// file name. Files are created when the first record for them is written.
+///
+/// ```rust
+/// use tracing_core::Level;
+///
+/// let files = tracing_ndjson::file::LevelFiles::new("logs")
+///     .with_file_name(Level::DEBUG, "verbose.ndjson")
+///     .with_file_name(Level::TRACE, "verbose.ndjson");
+/// let builder = tracing_ndjson::builder().with_level_files(files);
+/// ```
+#[derive(Debug, Clone)]
+pub struct LevelFiles {
+    directory: PathBuf,
+    /// File names indexed by level from TRACE to ERROR.
+    names: [String; 5],
+    sync_policy: SyncPolicy,
+    files: Arc<Mutex<std::collections::HashMap<String, FileWriter>>>,
+}
+
+impl LevelFiles {
+    /// Write files in `directory`, named `<level>.ndjson` by default.
+    pub fn new(directory: impl AsRef<Path>) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            names: [
+                Level::TRACE,
+                Level::DEBUG,
+                Level::INFO,
+                Level::WARN,
+                Level::ERROR,
+            ]
+            .map(|level| format!("{}.ndjson", level.as_str().to_lowercase())),
+            sync_policy: SyncPolicy::default(),
+            files: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        }
+    }
+
+    /// Set the name of the file records of a level are written to.
+    pub fn with_file_name(mut self, level: Level, name: impl Into<String>) -> Self {
+        self.names[level_index(level)] = name.into();
+        self
+    }
+
+    /// Set when records are synced to disk.
+    /// The default is SyncPolicy::Never.
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    /// Returns the directory files are written in.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns the path of the file records of a level are written to.
+    pub fn path(&self, level: Level) -> PathBuf {
+        self.directory.join(&self.names[level_index(level)])
+    }
+}
+
+impl RecordSink for LevelFiles {
+    /// Write a complete record emitted at the given level to the file of the level.
+    fn write_record(&self, record: &[u8], meta: &RecordMeta) -> io::Result<()> {
+        let name = &self.names[level_index(meta.level)];
+        let file = {
+            let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+            match files.get(name) {
+                Some(file) => file.clone(),
+                None => {
+                    let file = FileWriter::open(self.directory.join(name))?
+                        .with_sync_policy(self.sync_policy);
+                    files.insert(name.clone(), file.clone());
+                    file
+                }
+            }
+        };
+        file.write_record(record, meta)
+    }
+}
+
+fn level_index(level: Level) -> usize {
+    match level {
+        Level::TRACE => 0,
+        Level::DEBUG => 1,
+        Level::INFO => 2,
+        Level::WARN => 3,
+        Level::ERROR => 4,
+    }
+}
+
+/// Returns the path of the index file of a file.
+pub fn index_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_os_string();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+/// An entry of an index file, mapping a time to the byte offset of the first record written at
+/// or after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Milliseconds since UNIX_EPOCH.
+    pub timestamp_ms: i64,
+    pub offset: u64,
+}
+
+/// The index of a file written with an index, for seeking into large files by time.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "chrono")] {
+/// use std::io::{Seek, SeekFrom};
+///
+/// let index = tracing_ndjson::file::Index::open("logs/app.ndjson")?;
+/// let mut file = std::fs::File::open("logs/app.ndjson")?;
+/// file.seek(SeekFrom::Start(index.offset_at(chrono::Utc::now() - chrono::Duration::minutes(5))))?;
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Read the index of the file at `path`. Malformed entries, e.g. a partially written last
+    /// entry, are skipped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(index_path(path))?;
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let (timestamp_ms, offset) = line.split_once(' ')?;
+                Some(IndexEntry {
+                    timestamp_ms: timestamp_ms.parse().ok()?,
+                    offset: offset.parse().ok()?,
+                })
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Returns the entries in the order they were written.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Returns the offset to start reading from to see all records written at or after `time`:
+    /// the offset of the last entry before `time`, or 0 if there is none.
+    #[cfg(feature = "chrono")]
+    pub fn offset_at(&self, time: chrono::DateTime<chrono::Utc>) -> u64 {
+        let time = time.timestamp_millis();
+        let i = self
+            .entries
+            .partition_point(|entry| entry.timestamp_ms < time);
+        i.checked_sub(1)
+            .map(|i| self.entries[i].offset)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the host name, or `localhost` if it can't be determined.
+#[cfg(any(feature = "alert", feature = "chrono"))]
+pub(crate) fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// A writer made by `FileWriter` that writes its contents as one record when dropped.
+#[derive(Debug)]
+pub struct FileRecordWriter<'a> {
+    writer: &'a FileWriter,
+    level: Level,
+    buf: Vec<u8>,
+}
+
+impl Write for FileRecordWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for FileRecordWriter<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let _ = self
+                .writer
+                .write_record(&self.buf, &RecordMeta::new(self.level));
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for FileWriter {
+    type Writer = FileRecordWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        FileRecordWriter {
+            writer: self,
+            level: Level::INFO,
+            buf: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        FileRecordWriter {
+            writer: self,
+            level: *meta.level(),
+            buf: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing_core::Level;
+
+/// Retains the most recent encoded DEBUG and TRACE records in a bounded buffer until an ERROR
+/// event flushes them. Records older than the time to live are dropped when flushed.
+#[derive(Debug)]
+pub(crate) struct FlightRecorder {
+    capacity: usize,
+    ttl: Option<Duration>,
+    expired: AtomicU64,
+    records: Mutex<VecDeque<(Instant, Level, Vec<u8>)>>,
+}
+
+impl FlightRecorder {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            expired: AtomicU64::new(0),
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Drop records that were retained longer than `ttl` ago when flushing.
+    pub(crate) fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Retain an encoded record, returning true if the oldest record was evicted to make room.
+    pub(crate) fn push(&self, level: Level, line: Vec<u8>) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let evicted = records.len() == self.capacity && records.pop_front().is_some();
+        records.push_back((Instant::now(), level, line));
+        evicted
+    }
+
+    /// Take all retained records that haven't expired, oldest first. Expired records are
+    /// dropped and counted.
+    pub(crate) fn drain(&self) -> Vec<(Level, Vec<u8>)> {
+        let now = Instant::now();
+        let mut expired = 0;
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let fresh = records
+            .drain(..)
+            .filter_map(|(retained_at, level, line)| {
+                if self
+                    .ttl
+                    .is_some_and(|ttl| now.duration_since(retained_at) > ttl)
+                {
+                    expired += 1;
+                    None
+                } else {
+                    Some((level, line))
+                }
+            })
+            .collect();
+        self.expired.fetch_add(expired, Ordering::Relaxed);
+        fresh
+    }
+
+    /// Returns the number of records dropped because they expired since the last call.
+    pub(crate) fn take_expired(&self) -> u64 {
+        self.expired.swap(0, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,30 @@
+use serde_json::{Map, Value};
+use tracing_core::Metadata;
+
+use crate::FieldNames;
+
+/// A span enclosing an event, from the outermost to the innermost span.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "sentry"), allow(dead_code))]
+pub(crate) struct SpanContext {
+    pub(crate) name: &'static str,
+    pub(crate) target: String,
+    pub(crate) fields: Map<String, Value>,
+}
+
+/// Receives every written record in addition to the output, e.g. to forward errors to an error
+/// reporting service.
+pub(crate) trait Forward: Send + Sync {
+    fn forward(
+        &self,
+        metadata: &Metadata<'_>,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        spans: &[SpanContext],
+    );
+
+    /// Returns whether the forwarder uses the span context, which is only collected if needed.
+    fn needs_spans(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,200 @@
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The collected fields of a span serialized as JSON object members, e.g. `"id":42`, sorted by
+/// name, so events in the span copy them into their records without cloning or serializing
+/// their values again. Cached in the field storage of the span until a field is recorded.
+#[derive(Debug)]
+pub(crate) struct SpanFragment {
+    members: Vec<(String, Vec<u8>)>,
+}
+
+impl SpanFragment {
+    pub(crate) fn new(fields: Map<String, Value>) -> Self {
+        // Maps iterate in key order, so the members are sorted
+        let members = fields
+            .into_iter()
+            .map(|(name, value)| {
+                let mut member = Vec::new();
+                serde_json::to_writer(&mut member, &name).expect("strings serialize");
+                member.push(b':');
+                serde_json::to_writer(&mut member, &value).expect("values serialize");
+                (name, member)
+            })
+            .collect();
+        Self { members }
+    }
+
+    /// Create a fragment from serialized members, keeping the last member of each name.
+    pub(crate) fn from_members(mut members: Members) -> Self {
+        members.sort();
+        let members = members
+            .iter()
+            .map(|(name, member)| (name.to_string(), member.to_vec()))
+            .collect();
+        Self { members }
+    }
+}
+
+/// The members of a record serialized as its fields are visited, e.g. `"id":42`, so the record
+/// is encoded without collecting its fields into a map. As when inserting into a map, a member
+/// overrides earlier members with the same name. Reused by the records written on a thread.
+#[derive(Debug, Default)]
+pub(crate) struct Members {
+    buf: Vec<u8>,
+    /// The name and the serialized member of each member, as ranges of `buf`.
+    index: Vec<(Range<usize>, Range<usize>)>,
+    /// Space for rendering values using their `Debug` implementation.
+    pub(crate) rendered: String,
+}
+
+impl Members {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            index: Vec::new(),
+            rendered: String::new(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buf.clear();
+        self.index.clear();
+        self.rendered.clear();
+    }
+
+    /// Returns the number of bytes retained by the members.
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.capacity() + self.rendered.capacity()
+    }
+
+    pub(crate) fn push(&mut self, name: &str, value: &(impl Serialize + ?Sized)) {
+        let start = self.buf.len();
+        self.buf.extend_from_slice(name.as_bytes());
+        let name_end = self.buf.len();
+        serde_json::to_writer(&mut self.buf, name).expect("strings serialize");
+        self.buf.push(b':');
+        serde_json::to_writer(&mut self.buf, value).expect("values serialize");
+        self.index.push((start..name_end, name_end..self.buf.len()));
+    }
+
+    /// Sort the members by name, and then by position so `iter` keeps the last member of each
+    /// name.
+    fn sort(&mut self) {
+        let buf = &self.buf;
+        self.index.sort_unstable_by(|a, b| {
+            buf[a.0.clone()]
+                .cmp(&buf[b.0.clone()])
+                .then(a.0.start.cmp(&b.0.start))
+        });
+    }
+
+    /// Returns the names and serialized members of sorted members, skipping members overridden
+    /// by a later member with the same name.
+    fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        let buf = &self.buf;
+        let mut index = self.index.iter().peekable();
+        std::iter::from_fn(move || loop {
+            let (name, member) = index.next()?;
+            if index
+                .peek()
+                .is_some_and(|(next, _)| buf[next.clone()] == buf[name.clone()])
+            {
+                continue;
+            }
+            let name = std::str::from_utf8(&buf[name.clone()]).expect("names are strings");
+            return Some((name, &buf[member.clone()]));
+        })
+    }
+
+    /// Write the members as `NdjsonEncoder` would write a map with them, with the fields of the
+    /// spans spliced in as `encode_spliced`.
+    pub(crate) fn encode_spliced(
+        &mut self,
+        fragments: &[Arc<SpanFragment>],
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.sort();
+        let record = self
+            .iter()
+            .map(|(name, member)| (name, Member::Encoded(member)));
+        splice(record, fragments, buf)
+    }
+}
+
+/// A member of a record, with its value or serialized with its name.
+enum Member<'a> {
+    Value(&'a Value),
+    Encoded(&'a [u8]),
+}
+
+/// Write a record as `NdjsonEncoder` would with the fields of its spans merged into it, from
+/// the outermost to the innermost span: members are sorted by name, and span fields override
+/// record fields with the same name, as inner spans override outer spans.
+pub(crate) fn encode_spliced(
+    record: &Map<String, Value>,
+    fragments: &[Arc<SpanFragment>],
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    let record = record
+        .iter()
+        .map(|(name, value)| (name.as_str(), Member::Value(value)));
+    splice(record, fragments, buf)
+}
+
+/// Write the members of a record, sorted by name, with the fields of its spans merged into it
+/// as `encode_spliced`.
+fn splice<'a>(
+    record: impl Iterator<Item = (&'a str, Member<'a>)>,
+    fragments: &[Arc<SpanFragment>],
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut members: Vec<&(String, Vec<u8>)> = Vec::new();
+    for fragment in fragments.iter().rev() {
+        for member in &fragment.members {
+            if !members.iter().any(|(name, _)| *name == member.0) {
+                members.push(member);
+            }
+        }
+    }
+    members.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    buf.push(b'{');
+    let mut first = true;
+    let mut separate = |buf: &mut Vec<u8>| {
+        if !std::mem::take(&mut first) {
+            buf.push(b',');
+        }
+    };
+    let mut members = members.into_iter().peekable();
+    for (name, value) in record {
+        while let Some((_, member)) = members.next_if(|(member, _)| member.as_str() < name) {
+            separate(buf);
+            buf.extend_from_slice(member);
+        }
+        if let Some((_, member)) = members.next_if(|(member, _)| member == name) {
+            separate(buf);
+            buf.extend_from_slice(member);
+            continue;
+        }
+        separate(buf);
+        match value {
+            Member::Value(value) => {
+                serde_json::to_writer(&mut *buf, name)?;
+                buf.push(b':');
+                serde_json::to_writer(&mut *buf, value)?;
+            }
+            Member::Encoded(member) => buf.extend_from_slice(member),
+        }
+    }
+    for (_, member) in members {
+        separate(buf);
+        buf.extend_from_slice(member);
+    }
+    buf.push(b'}');
+    Ok(())
+}
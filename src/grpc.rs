@@ -0,0 +1,54 @@
+//! Helpers for creating gRPC spans with standard fields (`rpc.system`, `rpc.service`,
+//! `rpc.method` and `rpc.status_code`), yielding one schema'd record per RPC.
+//!
+//! With tonic, the server span can be created using `trace_fn`:
+//!
+//! ```rust,ignore
+//! tonic::transport::Server::builder()
+//!     .trace_fn(|request| tracing_ndjson::grpc::server_span(request.uri().path()))
+//!     .add_service(greeter)
+//!     .serve(addr)
+//!     .await?;
+//! ```
+//!
+//! and the status recorded once the response is known using `record_status`.
+
+/// Split a gRPC request path (`/package.Service/Method`) into its service and method.
+pub fn parse_path(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        return None;
+    }
+    Some((service, method))
+}
+
+/// Create a span for an incoming RPC with the given request path.
+/// The status code is recorded later using `record_status`.
+pub fn server_span(path: &str) -> tracing::Span {
+    let (service, method) = parse_path(path).unwrap_or(("unknown", "unknown"));
+    tracing::info_span!(
+        "grpc.server",
+        rpc.system = "grpc",
+        rpc.service = service,
+        rpc.method = method,
+        rpc.status_code = tracing::field::Empty,
+    )
+}
+
+/// Create a span for an outgoing RPC with the given request path.
+/// The status code is recorded later using `record_status`.
+pub fn client_span(path: &str) -> tracing::Span {
+    let (service, method) = parse_path(path).unwrap_or(("unknown", "unknown"));
+    tracing::info_span!(
+        "grpc.client",
+        rpc.system = "grpc",
+        rpc.service = service,
+        rpc.method = method,
+        rpc.status_code = tracing::field::Empty,
+    )
+}
+
+/// Record the numeric gRPC status code of an RPC on its span, e.g. `0` for OK.
+pub fn record_status(span: &tracing::Span, code: i32) {
+    span.record("rpc.status_code", code);
+}
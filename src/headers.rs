@@ -0,0 +1,136 @@
+//! Opening a root span for an incoming HTTP request with context propagated in its headers,
+//! e.g. `x-request-id`, `x-tenant` and `traceparent`, using the well-known names of the `fields`
+//! module, so framework integrations don't each reimplement the mapping.
+//!
+//! Headers are passed as name and value pairs, e.g. from an `http::HeaderMap`:
+//!
+//! ```rust,ignore
+//! let span = tracing_ndjson::headers::HeaderContext::new().root_span(
+//!     request.method().as_str(),
+//!     route,
+//!     request.headers().iter().map(|(name, value)| (name.as_str(), value.as_bytes())),
+//! );
+//! ```
+
+use crate::fields;
+
+/// The headers context is extracted from. Header names are matched case-insensitively.
+#[derive(Debug, Clone)]
+pub struct HeaderContext {
+    request_id: String,
+    tenant: String,
+    traceparent: String,
+}
+
+impl HeaderContext {
+    /// Extract the request ID from `x-request-id`, the tenant from `x-tenant`, and the trace ID
+    /// and parent span ID from a W3C `traceparent` header.
+    pub fn new() -> Self {
+        Self {
+            request_id: "x-request-id".to_string(),
+            tenant: "x-tenant".to_string(),
+            traceparent: "traceparent".to_string(),
+        }
+    }
+
+    /// Set the header the request ID is extracted from, e.g. `x-correlation-id`.
+    pub fn with_request_id_header(mut self, header: impl Into<String>) -> Self {
+        self.request_id = header.into().to_ascii_lowercase();
+        self
+    }
+
+    /// Set the header the tenant ID is extracted from, e.g. `x-org-id`.
+    pub fn with_tenant_header(mut self, header: impl Into<String>) -> Self {
+        self.tenant = header.into().to_ascii_lowercase();
+        self
+    }
+
+    /// Set the header a W3C trace context is extracted from.
+    pub fn with_traceparent_header(mut self, header: impl Into<String>) -> Self {
+        self.traceparent = header.into().to_ascii_lowercase();
+        self
+    }
+
+    /// Create a root span for a request with its method and route template, recording the
+    /// `request.id`, `tenant.id`, `trace.id`, `trace.parent_id` and `trace.sampled` fields from
+    /// the headers that are present. Values that aren't valid UTF-8 and malformed trace contexts are ignored.
+    pub fn root_span<I, K, V>(&self, method: &str, route: &str, headers: I) -> tracing::Span
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        let span = tracing::info_span!(
+            parent: None,
+            "request",
+            { fields::http::METHOD } = method,
+            { fields::http::ROUTE } = route,
+            { fields::request::ID } = tracing::field::Empty,
+            { fields::tenant::ID } = tracing::field::Empty,
+            { fields::trace::ID } = tracing::field::Empty,
+            { fields::trace::PARENT_ID } = tracing::field::Empty,
+            { fields::trace::SAMPLED } = tracing::field::Empty,
+        );
+        for (name, value) in headers {
+            let Ok(value) = std::str::from_utf8(value.as_ref()) else {
+                continue;
+            };
+            let name = name.as_ref();
+            if name.eq_ignore_ascii_case(&self.request_id) {
+                span.record(fields::request::ID, value);
+            } else if name.eq_ignore_ascii_case(&self.tenant) {
+                span.record(fields::tenant::ID, value);
+            } else if name.eq_ignore_ascii_case(&self.traceparent) {
+                if let Some((trace_id, parent_id)) = parse_traceparent(value) {
+                    span.record(fields::trace::ID, trace_id);
+                    span.record(fields::trace::PARENT_ID, parent_id);
+                }
+                if let Some(sampled) = traceparent_sampled(value) {
+                    span.record(fields::trace::SAMPLED, sampled);
+                }
+            }
+        }
+        span
+    }
+}
+
+impl Default for HeaderContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split a W3C `traceparent` header (`00-<trace-id>-<parent-id>-<flags>`) into its trace ID and
+/// parent span ID.
+pub fn parse_traceparent(traceparent: &str) -> Option<(&str, &str)> {
+    let mut parts = traceparent.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    let hex = |s: &str, len: usize| {
+        s.len() == len
+            && s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    let valid = hex(version, 2)
+        && version != "ff"
+        && hex(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && hex(parent_id, 16)
+        && parent_id.bytes().any(|b| b != b'0')
+        && hex(flags, 2);
+    // Version 00 has exactly four parts, later versions may append more
+    if !valid || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    Some((trace_id, parent_id))
+}
+
+/// Returns the sampled flag of a W3C `traceparent` header, or None if it is malformed.
+pub fn traceparent_sampled(traceparent: &str) -> Option<bool> {
+    parse_traceparent(traceparent)?;
+    let flags = traceparent.trim().split('-').nth(3)?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some(flags & 0x01 == 0x01)
+}
@@ -0,0 +1,36 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// Retains the most recent values recorded for each field of a span, stored in the span's
+/// extensions when span field history is enabled.
+#[derive(Debug)]
+pub(crate) struct FieldHistory {
+    capacity: usize,
+    values: BTreeMap<&'static str, VecDeque<serde_json::Value>>,
+}
+
+impl FieldHistory {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, name: &'static str, value: serde_json::Value) {
+        let values = self.values.entry(name).or_default();
+        if values.len() == self.capacity {
+            values.pop_front();
+        }
+        values.push_back(value);
+    }
+
+    /// Returns the values of fields that were recorded more than once, oldest first.
+    pub(crate) fn changed(
+        &self,
+    ) -> impl Iterator<Item = (&'static str, &VecDeque<serde_json::Value>)> {
+        self.values
+            .iter()
+            .filter(|(_, values)| values.len() > 1)
+            .map(|(name, values)| (*name, values))
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A kind of error the layer itself ran into while writing records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InternalError {
+    /// A record could not be encoded.
+    Serialization,
+    /// A span or the fields stored for it could not be found.
+    SpanStorage,
+    /// The output failed to write a record.
+    Write,
+}
+
+impl InternalError {
+    /// Returns the value of the `error.kind` field of the record.
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            InternalError::Serialization => "serialization",
+            InternalError::SpanStorage => "span_storage",
+            InternalError::Write => "write",
+        }
+    }
+
+    /// Returns the message of the record.
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            InternalError::Serialization => "failed to encode a record",
+            InternalError::SpanStorage => "span fields not found",
+            InternalError::Write => "failed to write a record",
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            InternalError::Serialization => 0,
+            InternalError::SpanStorage => 1,
+            InternalError::Write => 2,
+        }
+    }
+}
+
+/// Limits the records written for internal errors to at most one per kind per interval,
+/// counting the errors suppressed in between.
+#[derive(Debug)]
+pub(crate) struct InternalErrors {
+    interval: Duration,
+    last: Mutex<[Option<Instant>; 3]>,
+    suppressed: [AtomicU64; 3],
+}
+
+impl InternalErrors {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Mutex::new([None; 3]),
+            suppressed: Default::default(),
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the number of errors of the kind suppressed since the last record if a record
+    /// should be written now, or None if the error is suppressed.
+    pub(crate) fn report(&self, kind: InternalError) -> Option<u64> {
+        let now = Instant::now();
+        let suppressed = &self.suppressed[kind.index()];
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let last = &mut last[kind.index()];
+        if last.is_some_and(|last| now.duration_since(last) < self.interval) {
+            suppressed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        *last = Some(now);
+        Some(suppressed.swap(0, Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,85 @@
+use serde_json::Value;
+use tracing_subscriber::fmt::{format::DefaultFields, FormattedFields};
+use tracing_subscriber::registry::Extensions;
+
+/// Returns the span fields stored by a `tracing_subscriber::fmt` layer using the default field
+/// formatter, e.g. for spans created while that layer was the only one installed.
+pub(crate) fn formatted_fields(extensions: &Extensions<'_>) -> Option<Vec<(String, Value)>> {
+    let formatted = extensions.get::<FormattedFields<DefaultFields>>()?;
+    Some(parse_default_fields(&formatted.fields))
+}
+
+/// Parse fields formatted as `key=value` pairs separated by spaces, where string values are
+/// quoted using their `Debug` representation. ANSI styling is ignored.
+pub(crate) fn parse_default_fields(text: &str) -> Vec<(String, Value)> {
+    let text = strip_ansi(text);
+    let mut fields = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest.find(['=', ' ']).unwrap_or(rest.len());
+        if !rest[key_end..].starts_with('=') {
+            // A bare value, e.g. a message, which the default formatter writes without its name
+            let (value, tail) = split_value(rest);
+            fields.push(("message".to_string(), value));
+            rest = tail.trim_start();
+            continue;
+        }
+        let key = rest[..key_end].trim_start_matches("r#").to_string();
+        let (value, tail) = split_value(&rest[key_end + 1..]);
+        fields.push((key, value));
+        rest = tail.trim_start();
+    }
+    fields
+}
+
+/// Split a value from the start of the text, returning the value and the remaining text.
+fn split_value(text: &str) -> (Value, &str) {
+    if text.starts_with('"') {
+        let mut escaped = false;
+        for (i, c) in text.char_indices().skip(1) {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => {
+                    let quoted = &text[..=i];
+                    let value = serde_json::from_str::<String>(quoted)
+                        .unwrap_or_else(|_| quoted[1..quoted.len() - 1].to_string());
+                    return (Value::from(value), &text[i + 1..]);
+                }
+                _ => escaped = false,
+            }
+        }
+    }
+    let end = text.find(' ').unwrap_or(text.len());
+    let raw = &text[..end];
+    let value = if let Ok(value) = raw.parse::<i64>() {
+        Value::from(value)
+    } else if let Ok(value) = raw.parse::<u64>() {
+        Value::from(value)
+    } else if let Ok(value) = raw.parse::<bool>() {
+        Value::from(value)
+    } else if let Some(value) = raw.parse::<f64>().ok().filter(|v| v.is_finite()) {
+        Value::from(value)
+    } else {
+        Value::from(raw)
+    };
+    (value, &text[end..])
+}
+
+/// Remove ANSI escape sequences, e.g. the italic field names of colored output.
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip up to and including the final byte of the sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
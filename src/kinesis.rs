@@ -0,0 +1,184 @@
+//! Putting records to an AWS Kinesis Data Stream, for deployments whose log bus is a stream
+//! rather than files.
+//!
+//! Records are put in batches with the `PutRecords` API on a background thread, partitioned by
+//! the value of a field, and records that fail are retried with exponential backoff. Requests
+//! must be signed with AWS Signature Version 4, so they are sent by a transport provided by the
+//! application, e.g. wrapping the AWS SDK or an HTTP client with a SigV4 signer.
+//!
+//! ```rust
+//! use tracing_ndjson::kinesis::KinesisSink;
+//!
+//! let sink = KinesisSink::new("eu-west-1", "app-logs", |url, payload| {
+//!     // POST the payload to the URL with a signed request, with the headers
+//!     // `X-Amz-Target: Kinesis_20131202.PutRecords` and
+//!     // `Content-Type: application/x-amz-json-1.1`, returning the response body
+//!     # let _ = (url, payload);
+//!     Ok(b"{\"FailedRecordCount\":0,\"Records\":[]}".to_vec())
+//! })
+//! .with_partition_key("request.id");
+//! let builder = tracing_ndjson::builder().with_record_sink(sink);
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::sink::{RecordMeta, RecordSink};
+use crate::stream::{Batcher, Batching, Entry, Put, Transport};
+
+/// The maximum number of records in a `PutRecords` request.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Puts records to a Kinesis Data Stream in batches on a background thread.
+/// Use it with `Builder::with_record_sink` or `sink::Sink::custom`. Clones put records with the
+/// same background thread.
+#[derive(Clone)]
+pub struct KinesisSink {
+    url: String,
+    stream_name: String,
+    partition_key: Option<String>,
+    batching: Batching,
+    transport: Transport,
+    /// Spreads records without a partition key across shards.
+    sequence: Arc<AtomicU64>,
+    batcher: Arc<Batcher>,
+}
+
+impl std::fmt::Debug for KinesisSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KinesisSink")
+            .field("url", &self.url)
+            .field("stream_name", &self.stream_name)
+            .field("partition_key", &self.partition_key)
+            .field("batching", &self.batching)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KinesisSink {
+    /// Put records to the stream named `stream_name` in `region`, sending each request with
+    /// `transport`, which is called with the URL and the JSON payload and returns the response
+    /// body. By default records are spread across shards, batches of up to 500 records are put
+    /// at least every second, and each record is attempted 3 times, 100ms apart and doubling.
+    pub fn new(
+        region: &str,
+        stream_name: impl Into<String>,
+        transport: impl Fn(&str, &[u8]) -> std::io::Result<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            url: format!("https://kinesis.{}.amazonaws.com/", region),
+            stream_name: stream_name.into(),
+            partition_key: None,
+            batching: Batching {
+                size: MAX_BATCH_SIZE,
+                interval: Duration::from_secs(1),
+                attempts: 3,
+                backoff: Duration::from_millis(100),
+            },
+            transport: Arc::new(transport),
+            sequence: Default::default(),
+            batcher: Arc::new(Batcher::new("tracing-ndjson-kinesis")),
+        }
+    }
+
+    /// Use the value of a top-level field of each record as its partition key, e.g.
+    /// `request.id`, so records with the same value are kept in order on one shard. Records
+    /// without the field are spread across shards.
+    pub fn with_partition_key(mut self, field: impl Into<String>) -> Self {
+        self.partition_key = Some(field.into());
+        self
+    }
+
+    /// Put a batch when it reaches `size` records, at most 500, or `interval` after the last put.
+    pub fn with_batch(mut self, size: usize, interval: Duration) -> Self {
+        self.batching.size = size.clamp(1, MAX_BATCH_SIZE);
+        self.batching.interval = interval;
+        self
+    }
+
+    /// Attempt to put each record up to `attempts` times, waiting `backoff` before the first
+    /// retry and doubling it before each further retry.
+    pub fn with_retry(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.batching.attempts = attempts.max(1);
+        self.batching.backoff = backoff;
+        self
+    }
+
+    /// Returns the number of records dropped because too many were queued, or after the last
+    /// attempt to put them failed.
+    pub fn dropped_records(&self) -> u64 {
+        self.batcher.dropped()
+    }
+
+    /// Returns the partition key of a record without one, cycling through enough keys to reach
+    /// every shard.
+    fn next_key(&self) -> String {
+        (self.sequence.fetch_add(1, Ordering::Relaxed) % 1024).to_string()
+    }
+
+    fn put(&self) -> Put {
+        let url = self.url.clone();
+        let stream_name = self.stream_name.clone();
+        let transport = self.transport.clone();
+        Box::new(move |batch| {
+            let payload = payload(&stream_name, batch);
+            let response = transport(&url, payload.as_bytes())?;
+            failed_records(&response, batch.len())
+        })
+    }
+}
+
+impl RecordSink for KinesisSink {
+    fn write_record(&self, bytes: &[u8], _meta: &RecordMeta) -> std::io::Result<()> {
+        let key = self
+            .partition_key
+            .as_ref()
+            .and_then(|field| crate::stream::partition_key(bytes, field))
+            // Partition keys are at most 256 characters
+            .map(|key| key.chars().take(256).collect::<String>())
+            .filter(|key| !key.is_empty())
+            .unwrap_or_else(|| self.next_key());
+        let entry = Entry {
+            key: Some(key),
+            data: bytes.to_vec(),
+        };
+        self.batcher.send(self.batching, entry, || self.put());
+        Ok(())
+    }
+}
+
+/// Build a `PutRecords` request.
+fn payload(stream_name: &str, batch: &[Entry]) -> String {
+    let records: Vec<Value> = batch
+        .iter()
+        .map(|entry| {
+            json!({
+                "Data": crate::stream::base64(&entry.data),
+                "PartitionKey": entry.key,
+            })
+        })
+        .collect();
+    json!({ "StreamName": stream_name, "Records": records }).to_string()
+}
+
+/// Returns the indices of the records that failed in a `PutRecords` response.
+fn failed_records(response: &[u8], len: usize) -> std::io::Result<Vec<usize>> {
+    let response: Value = serde_json::from_slice(response)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    if response["FailedRecordCount"].as_u64() == Some(0) {
+        return Ok(Vec::new());
+    }
+    let records = response["Records"].as_array().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "no records in response")
+    })?;
+    Ok((0..len)
+        .filter(|i| {
+            records
+                .get(*i)
+                .is_none_or(|record| record.get("ErrorCode").is_some())
+        })
+        .collect())
+}
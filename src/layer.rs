@@ -1,37 +1,821 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::sync::Arc;
 
+#[cfg(feature = "chrono")]
+use chrono::{Datelike, Timelike};
 use serde_json::json;
 use tracing_core::Subscriber;
-use tracing_subscriber::{registry::LookupSpan, Layer};
+use tracing_subscriber::{fmt::format::FmtSpan, registry::LookupSpan, Layer};
 
-use crate::{storage::JsonStorage, TimestampFormat};
+use crate::{
+    adapters::FieldAdapter,
+    boost::VerbosityBoost,
+    caller::CallerCache,
+    canonical::CanonicalLine,
+    clock::ClockSource,
+    coalesce::{CoalesceFlush, Coalescer},
+    dedup::ValueDedup,
+    disk_space::DiskSpaceGuard,
+    flight::FlightRecorder,
+    forward::{Forward, SpanContext},
+    fragment::{Members, SpanFragment},
+    history::FieldHistory,
+    internal::{InternalError, InternalErrors},
+    non_blocking::NonBlocking,
+    output::Output,
+    quota::FieldQuota,
+    sampling::{Sampled, SpanSampler},
+    span_summary::{SpanStarted, SpanSummary},
+    stats::{SizeReport, Stats, Summary},
+    storage::{FieldVisitors, JsonStorage},
+    timings::Timings,
+    watchdog::WriteWatchdog,
+    Encoder, FieldNames, NdjsonEncoder, SpanOrder, TimestampFormat,
+};
 
+/// Encoding buffers that grew larger than this are not reused.
+const MAX_RETAINED_BUFFER: usize = 64 * 1024;
+
+thread_local! {
+    /// The buffers records are encoded and framed into, reused so writing a record doesn't
+    /// allocate.
+    static ENCODE_BUFFERS: Cell<(Vec<u8>, Vec<u8>)> = const { Cell::new((Vec::new(), Vec::new())) };
+
+    /// The members the fields of events are serialized into as they are visited, reused so
+    /// encoding an event doesn't collect its fields into a map.
+    static EVENT_MEMBERS: Cell<Members> = const { Cell::new(Members::new()) };
+
+    /// Whether the current thread is writing the record of an internal error, whose own errors
+    /// are not reported.
+    static REPORTING_INTERNAL_ERROR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A `Layer` that formats events as JSON. Create one using `Builder`.
+/// The layer also describes its effective configuration through its getters and `Debug`.
+#[derive(Clone)]
 pub struct JsonFormattingLayer {
-    pub(crate) level_name: &'static str,
+    pub(crate) level_name: Cow<'static, str>,
     pub(crate) level_value_casing: crate::Casing,
-    pub(crate) message_name: &'static str,
-    pub(crate) target_name: &'static str,
-    pub(crate) timestamp_name: &'static str,
+    pub(crate) message_name: Cow<'static, str>,
+    pub(crate) target_name: Cow<'static, str>,
+    pub(crate) timestamp_name: Cow<'static, str>,
     pub(crate) timestamp_format: crate::TimestampFormat,
     pub(crate) line_numbers: bool,
     pub(crate) file_names: bool,
     pub(crate) flatten_fields: bool,
     pub(crate) flatten_spans: bool,
+    pub(crate) span_order: SpanOrder,
+    pub(crate) span_depth: bool,
+    pub(crate) current_span: bool,
+    pub(crate) thread_ids: bool,
+    pub(crate) thread_names: bool,
+    pub(crate) span_list: bool,
+    pub(crate) caller: bool,
+    pub(crate) caller_cache: CallerCache,
+    pub(crate) encoder: Arc<dyn Encoder>,
+    pub(crate) framing: crate::Framing,
+    pub(crate) trailing_terminator: bool,
+    pub(crate) target_overrides: Vec<(String, JsonFormattingLayer)>,
+    pub(crate) field_adapters: Vec<Arc<dyn FieldAdapter>>,
+    pub(crate) field_visitors: FieldVisitors,
+    pub(crate) event_filter: Option<EventFilter>,
+    pub(crate) multiline: crate::Multiline,
+    pub(crate) exception_fields: bool,
+    pub(crate) clock: ClockSource,
+    pub(crate) level_override_field: Option<Cow<'static, str>>,
+    pub(crate) level_number_name: Option<Cow<'static, str>>,
+    pub(crate) verbosity_field: Option<(Cow<'static, str>, u8)>,
+    pub(crate) span_timings: bool,
+    pub(crate) span_events: tracing_subscriber::fmt::format::FmtSpan,
+    pub(crate) strict_span_lookup: bool,
+    pub(crate) exclusive_stdout: bool,
+    pub(crate) formatted_fields_fallback: bool,
+    pub(crate) storage_pooling: bool,
+    pub(crate) span_fragments: bool,
+    /// Whether the encoder is `NdjsonEncoder`, whose output span fragments can be spliced into.
+    pub(crate) ndjson_encoder: bool,
+    /// Whether an option reads or rewrites the fields of each record, so they are collected into
+    /// a map rather than spliced and streamed. Set by `Builder::build`.
+    pub(crate) needs_map: bool,
+    pub(crate) span_field_history: usize,
+    pub(crate) flight_recorder: Option<Arc<FlightRecorder>>,
+    pub(crate) size_report: Option<Arc<SizeReport>>,
+    pub(crate) span_summaries: std::collections::HashMap<String, Arc<SpanSummary>>,
+    pub(crate) forwarders: Vec<Arc<dyn Forward>>,
+    pub(crate) output: Output,
+    pub(crate) non_blocking: Option<Arc<NonBlocking>>,
+    pub(crate) record_ttl: Option<std::time::Duration>,
+    pub(crate) write_watchdog: Option<Arc<WriteWatchdog>>,
+    pub(crate) verbosity_boost: Option<Arc<VerbosityBoost>>,
+    pub(crate) disk_space: Option<Arc<DiskSpaceGuard>>,
+    pub(crate) internal_errors: Option<Arc<InternalErrors>>,
+    pub(crate) field_quota: Option<Arc<FieldQuota>>,
+    pub(crate) pii_mode: Option<crate::PiiMode>,
+    pub(crate) span_sampler: Option<Arc<SpanSampler>>,
+    pub(crate) value_dedup: Option<Arc<ValueDedup>>,
+    pub(crate) crate_field: bool,
+    pub(crate) global_fields: serde_json::Map<String, serde_json::Value>,
+    pub(crate) fingerprint: bool,
+    pub(crate) time_partitions: bool,
+    pub(crate) suppressed_targets: Vec<String>,
+    pub(crate) typed_fields: std::collections::HashMap<String, crate::FieldType>,
+    pub(crate) type_mismatch: crate::TypeMismatch,
+    pub(crate) display_capture: crate::CapturePolicy,
+    pub(crate) debug_capture: crate::CapturePolicy,
+    pub(crate) field_units: std::collections::HashMap<String, crate::Unit>,
+    pub(crate) unit_style: crate::UnitStyle,
+    pub(crate) allowed_fields: Option<std::collections::HashSet<String>>,
+    pub(crate) unknown_fields: crate::UnknownFields,
+    pub(crate) provenance: Option<crate::Provenance>,
+    pub(crate) collision_policy: crate::CollisionPolicy,
+    pub(crate) gcp_format: bool,
+    pub(crate) gcp_project: Option<String>,
+    pub(crate) sampled_field: bool,
+    pub(crate) target_strip_prefixes: Vec<String>,
+    pub(crate) target_segments: Option<usize>,
+    pub(crate) target_aliases: Vec<(String, String)>,
+    pub(crate) stats: Arc<Stats>,
+    #[cfg(all(feature = "signals", unix))]
+    pub(crate) crash_ring: Option<Arc<crate::signals::CrashRing>>,
+    #[cfg(feature = "diagnostics")]
+    pub(crate) diagnostics: Option<Arc<crate::diagnostics::Diagnostics>>,
+    #[cfg(feature = "opentelemetry")]
+    pub(crate) otel: Option<Arc<crate::otel::OtelLookup>>,
+    pub(crate) canonical_span: Option<(String, crate::CanonicalConflict)>,
+    pub(crate) canonical_rollups: Vec<crate::CanonicalRollup>,
+    pub(crate) coalescer: Option<Arc<Coalescer>>,
+    pub(crate) coalesce_flush: Option<Arc<CoalesceFlush>>,
+    pub(crate) summary: Option<Arc<Summary>>,
+}
+
+/// The depth and name of a span whose fields are added to a record.
+type SpanScope = (usize, &'static str);
+
+pub(crate) type EventFilter =
+    Arc<dyn Fn(&tracing_core::Metadata<'_>, &EventFields<'_>) -> bool + Send + Sync>;
+
+/// The fields of an event and its spans, as passed to an event filter.
+/// Field names are as they would be emitted, i.e. after the message field is renamed and
+/// field adapters are applied.
+pub struct EventFields<'a> {
+    event: &'a serde_json::Map<String, serde_json::Value>,
+    spans: &'a [serde_json::Map<String, serde_json::Value>],
+}
+
+impl EventFields<'_> {
+    /// Returns the value of a field, looking at the event fields first and then the fields of
+    /// the enclosing spans from the innermost to the outermost span.
+    pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+        self.event
+            .get(name)
+            .or_else(|| self.spans.iter().rev().find_map(|span| span.get(name)))
+    }
+
+    /// Returns the fields recorded on the event itself.
+    pub fn event(&self) -> &serde_json::Map<String, serde_json::Value> {
+        self.event
+    }
+}
+
+impl std::fmt::Debug for JsonFormattingLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonFormattingLayer")
+            .field("level_name", &self.level_name)
+            .field("level_value_casing", &self.level_value_casing)
+            .field("message_name", &self.message_name)
+            .field("target_name", &self.target_name)
+            .field("timestamp_name", &self.timestamp_name)
+            .field("timestamp_format", &self.timestamp_format)
+            .field("line_numbers", &self.line_numbers)
+            .field("file_names", &self.file_names)
+            .field("flatten_fields", &self.flatten_fields)
+            .field("flatten_spans", &self.flatten_spans)
+            .field("span_order", &self.span_order)
+            .field("span_depth", &self.span_depth)
+            .field("current_span", &self.current_span)
+            .field("thread_ids", &self.thread_ids)
+            .field("thread_names", &self.thread_names)
+            .field("span_list", &self.span_list)
+            .field("caller", &self.caller)
+            .field("framing", &self.framing)
+            .field("trailing_terminator", &self.trailing_terminator)
+            .field("target_overrides", &self.target_overrides)
+            .field("field_adapters", &self.field_adapters.len())
+            .field("field_visitors", &self.field_visitors.len())
+            .field("event_filter", &self.event_filter.is_some())
+            .field("multiline", &self.multiline)
+            .field("exception_fields", &self.exception_fields)
+            .field("clock", &self.clock.clock())
+            .field("level_override_field", &self.level_override_field)
+            .field("level_number_name", &self.level_number_name)
+            .field("verbosity_field", &self.verbosity_field)
+            .field("span_timings", &self.span_timings)
+            .field("span_events", &self.span_events)
+            .field("strict_span_lookup", &self.strict_span_lookup)
+            .field("exclusive_stdout", &self.exclusive_stdout)
+            .field("formatted_fields_fallback", &self.formatted_fields_fallback)
+            .field("storage_pooling", &self.storage_pooling)
+            .field("span_fragments", &self.span_fragments)
+            .field("span_field_history", &self.span_field_history)
+            .field("flight_recorder", &self.flight_recorder())
+            .field("size_report", &self.size_report())
+            .field("span_summaries", &self.span_summaries().collect::<Vec<_>>())
+            .field("forwarders", &self.forwarders.len())
+            .field("output", &self.output)
+            .field("non_blocking", &self.non_blocking())
+            .field("priority_lanes", &self.priority_lanes())
+            .field("record_ttl", &self.record_ttl)
+            .field("write_watchdog", &self.write_watchdog())
+            .field("verbosity_boost", &self.verbosity_boost())
+            .field("disk_space_backoff", &self.disk_space_backoff())
+            .field("internal_error_records", &self.internal_error_records())
+            .field("field_quota", &self.field_quota())
+            .field("pii_mode", &self.pii_mode)
+            .field("span_sampling", &self.span_sampling())
+            .field("value_dedup", &self.value_dedup())
+            .field("crate_field", &self.crate_field)
+            .field("global_fields", &self.global_fields)
+            .field("time_partitions", &self.time_partitions)
+            .field("fingerprint", &self.fingerprint)
+            .field("suppressed_targets", &self.suppressed_targets)
+            .field("typed_fields", &self.typed_fields)
+            .field("type_mismatch", &self.type_mismatch)
+            .field("display_capture", &self.display_capture)
+            .field("debug_capture", &self.debug_capture)
+            .field("field_units", &self.field_units)
+            .field("unit_style", &self.unit_style)
+            .field("allowed_fields", &self.allowed_fields)
+            .field("unknown_fields", &self.unknown_fields)
+            .field("provenance", &self.provenance)
+            .field("collision_policy", &self.collision_policy)
+            .field("gcp_format", &self.gcp_format)
+            .field("gcp_project", &self.gcp_project)
+            .field("sampled_field", &self.sampled_field)
+            .field("target_strip_prefixes", &self.target_strip_prefixes)
+            .field("target_segments", &self.target_segments)
+            .field("target_aliases", &self.target_aliases)
+            .field("canonical_span", &self.canonical_span)
+            .field("canonical_rollups", &self.canonical_rollups)
+            .field("progress_coalescing", &self.progress_coalescing())
+            .field("summary", &self.summary.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl JsonFormattingLayer {
+    /// Count a span or span storage that could not be found, panicking in debug builds if strict
+    /// span lookup is enabled.
+    fn span_lookup_failed(&self, message: &str) {
+        self.stats.record_span_lookup_failure();
+        self.internal_error(InternalError::SpanStorage, message);
+        #[cfg(feature = "diagnostics")]
+        self.diagnose(crate::diagnostics::Misconfiguration::MissingSpanStorage);
+        if self.strict_span_lookup && cfg!(debug_assertions) {
+            panic!("{}", message);
+        }
+    }
+
+    /// Returns the field name for the level field.
+    pub fn level_name(&self) -> &str {
+        &self.level_name
+    }
+
+    /// Returns the casing for the level field value.
+    pub fn level_value_casing(&self) -> &crate::Casing {
+        &self.level_value_casing
+    }
+
+    /// Returns the field name for the message field.
+    pub fn message_name(&self) -> &str {
+        &self.message_name
+    }
+
+    /// Returns the field name for the target field.
+    pub fn target_name(&self) -> &str {
+        &self.target_name
+    }
+
+    /// Returns the field name for the timestamp field.
+    pub fn timestamp_name(&self) -> &str {
+        &self.timestamp_name
+    }
+
+    /// Returns the timestamp format for the timestamp field.
+    pub fn timestamp_format(&self) -> &TimestampFormat {
+        &self.timestamp_format
+    }
+
+    /// Returns whether line numbers are included.
+    pub fn line_numbers(&self) -> bool {
+        self.line_numbers
+    }
+
+    /// Returns whether file names are included.
+    pub fn file_names(&self) -> bool {
+        self.file_names
+    }
+
+    /// Returns whether event fields are flattened.
+    pub fn flatten_fields(&self) -> bool {
+        self.flatten_fields
+    }
+
+    /// Returns whether span fields are flattened.
+    pub fn flatten_spans(&self) -> bool {
+        self.flatten_spans
+    }
+
+    /// Returns the order of the nested `spans` array.
+    pub fn span_order(&self) -> SpanOrder {
+        self.span_order
+    }
+
+    /// Returns whether the depth of each span is included in the nested `spans` array.
+    pub fn span_depth(&self) -> bool {
+        self.span_depth
+    }
+
+    /// Returns whether the ID of the thread that recorded the event is included.
+    pub fn thread_ids(&self) -> bool {
+        self.thread_ids
+    }
+
+    /// Returns whether the name of the thread that recorded the event is included.
+    pub fn thread_names(&self) -> bool {
+        self.thread_names
+    }
+
+    /// Returns whether the name of the current span is included as the `span` field.
+    pub fn current_span(&self) -> bool {
+        self.current_span
+    }
+
+    /// Returns whether the names of the current span and its parents are included as the
+    /// `span_list` field.
+    pub fn span_list(&self) -> bool {
+        self.span_list
+    }
+
+    /// Returns whether the caller is included.
+    pub fn caller(&self) -> bool {
+        self.caller
+    }
+
+    /// Returns how records are framed in the output stream.
+    pub fn framing(&self) -> &crate::Framing {
+        &self.framing
+    }
+
+    /// Returns whether records written to stdout or a file are followed by the terminator of
+    /// the framing, rather than preceded by it.
+    pub fn trailing_terminator(&self) -> bool {
+        self.trailing_terminator
+    }
+
+    /// Returns how multi-line string values are emitted.
+    pub fn multiline(&self) -> crate::Multiline {
+        self.multiline
+    }
+
+    /// Returns whether exception fields are extracted from multi-line values.
+    pub fn exception_fields(&self) -> bool {
+        self.exception_fields
+    }
+
+    /// Returns the name of the event field that overrides the emitted level, if any.
+    pub fn level_override_field(&self) -> Option<&str> {
+        self.level_override_field.as_deref()
+    }
+
+    /// Returns the name of the field the level is emitted in as a syslog severity number, if
+    /// enabled.
+    pub fn level_number_name(&self) -> Option<&str> {
+        self.level_number_name.as_deref()
+    }
+
+    /// Returns the name of the field whose value selects a verbosity tier of the level, and the
+    /// highest tier, if set.
+    pub fn verbosity_field(&self) -> Option<(&str, u8)> {
+        self.verbosity_field
+            .as_ref()
+            .map(|(name, max_tier)| (name.as_ref(), *max_tier))
+    }
+
+    /// Returns whether a record with the number of polls and busy and idle time is written when a
+    /// span closes.
+    pub fn span_timings(&self) -> bool {
+        self.span_timings
+    }
+
+    /// Returns the points in the lifecycle of spans records are written at.
+    pub fn span_events(&self) -> &tracing_subscriber::fmt::format::FmtSpan {
+        &self.span_events
+    }
+
+    /// Returns whether a span or span storage that cannot be found panics in debug builds.
+    pub fn strict_span_lookup(&self) -> bool {
+        self.strict_span_lookup
+    }
+
+    /// Returns whether the layer claims exclusive ownership of stdout.
+    pub fn exclusive_stdout(&self) -> bool {
+        self.exclusive_stdout
+    }
+
+    /// Returns whether span fields formatted by a `tracing_subscriber::fmt` layer are read for
+    /// spans without stored fields.
+    pub fn formatted_fields_fallback(&self) -> bool {
+        self.formatted_fields_fallback
+    }
+
+    /// Returns whether the field storage of spans and events is reused.
+    pub fn storage_pooling(&self) -> bool {
+        self.storage_pooling
+    }
+
+    /// Returns whether span fields are serialized once and spliced into records when possible.
+    pub fn span_fragments(&self) -> bool {
+        self.span_fragments
+    }
+
+    /// Returns how many values are retained for each span field, where 0 means span field
+    /// history is disabled.
+    pub fn span_field_history(&self) -> usize {
+        self.span_field_history
+    }
+
+    /// Returns whether the crate name is emitted as the `crate` field.
+    pub fn crate_field(&self) -> bool {
+        self.crate_field
+    }
+
+    /// Returns the fields added to every record.
+    pub fn global_fields(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.global_fields
+    }
+
+    /// Returns whether the `date`, `hour` and `iso_week` fields are emitted.
+    pub fn time_partitions(&self) -> bool {
+        self.time_partitions
+    }
+
+    /// Returns the declared types of fields.
+    pub fn typed_fields(&self) -> &std::collections::HashMap<String, crate::FieldType> {
+        &self.typed_fields
+    }
+
+    /// Returns how typed fields with values of another type are handled.
+    pub fn type_mismatch(&self) -> crate::TypeMismatch {
+        self.type_mismatch
+    }
+
+    /// Returns how fields captured in the given mode are written.
+    pub fn capture_policy(&self, mode: crate::CaptureMode) -> crate::CapturePolicy {
+        match mode {
+            crate::CaptureMode::Display => self.display_capture,
+            crate::CaptureMode::Debug => self.debug_capture,
+        }
+    }
+
+    /// Returns whether the capture mode of fields is detected, which is only needed if a capture
+    /// policy is set.
+    fn detects_captures(&self) -> bool {
+        self.display_capture != crate::CapturePolicy::Keep
+            || self.debug_capture != crate::CapturePolicy::Keep
+    }
+
+    /// Returns new field storage, detecting capture modes if a capture policy is set.
+    fn storage(&self) -> JsonStorage {
+        let mut storage = if self.storage_pooling {
+            JsonStorage::pooled(self.detects_captures())
+        } else {
+            JsonStorage::detecting_captures(self.detects_captures())
+        };
+        storage.visitors = self.field_visitors.clone();
+        storage
+    }
+
+    /// Returns the declared units of fields.
+    pub fn field_units(&self) -> &std::collections::HashMap<String, crate::Unit> {
+        &self.field_units
+    }
+
+    /// Returns how fields with a declared unit are written.
+    pub fn unit_style(&self) -> crate::UnitStyle {
+        self.unit_style
+    }
+
+    /// Returns the allowed field names if strict schema mode is enabled.
+    pub fn allowed_fields(&self) -> Option<&std::collections::HashSet<String>> {
+        self.allowed_fields.as_ref()
+    }
+
+    /// Returns how fields not in the allowed fields are handled.
+    pub fn unknown_fields(&self) -> crate::UnknownFields {
+        self.unknown_fields
+    }
+
+    /// Returns how the origin of each field is annotated, if enabled.
+    pub fn field_provenance(&self) -> Option<crate::Provenance> {
+        self.provenance
+    }
+
+    /// Returns how event and span fields colliding with built-in fields are merged.
+    pub fn collision_policy(&self) -> &crate::CollisionPolicy {
+        &self.collision_policy
+    }
+
+    /// Returns whether records are written in the Google Cloud Logging structured format.
+    pub fn gcp_format(&self) -> bool {
+        self.gcp_format
+    }
+
+    /// Returns the Google Cloud project trace IDs are qualified with in the GCP format.
+    pub fn gcp_project(&self) -> Option<&str> {
+        self.gcp_project.as_deref()
+    }
+
+    /// Returns whether the sampled flag of the trace context is emitted as the `sampled` field.
+    pub fn sampled_field(&self) -> bool {
+        self.sampled_field
+    }
+
+    /// Returns whether a `fingerprint` field grouping similar records is emitted.
+    pub fn fingerprint(&self) -> bool {
+        self.fingerprint
+    }
+
+    /// Returns the patterns of targets whose events are suppressed.
+    pub fn suppressed_targets(&self) -> &[String] {
+        &self.suppressed_targets
+    }
+
+    /// Returns the prefixes stripped from targets.
+    pub fn target_strip_prefixes(&self) -> &[String] {
+        &self.target_strip_prefixes
+    }
+
+    /// Returns the number of trailing target path segments kept, if targets are shortened.
+    pub fn target_segments(&self) -> Option<usize> {
+        self.target_segments
+    }
+
+    /// Returns the target aliases as pairs of target prefix and alias.
+    pub fn target_aliases(&self) -> &[(String, String)] {
+        &self.target_aliases
+    }
+
+    /// Returns the interval and the number of top callsites of the periodic size report, if
+    /// enabled.
+    pub fn size_report(&self) -> Option<(std::time::Duration, usize)> {
+        self.size_report
+            .as_ref()
+            .map(|report| (report.interval(), report.top()))
+    }
+
+    /// Returns the number of records retained for a crash dump and their maximum length in
+    /// bytes, if the crash ring is enabled.
+    #[cfg(all(feature = "signals", unix))]
+    pub fn crash_ring(&self) -> Option<(usize, usize)> {
+        self.crash_ring
+            .as_ref()
+            .map(|ring| (ring.slots(), ring.slot_len()))
+    }
+
+    /// Returns whether misconfigurations are reported with warning records.
+    #[cfg(feature = "diagnostics")]
+    pub fn diagnostics(&self) -> bool {
+        self.diagnostics.is_some()
+    }
+
+    /// Returns the fields the OpenTelemetry trace and span IDs are written to, if they are
+    /// added to records.
+    #[cfg(feature = "opentelemetry")]
+    pub fn otel_ids(&self) -> Option<&crate::otel::OtelIds> {
+        self.otel.as_ref().and_then(|otel| otel.ids())
+    }
+
+    /// Returns the minimum interval between records of the same kind of internal error, if
+    /// internal errors are written as records.
+    pub fn internal_error_records(&self) -> Option<std::time::Duration> {
+        self.internal_errors
+            .as_ref()
+            .map(|internal_errors| internal_errors.interval())
+    }
+
+    /// Returns the free space below which the minimum level is raised, the raised level and the
+    /// interval free space is checked at, if disk space backoff is enabled.
+    pub fn disk_space_backoff(&self) -> Option<(u64, tracing_core::Level, std::time::Duration)> {
+        self.disk_space
+            .as_ref()
+            .map(|guard| (guard.min_free(), guard.level(), guard.interval()))
+    }
+
+    /// Returns the name of the spans written as a single canonical log line and how conflicting
+    /// fields are merged, if enabled.
+    pub fn canonical_log_line(&self) -> Option<(&str, crate::CanonicalConflict)> {
+        self.canonical_span
+            .as_ref()
+            .map(|(name, conflict)| (name.as_str(), *conflict))
+    }
+
+    /// Returns the rollups of child spans added to canonical log lines.
+    pub fn canonical_rollups(&self) -> &[crate::CanonicalRollup] {
+        &self.canonical_rollups
+    }
+
+    /// Returns the field marking progress callsites and the window their records are coalesced
+    /// in, if enabled.
+    pub fn progress_coalescing(&self) -> Option<(&str, std::time::Duration)> {
+        self.coalescer
+            .as_ref()
+            .map(|coalescer| (coalescer.field(), coalescer.window()))
+    }
+
+    /// Returns the names of the spans whose durations are summarized, with the interval of
+    /// their summaries.
+    pub fn span_summaries(&self) -> impl Iterator<Item = (&str, std::time::Duration)> + '_ {
+        self.span_summaries
+            .iter()
+            .map(|(name, summary)| (name.as_str(), summary.interval()))
+    }
+
+    /// Returns the number of DEBUG and TRACE records retained until an ERROR event, if the flight
+    /// recorder is enabled.
+    pub fn flight_recorder(&self) -> Option<usize> {
+        self.flight_recorder
+            .as_ref()
+            .map(|recorder| recorder.capacity())
+    }
+
+    /// Returns the time to live of records buffered by non-blocking output or the flight
+    /// recorder, if set.
+    pub fn record_ttl(&self) -> Option<std::time::Duration> {
+        self.record_ttl
+    }
+
+    /// Returns whether WARN and ERROR records are written ahead of other records queued by
+    /// non-blocking output.
+    pub fn priority_lanes(&self) -> bool {
+        self.non_blocking
+            .as_ref()
+            .is_some_and(|non_blocking| non_blocking.queue().priority_lanes())
+    }
+
+    /// Returns the capacity and high-water mark of the queue of records written by a background
+    /// thread, if non-blocking output is enabled.
+    pub fn non_blocking(&self) -> Option<(usize, usize)> {
+        self.non_blocking.as_ref().map(|non_blocking| {
+            (
+                non_blocking.queue().capacity(),
+                non_blocking.queue().high_water(),
+            )
+        })
+    }
+
+    /// Returns the duration above which a write is reported as slow, if the write watchdog is
+    /// enabled.
+    pub fn write_watchdog(&self) -> Option<std::time::Duration> {
+        self.write_watchdog
+            .as_ref()
+            .map(|watchdog| watchdog.threshold())
+    }
+
+    /// Returns the correlation field, window and most verbose boosted level, if verbosity boosts
+    /// are enabled.
+    pub fn verbosity_boost(&self) -> Option<(&str, std::time::Duration, tracing_core::Level)> {
+        self.verbosity_boost
+            .as_ref()
+            .map(|boost| (boost.field(), boost.window(), boost.level()))
+    }
+
+    /// Returns the quota field, number of records per window and window, if a field quota is
+    /// enabled.
+    pub fn field_quota(&self) -> Option<(&str, u64, std::time::Duration)> {
+        self.field_quota
+            .as_ref()
+            .map(|quota| (quota.field(), quota.limit(), quota.window()))
+    }
+
+    /// Returns how potential PII in field values is handled, if PII detection is enabled.
+    pub fn pii_mode(&self) -> Option<crate::PiiMode> {
+        self.pii_mode
+    }
+
+    /// Returns the 1-in-N rate at which root spans are sampled and the most verbose level emitted
+    /// in spans that are not sampled, if span sampling is enabled.
+    pub fn span_sampling(&self) -> Option<(u64, tracing_core::Level)> {
+        self.span_sampler
+            .as_ref()
+            .map(|sampler| (sampler.one_in(), sampler.unsampled_level()))
+    }
+
+    /// Returns the minimum length and window of deduplicated field values, if enabled.
+    pub fn value_dedup(&self) -> Option<(usize, std::time::Duration)> {
+        self.value_dedup
+            .as_ref()
+            .map(|dedup| (dedup.min_len(), dedup.window()))
+    }
+
+    /// Returns the clock used to timestamp records.
+    pub fn clock(&self) -> crate::Clock {
+        self.clock.clock()
+    }
 }
 
 impl Default for JsonFormattingLayer {
     fn default() -> Self {
         Self {
-            level_name: "level",
+            level_name: Cow::Borrowed("level"),
             level_value_casing: crate::Casing::default(),
-            message_name: "message",
-            target_name: "target",
-            timestamp_name: "timestamp",
+            message_name: Cow::Borrowed("message"),
+            target_name: Cow::Borrowed("target"),
+            timestamp_name: Cow::Borrowed("timestamp"),
             timestamp_format: crate::TimestampFormat::default(),
             line_numbers: false,
             file_names: false,
             flatten_fields: true,
             flatten_spans: true,
+            span_order: SpanOrder::RootToLeaf,
+            span_depth: false,
+            current_span: false,
+            thread_ids: false,
+            thread_names: false,
+            span_list: false,
+            caller: false,
+            caller_cache: CallerCache::default(),
+            encoder: Arc::new(NdjsonEncoder),
+            framing: crate::Framing::default(),
+            trailing_terminator: true,
+            target_overrides: Vec::new(),
+            field_adapters: Vec::new(),
+            field_visitors: FieldVisitors::default(),
+            event_filter: None,
+            multiline: crate::Multiline::default(),
+            exception_fields: false,
+            clock: ClockSource::default(),
+            level_override_field: None,
+            level_number_name: None,
+            verbosity_field: None,
+            span_timings: false,
+            span_events: tracing_subscriber::fmt::format::FmtSpan::NONE,
+            strict_span_lookup: false,
+            exclusive_stdout: false,
+            formatted_fields_fallback: false,
+            storage_pooling: true,
+            span_fragments: true,
+            ndjson_encoder: true,
+            needs_map: false,
+            span_field_history: 0,
+            flight_recorder: None,
+            size_report: None,
+            span_summaries: std::collections::HashMap::new(),
+            forwarders: Vec::new(),
+            output: Output::default(),
+            non_blocking: None,
+            record_ttl: None,
+            write_watchdog: None,
+            verbosity_boost: None,
+            disk_space: None,
+            internal_errors: None,
+            field_quota: None,
+            pii_mode: None,
+            span_sampler: None,
+            value_dedup: None,
+            crate_field: false,
+            global_fields: serde_json::Map::new(),
+            time_partitions: false,
+            fingerprint: false,
+            suppressed_targets: Vec::new(),
+            typed_fields: std::collections::HashMap::new(),
+            type_mismatch: crate::TypeMismatch::default(),
+            display_capture: crate::CapturePolicy::default(),
+            debug_capture: crate::CapturePolicy::default(),
+            field_units: std::collections::HashMap::new(),
+            unit_style: crate::UnitStyle::default(),
+            allowed_fields: None,
+            unknown_fields: crate::UnknownFields::default(),
+            provenance: None,
+            collision_policy: crate::CollisionPolicy::default(),
+            gcp_format: false,
+            gcp_project: None,
+            sampled_field: false,
+            target_strip_prefixes: Vec::new(),
+            target_segments: None,
+            target_aliases: Vec::new(),
+            stats: Arc::new(Stats::default()),
+            #[cfg(all(feature = "signals", unix))]
+            crash_ring: None,
+            #[cfg(feature = "diagnostics")]
+            diagnostics: None,
+            #[cfg(feature = "opentelemetry")]
+            otel: None,
+            canonical_span: None,
+            canonical_rollups: Vec::new(),
+            coalescer: None,
+            coalesce_flush: None,
+            summary: None,
         }
     }
 }
@@ -40,16 +824,46 @@ impl<S> Layer<S> for JsonFormattingLayer
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
+    fn on_register_dispatch(&self, subscriber: &tracing_core::Dispatch) {
+        #[cfg(feature = "diagnostics")]
+        if subscriber
+            .downcast_ref::<tracing_subscriber::Registry>()
+            .is_none()
+        {
+            self.diagnose(crate::diagnostics::Misconfiguration::NoRegistry);
+        }
+        #[cfg(feature = "opentelemetry")]
+        if let Some(otel) = &self.otel {
+            otel.register(subscriber);
+        }
+        if !self.exclusive_stdout || !matches!(self.output.base(), Output::Stdout) {
+            return;
+        }
+        crate::exclusive::claim_stdout();
+        // Panicking here would poison the global dispatcher registry, so only warn
+        if let Err(e) = crate::exclusive::check_stdout(subscriber) {
+            eprintln!("tracing-ndjson: {}", e);
+        }
+    }
+
     fn on_new_span(
         &self,
         attrs: &tracing_core::span::Attributes<'_>,
         id: &tracing_core::span::Id,
         ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        let span = ctx.span(id).expect("Span not found, this is a bug");
+        let Some(span) = ctx.span(id) else {
+            self.span_lookup_failed("Span not found on 'new_span', this is a bug");
+            return;
+        };
+
+        #[cfg(feature = "diagnostics")]
+        if let Some(diagnostics) = &self.diagnostics {
+            diagnostics.span_created();
+        }
 
         // Create a new visitor to store fields
-        let mut visitor = JsonStorage::default();
+        let mut visitor = self.storage();
 
         // Register all fields.
         // Fields on the new span should override fields on the parent span if there is a conflict.
@@ -57,7 +871,39 @@ where
 
         // Associate the visitor with the Span for future usage via the Span's extensions
         let mut extensions = span.extensions_mut();
+        let layer = self.layer_for(attrs.metadata().target());
+        if self.span_summaries.contains_key(attrs.metadata().name())
+            || self.rollup_matches(attrs.metadata().name())
+        {
+            extensions.insert(SpanStarted(std::time::Instant::now()));
+        }
+        if layer.span_timings || layer.has_span_event(FmtSpan::CLOSE) {
+            extensions.insert(Timings::new());
+        }
+        if matches!(&self.canonical_span, Some((name, _)) if name == attrs.metadata().name()) {
+            extensions.insert(CanonicalLine::new(*attrs.metadata().level()));
+        }
+        if layer.span_field_history > 0 {
+            let mut history = FieldHistory::new(layer.span_field_history);
+            for (name, value) in visitor.values() {
+                history.push(name, value.clone());
+            }
+            extensions.insert(history);
+        }
         extensions.insert(visitor);
+
+        // Root spans decide whether they are sampled, and child spans inherit the decision
+        if let Some(sampler) = &self.span_sampler {
+            let inherited = span
+                .parent()
+                .and_then(|parent| parent.extensions().get::<Sampled>().copied());
+            extensions.insert(inherited.unwrap_or_else(|| sampler.decide()));
+        }
+        drop(extensions);
+
+        if layer.has_span_event(FmtSpan::NEW) {
+            layer.write_span_event(id, &ctx, "new");
+        }
     }
 
     fn on_record(
@@ -66,128 +912,1890 @@ where
         values: &tracing_core::span::Record<'_>,
         ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        let span = ctx.span(span).expect("Span not found, this is a bug");
+        let Some(span) = ctx.span(span) else {
+            self.span_lookup_failed("Span not found on 'record', this is a bug");
+            return;
+        };
 
-        // Before you can associate a record to an existing Span, well, that Span has to be created!
-        // We can thus rely on the invariant that we always associate a JsonVisitor with a Span
-        // on creation (`new_span` method), hence it's safe to unwrap the Option.
+        // A visitor is associated with every span on creation (`new_span` method), unless the
+        // span was created before this layer was added, e.g. by a layer ordering mistake.
         let mut extensions = span.extensions_mut();
-        let visitor = extensions
-            .get_mut::<JsonStorage>()
-            .expect("Visitor not found on 'record', this is a bug");
-        // Register all new fields
-        values.record(visitor);
+        if extensions.get_mut::<JsonStorage>().is_none() {
+            self.span_lookup_failed("Visitor not found on 'record', this is a bug");
+            extensions.insert(JsonStorage::default());
+        }
+        // Register all new fields, retaining their values if span field history is enabled
+        let mut recorded = self.storage();
+        values.record(&mut recorded);
+        if let Some(history) = extensions.get_mut::<FieldHistory>() {
+            for (name, value) in recorded.values() {
+                history.push(name, value.clone());
+            }
+        }
+        let storage = extensions.get_mut::<JsonStorage>().expect("inserted above");
+        storage.values.extend(recorded.values.drain());
+        storage.captures.extend(recorded.captures.drain());
+        storage.fragment.take();
     }
 
     fn on_event(
         &self,
         event: &tracing_core::Event<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        // Record the event fields
-        let mut visitor = crate::storage::JsonStorage::default();
-        event.record(&mut visitor);
-
-        let mut root: HashMap<&str, serde_json::Value> = HashMap::new();
-
-        // level
-        root.insert(
-            self.level_name,
-            match self.level_value_casing {
-                crate::Casing::Lowercase => {
-                    json!(event.metadata().level().to_string().to_lowercase())
-                }
-                crate::Casing::Uppercase => {
-                    json!(event.metadata().level().to_string().to_uppercase())
-                }
-            },
-        );
-
-        // target
-        root.insert(self.target_name, json!(event.metadata().target()));
-
-        // timestamp
-        let timestamp = match &self.timestamp_format {
-            TimestampFormat::Unix | TimestampFormat::UnixMillis => {
-                json!(self.timestamp_format.format_number(&chrono::Utc::now()))
+        #[cfg(feature = "diagnostics")]
+        if let Some(diagnostics) = &self.diagnostics {
+            if diagnostics.event_recorded() && !self.flatten_spans {
+                self.diagnose(crate::diagnostics::Misconfiguration::UnusedSpans);
             }
-            TimestampFormat::Rfc3339 | TimestampFormat::Rfc3339Nanos => {
-                json!(self.timestamp_format.format_string(&chrono::Utc::now()))
+        }
+        self.layer_for(event.metadata().target())
+            .format_event(event, &ctx);
+    }
+
+    fn on_enter(
+        &self,
+        id: &tracing_core::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+                timings.enter();
             }
-            TimestampFormat::Custom(_) => {
-                json!(self.timestamp_format.format_string(&chrono::Utc::now()))
+            let layer = self.layer_for(span.metadata().target());
+            if layer.has_span_event(FmtSpan::ENTER) {
+                layer.write_span_event(id, &ctx, "enter");
             }
-        };
-        root.insert(self.timestamp_name, timestamp);
-
-        if self.file_names && event.metadata().file().is_some() {
-            root.insert("file", json!(event.metadata().file().expect("is some")));
         }
+    }
 
-        if self.line_numbers && event.metadata().line().is_some() {
-            root.insert("line", json!(event.metadata().line().expect("is some")));
+    fn on_exit(&self, id: &tracing_core::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timings) = span.extensions_mut().get_mut::<Timings>() {
+                timings.exit();
+            }
+            let layer = self.layer_for(span.metadata().target());
+            if layer.has_span_event(FmtSpan::EXIT) {
+                layer.write_span_event(id, &ctx, "exit");
+            }
         }
+    }
 
-        // Serialize the event fields
-        if self.flatten_fields {
-            visitor.values().iter().for_each(|(k, v)| {
-                if *k == "message" {
-                    root.insert(self.message_name, v.clone());
-                } else {
-                    root.insert(k, v.clone());
-                }
-            });
-        } else {
-            let mut fields = HashMap::new();
-            visitor.values().iter().for_each(|(k, v)| {
-                if *k == "message" {
-                    fields.insert(self.message_name, v.clone());
-                } else {
-                    fields.insert(k, v.clone());
-                }
-            });
-            root.insert("fields", json!(fields));
+    fn on_close(&self, id: tracing_core::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(record) = self.build_span_summary(&id, &ctx) {
+            self.write_record(&record, tracing_core::Level::INFO);
+        }
+        self.roll_up_into_canonical_line(&id, &ctx);
+        if let Some((record, level)) = self.build_canonical_line(&id, &ctx) {
+            self.write_record(&record, level);
+        }
+        let layer = self.layer_for(span.metadata().target());
+        if !layer.span_timings
+            && layer.span_field_history == 0
+            && !layer.has_span_event(FmtSpan::CLOSE)
+        {
+            return;
+        }
+        match layer.build_close_record(&id, &ctx) {
+            Some(record) => layer.write_record(&record, *span.metadata().level()),
+            None => layer.stats.record_drop(),
         }
+    }
+}
 
-        // Span fields (if any)
-        let mut spans = vec![];
-        if let Some(leaf_span) = _ctx.lookup_current() {
-            for span in leaf_span.scope().from_root() {
-                let mut fields = HashMap::new();
-                let ext = span.extensions();
-                let visitor = ext.get::<crate::storage::JsonStorage>();
-                if let Some(visitor) = visitor {
-                    visitor.values().iter().for_each(|(k, v)| {
-                        if *k == "message" {
-                            fields.insert(self.message_name, v.clone());
-                        } else {
-                            fields.insert(k, v.clone());
+impl JsonFormattingLayer {
+    /// Returns the configuration for the given target, taking target overrides into account.
+    /// The override with the longest matching prefix wins.
+    pub(crate) fn layer_for(&self, target: &str) -> &JsonFormattingLayer {
+        self.target_overrides
+            .iter()
+            .filter(|(prefix, _)| crate::adapters::target_matches(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, layer)| layer)
+            .unwrap_or(self)
+    }
+
+    /// Collect the recorded fields of an event or span, renaming the message field,
+    /// applying capture policies, any field adapters configured for the target, declared types
+    /// and units, and folding multi-line values.
+    fn collect_fields(
+        &self,
+        target: &str,
+        storage: &JsonStorage,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut fields = serde_json::Map::new();
+        for (k, v) in storage.values() {
+            let Some(name) = self.recorded_name(storage, k) else {
+                continue;
+            };
+            let name = name.into_owned();
+            let adapted = self
+                .field_adapters
+                .iter()
+                .try_fold((name, v.clone()), |(name, value), adapter| {
+                    adapter.adapt(target, name, value)
+                });
+            let Some((name, value)) = adapted else {
+                continue;
+            };
+            let value = match self.typed_fields.get(&name) {
+                Some(field_type) => match field_type.coerce(value.clone()) {
+                    Some(coerced) => coerced,
+                    None => {
+                        self.stats.record_type_mismatch();
+                        match self.type_mismatch {
+                            crate::TypeMismatch::Keep => value,
+                            crate::TypeMismatch::Drop => continue,
+                        }
+                    }
+                },
+                None => value,
+            };
+            let (name, value) = match self.field_units.get(&name) {
+                Some(unit) => unit.apply(self.unit_style, name, value),
+                None => (name, value),
+            };
+            match value {
+                serde_json::Value::String(text) if text.contains('\n') => {
+                    if self.exception_fields {
+                        if let Some((kind, message)) = crate::multiline::parse_exception(&text) {
+                            fields.insert("exception.type".to_string(), json!(kind));
+                            fields.insert("exception.message".to_string(), json!(message));
                         }
-                    });
+                    }
+                    fields.insert(name, self.multiline.apply(text));
                 }
-                if !fields.is_empty() {
-                    spans.push(fields);
+                value => {
+                    fields.insert(name, value);
                 }
             }
         }
+        fields
+    }
 
-        if !spans.is_empty() {
-            if self.flatten_spans {
-                spans.iter().for_each(|fields| {
-                    fields.iter().for_each(|(k, v)| {
-                        if *k == "message" {
-                            root.insert(self.message_name, v.clone());
-                        } else {
-                            root.insert(k, v.clone());
-                        }
-                    });
-                });
-            } else {
-                root.insert("spans", json!(spans));
-            }
+    /// Returns the name of a recorded field in records, renaming the message field and applying
+    /// capture policies, or None if the field is dropped.
+    fn recorded_name<'a>(&'a self, storage: &JsonStorage, name: &'a str) -> Option<Cow<'a, str>> {
+        if name == "message" {
+            return Some(Cow::Borrowed(&self.message_name));
+        }
+        match storage.captures.get(name) {
+            Some(mode) => match self.capture_policy(*mode) {
+                crate::CapturePolicy::Keep => Some(Cow::Borrowed(name)),
+                crate::CapturePolicy::Prefix => {
+                    Some(Cow::Owned(format!("{}{}", mode.prefix(), name)))
+                }
+                crate::CapturePolicy::Drop => None,
+            },
+            None => Some(Cow::Borrowed(name)),
+        }
+    }
+
+    /// Serialize the recorded fields of a span as `collect_fields` would collect them, without
+    /// cloning their values, if the fields of events are serialized as they are visited.
+    fn span_fragment(&self, target: &str, storage: &JsonStorage) -> SpanFragment {
+        if !self.streams_event_fields() {
+            return SpanFragment::new(self.collect_fields(target, storage));
+        }
+        let mut members = Members::new();
+        let mut encoder = FieldEncoder {
+            layer: self,
+            members: &mut members,
+        };
+        for (k, v) in storage.values() {
+            if let Some(name) = self.recorded_name(storage, k) {
+                encoder.push_ref(&name, v);
+            }
+        }
+        SpanFragment::from_members(members)
+    }
+
+    fn format_event<S>(
+        &self,
+        event: &tracing_core::Event<'_>,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let level = *event.metadata().level();
+        if let Some(guard) = &self.disk_space {
+            if let Some(available) = guard.check(&self.output) {
+                if let Some(low) = guard.update(available) {
+                    self.write_disk_space_notice(guard, low, available);
+                }
+            }
+            if guard.is_low() && level > guard.level() {
+                self.record_dropped_event(event.metadata());
+                return;
+            }
+        }
+        if let Some(coalescer) = &self.coalescer {
+            for (record, level) in coalescer.take_due(false) {
+                self.write_record(&record, level);
+            }
+        }
+        if self.absorb_into_canonical_line(event, ctx) {
+            return;
+        }
+        if self.streams_event_fields() {
+            let written = self.with_streamed_event(event, ctx, |line| {
+                self.write_event_line(event.metadata(), line, level)
+            });
+            if written.is_none() {
+                self.record_dropped_event(event.metadata());
+            }
+            return;
+        }
+        let splice_spans = self.splices_span_fields();
+        let record = self.build_event_record(event, ctx, splice_spans);
+        // Overflows are detected while building the record
+        if let Some(quota) = &self.field_quota {
+            for (value, dropped) in quota.take_overflows() {
+                self.write_record(
+                    &self.quota_record(quota.field(), value, dropped),
+                    tracing_core::Level::WARN,
+                );
+            }
+        }
+        let Some(record) = record else {
+            self.record_dropped_event(event.metadata());
+            return;
+        };
+        self.stats.record_target(event.metadata().target(), level);
+        let record = match &self.coalescer {
+            Some(coalescer) if coalescer.matches(event.metadata()) => {
+                match coalescer.offer(event.metadata(), record, level, &self.stats) {
+                    Some(record) => record,
+                    None => return,
+                }
+            }
+            _ => record,
+        };
+        if !self.forwarders.is_empty() {
+            let spans = if self
+                .forwarders
+                .iter()
+                .any(|forwarder| forwarder.needs_spans())
+            {
+                self.span_context(ctx)
+            } else {
+                Vec::new()
+            };
+            for forwarder in &self.forwarders {
+                forwarder.forward(event.metadata(), &self.field_names(), &record, &spans);
+            }
+        }
+        let fragments = if splice_spans {
+            self.span_fragments_of(ctx)
+        } else {
+            Vec::new()
+        };
+        if self
+            .with_encoded_spliced(&record, &fragments, |line| {
+                self.write_event_line(event.metadata(), line, level)
+            })
+            .is_none()
+        {
+            self.record_dropped_event(event.metadata());
+        }
+    }
+
+    /// Count an event that was not written.
+    fn record_dropped_event(&self, metadata: &'static tracing_core::Metadata<'static>) {
+        self.stats.record_drop();
+        if self.stats.tracks_callsites() {
+            self.stats.record_callsite(metadata, None);
+        }
+    }
+
+    /// Write the encoded and framed record of an event, retaining it in the flight recorder if
+    /// enabled.
+    fn write_event_line(
+        &self,
+        metadata: &'static tracing_core::Metadata<'static>,
+        line: &[u8],
+        level: tracing_core::Level,
+    ) {
+        if self.stats.tracks_callsites() {
+            self.stats.record_callsite(metadata, Some(line.len()));
+        }
+        if let Some(report) = &self.size_report {
+            if report.due() {
+                self.write_record(&report.record(self), tracing_core::Level::INFO);
+            }
+        }
+        let Some(recorder) = &self.flight_recorder else {
+            self.write_line(line, level);
+            return;
+        };
+
+        if level >= tracing_core::Level::DEBUG {
+            if recorder.push(level, line.to_vec()) {
+                self.stats.record_drop();
+            }
+            return;
+        }
+        if level == tracing_core::Level::ERROR {
+            let retained = recorder.drain();
+            self.stats.record_expired(recorder.take_expired());
+            if !retained.is_empty() {
+                let mut marker = self.notice_record(
+                    tracing_core::Level::INFO,
+                    "tracing_ndjson::flight_recorder",
+                    "flight recorder dump",
+                );
+                marker.insert("records".to_string(), json!(retained.len()));
+                self.write_record(&marker, tracing_core::Level::INFO);
+                for (level, line) in retained {
+                    self.write_line(&line, level);
+                }
+            }
+        }
+        self.write_line(line, level);
+    }
+
+    /// Write a notice that the minimum level was raised because free disk space is low, or
+    /// restored because it recovered.
+    fn write_disk_space_notice(&self, guard: &DiskSpaceGuard, low: bool, available: u64) {
+        let (level, message) = if low {
+            (
+                tracing_core::Level::WARN,
+                format!(
+                    "low disk space, only writing {} and more severe records",
+                    guard.level()
+                ),
+            )
+        } else {
+            (
+                tracing_core::Level::INFO,
+                "disk space recovered, writing all records".to_string(),
+            )
+        };
+        let mut notice = self.notice_record(level, "tracing_ndjson::disk_space", &message);
+        notice.insert("disk.available_bytes".to_string(), json!(available));
+        notice.insert("disk.min_free_bytes".to_string(), json!(guard.min_free()));
+        self.write_record(&notice, level);
+    }
+
+    /// Write a warning that a misconfiguration was found, if diagnostics are enabled and it was
+    /// not reported before.
+    #[cfg(feature = "diagnostics")]
+    fn diagnose(&self, kind: crate::diagnostics::Misconfiguration) {
+        if !self
+            .diagnostics
+            .as_ref()
+            .is_some_and(|diagnostics| diagnostics.report(kind))
+        {
+            return;
+        }
+        let level = tracing_core::Level::WARN;
+        let mut notice = self.notice_record(level, "tracing_ndjson::diagnostics", kind.message());
+        notice.insert("diagnostic".to_string(), json!(kind.code()));
+        self.write_record(&notice, level);
+    }
+
+    /// Write an ERROR record with target `tracing_ndjson::internal` for an error the layer ran
+    /// into, if internal errors are written as records and the kind of error was not reported
+    /// within the interval. Errors writing this record are not reported again.
+    fn internal_error(&self, kind: InternalError, error: &str) {
+        let Some(internal_errors) = &self.internal_errors else {
+            return;
+        };
+        if REPORTING_INTERNAL_ERROR.try_with(Cell::get).unwrap_or(true) {
+            return;
+        }
+        let Some(suppressed) = internal_errors.report(kind) else {
+            return;
+        };
+        let level = tracing_core::Level::ERROR;
+        let mut record = self.notice_record(level, "tracing_ndjson::internal", kind.message());
+        record.insert("error.kind".to_string(), json!(kind.code()));
+        record.insert("error.message".to_string(), json!(error));
+        record.insert("error.suppressed".to_string(), json!(suppressed));
+        REPORTING_INTERNAL_ERROR.set(true);
+        self.write_record(&record, level);
+        REPORTING_INTERNAL_ERROR.set(false);
+    }
+
+    /// Build the record written when records with a value of the quota field were dropped.
+    fn quota_record(
+        &self,
+        field: &str,
+        value: String,
+        dropped: u64,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut record = self.notice_record(
+            tracing_core::Level::WARN,
+            "tracing_ndjson::quota",
+            "quota exceeded",
+        );
+        record.insert(field.to_string(), json!(value));
+        record.insert("dropped".to_string(), json!(dropped));
+        record
+    }
+
+    /// Build a record written by the layer itself, e.g. a notice or a summary, with the global
+    /// fields, its level, target, message and the current time.
+    pub(crate) fn notice_record(
+        &self,
+        level: tracing_core::Level,
+        target: &str,
+        message: &str,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut record = self.global_fields.clone();
+        record.insert(
+            self.level_name.to_string(),
+            self.level_value(level.as_str()),
+        );
+        record.insert(self.target_name.to_string(), json!(target));
+        record.insert(self.timestamp_name.to_string(), self.timestamp_value());
+        record.insert(self.message_name.to_string(), json!(message));
+        record
+    }
+
+    fn field_names(&self) -> FieldNames<'_> {
+        FieldNames {
+            level: &self.level_name,
+            message: &self.message_name,
+            target: &self.target_name,
+            timestamp: &self.timestamp_name,
+        }
+    }
+
+    /// Returns the spans enclosing the current event with their fields, from the outermost
+    /// to the innermost span.
+    fn span_context<S>(&self, ctx: &tracing_subscriber::layer::Context<'_, S>) -> Vec<SpanContext>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(leaf_span) = ctx.lookup_current() else {
+            return Vec::new();
+        };
+        leaf_span
+            .scope()
+            .from_root()
+            .map(|span| {
+                let fields = self.span_storage_fields(&span).unwrap_or_default();
+                SpanContext {
+                    name: span.name(),
+                    target: span.metadata().target().to_string(),
+                    fields,
+                }
+            })
+            .collect()
+    }
+
+    /// Encode and frame a record, or None if the encoder fails.
+    pub(crate) fn encode_record(
+        &self,
+        record: &serde_json::Map<String, serde_json::Value>,
+    ) -> Option<Vec<u8>> {
+        self.with_encoded(record, <[u8]>::to_vec)
+    }
+
+    /// Encode and frame a record into the buffers reused by the current thread, calling `f` with
+    /// the framed record, or return None if it could not be encoded.
+    fn with_encoded<R>(
+        &self,
+        record: &serde_json::Map<String, serde_json::Value>,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R> {
+        self.with_encoded_spliced(record, &[], f)
+    }
+
+    /// Encode and frame a record with the serialized fields of its spans spliced into it, as
+    /// `with_encoded`.
+    fn with_encoded_spliced<R>(
+        &self,
+        record: &serde_json::Map<String, serde_json::Value>,
+        fragments: &[Arc<SpanFragment>],
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R> {
+        self.with_buffers(
+            |buf| {
+                if fragments.is_empty() {
+                    self.encoder.encode(&self.field_names(), record, buf)
+                } else {
+                    crate::fragment::encode_spliced(record, fragments, buf)
+                }
+            },
+            f,
+        )
+    }
+
+    /// Encode a record with `encode` and frame it into the buffers reused by the current thread,
+    /// calling `f` with the framed record, or return None if it could not be encoded.
+    fn with_buffers<R>(
+        &self,
+        encode: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R> {
+        // Records written while another record is written, e.g. warnings, find the buffers taken
+        // and use their own
+        let (mut buf, mut line) = ENCODE_BUFFERS.try_with(Cell::take).unwrap_or_default();
+        buf.clear();
+        line.clear();
+        let result = match encode(&mut buf) {
+            Ok(()) => {
+                self.framing.frame(&buf, &mut line);
+                Some(f(&line))
+            }
+            Err(e) => {
+                self.internal_error(InternalError::Serialization, &e.to_string());
+                None
+            }
+        };
+        // A single large record shouldn't retain its memory for the lifetime of the thread
+        if buf.capacity() <= MAX_RETAINED_BUFFER && line.capacity() <= MAX_RETAINED_BUFFER {
+            let _ = ENCODE_BUFFERS.try_with(|buffers| buffers.set((buf, line)));
+        }
+        result
+    }
+
+    /// Write the records retained by coalescing and wait up to `timeout` for the records queued
+    /// for non-blocking output to be written.
+    #[cfg(all(feature = "signals", unix))]
+    pub(crate) fn flush(&self, timeout: std::time::Duration) {
+        if let Some(coalescer) = &self.coalescer {
+            for (record, level) in coalescer.take_due(true) {
+                self.write_record(&record, level);
+            }
+        }
+        if let Some(non_blocking) = &self.non_blocking {
+            non_blocking.queue().flush(timeout);
+        }
+    }
+
+    /// Encode, frame and write a record emitted at the given level.
+    pub(crate) fn write_record(
+        &self,
+        record: &serde_json::Map<String, serde_json::Value>,
+        level: tracing_core::Level,
+    ) {
+        self.with_encoded(record, |line| self.write_line(line, level));
+    }
+
+    /// Write an encoded and framed record emitted at the given level.
+    fn write_line(&self, line: &[u8], level: tracing_core::Level) {
+        if crate::test::write(line) {
+            self.stats.record_event(level, line.len());
+            return;
+        }
+        #[cfg(all(feature = "signals", unix))]
+        if let Some(ring) = &self.crash_ring {
+            ring.push(line);
+        }
+        let Some(non_blocking) = &self.non_blocking else {
+            let started = self
+                .write_watchdog
+                .as_ref()
+                .map(|_| std::time::Instant::now());
+            match self.output.write(line, level) {
+                Ok(()) => self.stats.record_event(level, line.len()),
+                Err(e) => {
+                    self.stats.record_write_error();
+                    self.internal_error(InternalError::Write, &e.to_string());
+                }
+            }
+            if let (Some(watchdog), Some(started)) = (&self.write_watchdog, started) {
+                let elapsed = started.elapsed();
+                if elapsed > watchdog.threshold() {
+                    self.stats.record_slow_write(elapsed);
+                }
+                if watchdog.warn(elapsed) {
+                    self.write_slow_write_warning(elapsed, watchdog.threshold());
+                }
+            }
+            return;
+        };
+
+        let queued = non_blocking.queue().push(level, line.to_vec());
+        self.stats.record_queue_depths(queued.depths);
+        if queued.queued {
+            self.stats.record_event(level, line.len());
+        } else {
+            self.stats.record_drop();
+        }
+        if let Some((dropped, duration)) = queued.recovered {
+            let mut notice = self.notice_record(
+                tracing_core::Level::WARN,
+                "tracing_ndjson::non_blocking",
+                "output degraded to WARN and ERROR records",
+            );
+            notice.insert("degraded.dropped".to_string(), json!(dropped));
+            notice.insert(
+                "degraded.duration_ms".to_string(),
+                json!(duration.as_millis() as u64),
+            );
+            if let Some(line) = self.encode_record(&notice) {
+                if non_blocking
+                    .queue()
+                    .push(tracing_core::Level::WARN, line.clone())
+                    .queued
+                {
+                    self.stats
+                        .record_event(tracing_core::Level::WARN, line.len());
+                }
+            }
+        }
+    }
+
+    /// Write a warning that a write blocked for longer than the watchdog threshold. The warning
+    /// itself is not timed.
+    fn write_slow_write_warning(
+        &self,
+        elapsed: std::time::Duration,
+        threshold: std::time::Duration,
+    ) {
+        let mut warning = self.notice_record(
+            tracing_core::Level::WARN,
+            "tracing_ndjson::watchdog",
+            "slow write",
+        );
+        warning.insert("write_ms".to_string(), json!(elapsed.as_secs_f64() * 1e3));
+        warning.insert(
+            "threshold_ms".to_string(),
+            json!(threshold.as_secs_f64() * 1e3),
+        );
+        warning.insert("slow_writes".to_string(), json!(self.stats.slow_writes()));
+        if let Some(line) = self.encode_record(&warning) {
+            match self.output.write(&line, tracing_core::Level::WARN) {
+                Ok(()) => self
+                    .stats
+                    .record_event(tracing_core::Level::WARN, line.len()),
+                Err(e) => {
+                    self.stats.record_write_error();
+                    self.internal_error(InternalError::Write, &e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Shorten a target as configured. An alias for the longest matching target prefix replaces
+    /// that prefix, otherwise the first matching strip prefix is removed and only the configured
+    /// number of trailing path segments are kept.
+    pub(crate) fn shorten_target<'a>(&self, target: &'a str) -> Cow<'a, str> {
+        let alias = self
+            .target_aliases
+            .iter()
+            .filter(|(prefix, _)| crate::adapters::target_matches(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len());
+        if let Some((prefix, alias)) = alias {
+            return Cow::Owned(format!("{}{}", alias, &target[prefix.len()..]));
+        }
+
+        let mut target = self
+            .target_strip_prefixes
+            .iter()
+            .find_map(|prefix| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .filter(|t| !t.is_empty())
+            })
+            .unwrap_or(target);
+        if let Some(segments) = self.target_segments {
+            let mut start = target.len();
+            for _ in 0..segments.max(1) {
+                match target[..start].rfind("::") {
+                    Some(i) => start = i,
+                    None => {
+                        start = 0;
+                        break;
+                    }
+                }
+            }
+            if start > 0 {
+                target = &target[start + 2..];
+            }
+        }
+        Cow::Borrowed(target)
+    }
+
+    /// Returns true if the target matches one of the suppressed target patterns.
+    fn is_suppressed(&self, target: &str) -> bool {
+        self.suppressed_targets
+            .iter()
+            .any(|pattern| pattern_matches(pattern, target))
+    }
+
+    /// Qualify a trace ID with the configured project, as `projects/<project>/traces/<trace ID>`.
+    /// IDs that are already qualified, or without a project, are returned unchanged.
+    fn gcp_trace(&self, trace: serde_json::Value) -> serde_json::Value {
+        match (&self.gcp_project, trace) {
+            (Some(project), serde_json::Value::String(id)) if !id.starts_with("projects/") => {
+                json!(format!("projects/{}/traces/{}", project, id))
+            }
+            (_, trace) => trace,
+        }
+    }
+
+    /// Render a level using the configured casing, or as a Google Cloud Logging severity in the
+    /// GCP format.
+    pub(crate) fn level_value(&self, level: &str) -> serde_json::Value {
+        if self.gcp_format {
+            return match level {
+                "TRACE" | "DEBUG" => json!("DEBUG"),
+                "WARN" => json!("WARNING"),
+                _ => json!(level.to_uppercase()),
+            };
+        }
+        match &self.level_value_casing {
+            crate::Casing::Lowercase => json!(level.to_lowercase()),
+            crate::Casing::Uppercase => json!(level.to_uppercase()),
+            crate::Casing::Capitalized => {
+                let level = level.to_lowercase();
+                let mut chars = level.chars();
+                match chars.next() {
+                    Some(first) => json!(first.to_uppercase().chain(chars).collect::<String>()),
+                    None => json!(level),
+                }
+            }
+            crate::Casing::Custom(values) => {
+                match level
+                    .parse::<tracing_core::Level>()
+                    .ok()
+                    .and_then(|level| values.get(&level))
+                {
+                    Some(value) => json!(value),
+                    None => json!(level.to_lowercase()),
+                }
+            }
+        }
+    }
+
+    /// Render the current time using the configured clock and timestamp format.
+    pub(crate) fn timestamp_value(&self) -> serde_json::Value {
+        self.timestamp_value_at(self.clock.now())
+    }
+
+    /// Render a time in nanoseconds since UNIX_EPOCH using the configured timestamp format.
+    fn timestamp_value_at(&self, nanos: i64) -> serde_json::Value {
+        match &self.timestamp_format {
+            TimestampFormat::Unix | TimestampFormat::UnixMillis => {
+                json!(self.timestamp_format.format_number(nanos))
+            }
+            _ => json!(self.timestamp_format.format_string(nanos)),
+        }
+    }
+
+    /// Returns whether an event may be written: events of suppressed targets are not, and events
+    /// in spans only when their span is sampled.
+    fn samples_event<S>(
+        &self,
+        event: &tracing_core::Event<'_>,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if self.is_suppressed(event.metadata().target()) {
+            return false;
+        }
+
+        // Events outside of spans are not sampled
+        if let Some(sampler) = &self.span_sampler {
+            let sampled = ctx
+                .lookup_current()
+                .and_then(|span| span.extensions().get::<Sampled>().copied());
+            if let Some(sampled) = sampled {
+                return sampler.allow(sampled, *event.metadata().level());
+            }
+        }
+        true
+    }
+
+    /// Encode the record of an event with its fields serialized as they are visited and the
+    /// fields of its spans spliced into it, calling `f` with the framed record, or return None
+    /// if the event is not written or its record could not be encoded.
+    fn with_streamed_event<S, R>(
+        &self,
+        event: &tracing_core::Event<'_>,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if !self.samples_event(event, ctx) {
+            return None;
+        }
+        let metadata = event.metadata();
+        let mut members = EVENT_MEMBERS.try_with(Cell::take).unwrap_or_default();
+        members.clear();
+        let span_names = match ctx.lookup_current() {
+            Some(leaf_span) => self.span_names(&leaf_span),
+            None => Vec::new(),
+        };
+        self.insert_builtins(
+            metadata,
+            metadata.level().as_str(),
+            span_names,
+            |name, value| members.push(name, value),
+        );
+        event.record(&mut FieldEncoder {
+            layer: self,
+            members: &mut members,
+        });
+        #[cfg(feature = "opentelemetry")]
+        if let (Some(otel), Some(span)) = (&self.otel, ctx.lookup_current()) {
+            otel.insert(&span, |name, value| members.push(name, &value));
+        }
+        self.stats
+            .record_target(metadata.target(), *metadata.level());
+        let fragments = self.span_fragments_of(ctx);
+        let result = self.with_buffers(|buf| members.encode_spliced(&fragments, buf), f);
+        if members.capacity() <= MAX_RETAINED_BUFFER {
+            let _ = EVENT_MEMBERS.try_with(|cell| cell.set(members));
+        }
+        result
+    }
+
+    /// Build the record of an event, without the fields of its spans if they are spliced into
+    /// the encoded record instead.
+    fn build_event_record<S>(
+        &self,
+        event: &tracing_core::Event<'_>,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+        splice_spans: bool,
+    ) -> Option<serde_json::Map<String, serde_json::Value>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if !self.samples_event(event, ctx) {
+            return None;
+        }
+
+        // Record the event fields
+        let mut visitor = self.storage();
+        event.record(&mut visitor);
+        let mut fields = self.collect_fields(event.metadata().target(), &visitor);
+        let level_override = self
+            .level_override_field
+            .as_ref()
+            .and_then(|name| fields.remove(name.as_ref()));
+        let tier = self.verbosity_field.as_ref().and_then(|(name, max_tier)| {
+            let tier = fields.remove(name.as_ref())?.as_u64()?;
+            Some(tier.min(u64::from(*max_tier)))
+        });
+
+        // Span fields (if any)
+        let (mut spans, span_scopes, span_names) = match ctx.lookup_current() {
+            Some(leaf_span) if splice_spans => {
+                (Vec::new(), Vec::new(), self.span_names(&leaf_span))
+            }
+            Some(leaf_span) => {
+                let (spans, span_scopes) = self.span_fields(&leaf_span);
+                (spans, span_scopes, self.span_names(&leaf_span))
+            }
+            None => Default::default(),
+        };
+
+        if let Some(mode) = self.pii_mode {
+            for fields in std::iter::once(&mut fields).chain(spans.iter_mut()) {
+                crate::pii::scan(mode, event.metadata(), fields, &self.stats);
+            }
+        }
+
+        // The trace and span IDs are looked up before the fields are moved into the record
+        let gcp_ids = self.gcp_format.then(|| {
+            let find = |name| {
+                std::iter::once(&fields)
+                    .chain(spans.iter().rev())
+                    .find_map(|fields: &serde_json::Map<_, _>| fields.get(name).cloned())
+            };
+            (
+                find(crate::fields::trace::ID),
+                find(crate::fields::trace::SPAN_ID),
+            )
+        });
+
+        // The sampled flag of the innermost trace context replaces the `trace.sampled` fields
+        let sampled = if self.sampled_field {
+            let mut sampled = None;
+            for fields in std::iter::once(&mut fields).chain(spans.iter_mut().rev()) {
+                if let Some(serde_json::Value::Bool(value)) =
+                    fields.remove(crate::fields::trace::SAMPLED)
+                {
+                    sampled = sampled.or(Some(value));
+                }
+            }
+            sampled
+        } else {
+            None
+        };
+
+        // The OpenTelemetry context of the current span takes precedence over the trace fields
+        #[cfg(feature = "opentelemetry")]
+        let otel_context = (self.gcp_format || self.sampled_field)
+            .then(|| {
+                self.otel
+                    .as_ref()
+                    .zip(ctx.lookup_current())
+                    .and_then(|(otel, span)| otel.context(&span))
+            })
+            .flatten();
+        #[cfg(feature = "opentelemetry")]
+        let sampled = match &otel_context {
+            Some(span_context) if self.sampled_field => Some(span_context.is_sampled()),
+            _ => sampled,
+        };
+
+        let level = match (level_override, tier) {
+            (Some(serde_json::Value::String(level)), _) => level,
+            (_, Some(tier)) if tier > 0 => format!("{}{}", event.metadata().level(), tier),
+            _ => event.metadata().level().to_string(),
+        };
+        let mut record = self.assemble(
+            event.metadata(),
+            level,
+            fields,
+            spans,
+            span_scopes,
+            span_names,
+        )?;
+        if let Some((trace, span_id)) = gcp_ids {
+            #[cfg(feature = "opentelemetry")]
+            let (trace, span_id) = match &otel_context {
+                Some(span_context) => (
+                    Some(json!(span_context.trace_id().to_string())),
+                    Some(json!(span_context.span_id().to_string())),
+                ),
+                None => (trace, span_id),
+            };
+            if let Some(span_id) = span_id {
+                record.insert("logging.googleapis.com/spanId".to_string(), span_id);
+            }
+            if let Some(trace) = trace {
+                record.insert(
+                    "logging.googleapis.com/trace".to_string(),
+                    self.gcp_trace(trace),
+                );
+            }
+        }
+        if let Some(sampled) = sampled {
+            record.insert("sampled".to_string(), json!(sampled));
+        }
+        #[cfg(feature = "opentelemetry")]
+        if let (Some(otel), Some(span)) = (&self.otel, ctx.lookup_current()) {
+            otel.insert(&span, |name, value| {
+                record.insert(name.to_string(), value);
+            });
+        }
+        Some(record)
+    }
+
+    /// Returns whether an option reads or rewrites the fields of each record, so they must be
+    /// collected into a map before the record is encoded.
+    pub(crate) fn reads_record_fields(&self) -> bool {
+        !self.target_overrides.is_empty()
+            || !self.forwarders.is_empty()
+            || self.coalescer.is_some()
+            || self.event_filter.is_some()
+            || self.verbosity_boost.is_some()
+            || self.field_quota.is_some()
+            || self.allowed_fields.is_some()
+            || self.value_dedup.is_some()
+            || self.pii_mode.is_some()
+            || self.provenance.is_some()
+            || self.collision_policy != crate::CollisionPolicy::Overwrite
+            || self.level_number_name.is_some()
+            || self.gcp_format
+            || self.sampled_field
+            || !self.field_adapters.is_empty()
+            || !self.typed_fields.is_empty()
+            || !self.field_units.is_empty()
+            || self.level_override_field.is_some()
+            || self.verbosity_field.is_some()
+            || self.fingerprint
+    }
+
+    /// Returns whether the fields of spans are serialized once per span and spliced into encoded
+    /// records, which is only possible when records are NDJSON and no option needs the fields
+    /// of each record in a map.
+    fn splices_span_fields(&self) -> bool {
+        self.span_fragments && self.ndjson_encoder && self.flatten_spans && !self.needs_map
+    }
+
+    /// Returns whether the fields of events are serialized as they are visited rather than
+    /// collected into a map first, which is only possible when the fields of spans are spliced
+    /// and event fields are flattened.
+    fn streams_event_fields(&self) -> bool {
+        self.splices_span_fields() && self.flatten_fields
+    }
+
+    /// Returns the serialized fields of the current span and its parents, from the outermost to
+    /// the innermost span, serializing and caching the fields of spans not serialized since
+    /// their last recorded field.
+    fn span_fragments_of<S>(
+        &self,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> Vec<Arc<SpanFragment>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(leaf_span) = ctx.lookup_current() else {
+            return Vec::new();
+        };
+        leaf_span
+            .scope()
+            .from_root()
+            .filter_map(|span| {
+                let ext = span.extensions();
+                match ext.get::<JsonStorage>() {
+                    Some(storage) => Some(
+                        storage
+                            .fragment
+                            .get_or_init(|| {
+                                Arc::new(self.span_fragment(span.metadata().target(), storage))
+                            })
+                            .clone(),
+                    ),
+                    None => {
+                        drop(ext);
+                        let fields = self.span_storage_fields(&span)?;
+                        Some(Arc::new(SpanFragment::new(fields)))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Collect the fields of a span and its parents, from the outermost to the innermost span,
+    /// with the depth and name of each span. Spans without fields are skipped.
+    fn span_fields<S>(
+        &self,
+        leaf_span: &tracing_subscriber::registry::SpanRef<'_, S>,
+    ) -> (
+        Vec<serde_json::Map<String, serde_json::Value>>,
+        Vec<SpanScope>,
+    )
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut spans = vec![];
+        let mut scopes = vec![];
+        for (depth, span) in leaf_span.scope().from_root().enumerate() {
+            if let Some(fields) = self.span_storage_fields(&span) {
+                if !fields.is_empty() {
+                    spans.push(fields);
+                    scopes.push((depth, span.name()));
+                }
+            }
+        }
+        (spans, scopes)
+    }
+
+    /// Returns the names of a span and its parents, from the outermost to the innermost span,
+    /// if the current span or span list is included.
+    fn span_names<S>(
+        &self,
+        leaf_span: &tracing_subscriber::registry::SpanRef<'_, S>,
+    ) -> Vec<&'static str>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if !self.current_span && !self.span_list {
+            return Vec::new();
+        }
+        leaf_span
+            .scope()
+            .from_root()
+            .map(|span| span.name())
+            .collect()
+    }
+
+    /// Collect the stored fields of a span, falling back to the fields formatted by a
+    /// `tracing_subscriber::fmt` layer if enabled, or None if the span has no stored fields.
+    fn span_storage_fields<S>(
+        &self,
+        span: &tracing_subscriber::registry::SpanRef<'_, S>,
+    ) -> Option<serde_json::Map<String, serde_json::Value>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let ext = span.extensions();
+        if let Some(visitor) = ext.get::<JsonStorage>() {
+            return Some(self.collect_fields(span.metadata().target(), visitor));
+        }
+        if !self.formatted_fields_fallback {
+            return None;
+        }
+        // Formatted names are mapped back to the static names of the span's fields
+        let formatted = crate::interop::formatted_fields(&ext)?;
+        let mut storage = JsonStorage::default();
+        for (name, value) in formatted {
+            let name = if name == "message" {
+                "message"
+            } else {
+                match span.metadata().fields().iter().find_map(|field| {
+                    let field = field.name();
+                    let field = field.strip_prefix("r#").unwrap_or(field);
+                    (field == name).then_some(field)
+                }) {
+                    Some(field) => field,
+                    None => continue,
+                }
+            };
+            storage.values.insert(name, value);
+        }
+        Some(self.collect_fields(span.metadata().target(), &storage))
+    }
+
+    /// Merge the fields of an event into the canonical log line of its innermost canonical span,
+    /// returning whether it was merged.
+    fn absorb_into_canonical_line<S>(
+        &self,
+        event: &tracing_core::Event<'_>,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some((name, conflict)) = &self.canonical_span else {
+            return false;
+        };
+        let Some(span) = ctx
+            .lookup_current()
+            .and_then(|leaf| leaf.scope().find(|span| span.name() == name))
+        else {
+            return false;
+        };
+        if self.is_suppressed(event.metadata().target()) {
+            return false;
+        }
+        let mut visitor = self.storage();
+        event.record(&mut visitor);
+        let fields = self.collect_fields(event.metadata().target(), &visitor);
+        let mut extensions = span.extensions_mut();
+        let Some(line) = extensions.get_mut::<CanonicalLine>() else {
+            return false;
+        };
+        line.absorb(fields, *event.metadata().level(), *conflict);
+        true
+    }
+
+    /// Returns whether spans with the name are aggregated by a canonical rollup.
+    fn rollup_matches(&self, name: &str) -> bool {
+        self.canonical_span.is_some()
+            && self
+                .canonical_rollups
+                .iter()
+                .any(|rollup| pattern_matches(&rollup.pattern, name))
+    }
+
+    /// Add a closing span to the rollups of the canonical log line of its innermost enclosing
+    /// canonical span.
+    pub(crate) fn roll_up_into_canonical_line<S>(
+        &self,
+        id: &tracing_core::span::Id,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some((name, _)) = &self.canonical_span else {
+            return;
+        };
+        let Some(span) = ctx.span(id).filter(|span| self.rollup_matches(span.name())) else {
+            return;
+        };
+        let Some(SpanStarted(started)) = span.extensions().get::<SpanStarted>().copied() else {
+            return;
+        };
+        let Some(parent) = span.scope().skip(1).find(|parent| parent.name() == name) else {
+            return;
+        };
+        let duration_ms = started.elapsed().as_secs_f64() * 1e3;
+        let mut extensions = parent.extensions_mut();
+        let Some(line) = extensions.get_mut::<CanonicalLine>() else {
+            return;
+        };
+        for rollup in &self.canonical_rollups {
+            if pattern_matches(&rollup.pattern, span.name()) {
+                line.roll_up(rollup, duration_ms);
+            }
+        }
+    }
+
+    /// Build the canonical log line of a closing canonical span, with its level.
+    pub(crate) fn build_canonical_line<S>(
+        &self,
+        id: &tracing_core::span::Id,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> Option<(
+        serde_json::Map<String, serde_json::Value>,
+        tracing_core::Level,
+    )>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let (name, _) = self.canonical_span.as_ref()?;
+        let span = ctx.span(id).filter(|span| span.name() == name)?;
+        let line = span.extensions_mut().remove::<CanonicalLine>()?;
+        let (mut fields, level, events) = line.finish(&self.canonical_rollups);
+        fields
+            .entry(self.message_name.to_string())
+            .or_insert_with(|| json!(span.name()));
+        fields.insert("canonical.events".to_string(), json!(events));
+        let (spans, span_scopes) = self.span_fields(&span);
+        let record = self.assemble(
+            span.metadata(),
+            level.to_string(),
+            fields,
+            spans,
+            span_scopes,
+            self.span_names(&span),
+        )?;
+        Some((record, level))
+    }
+
+    /// Count the duration of a closing span with a summary, returning the summary record if its
+    /// interval has elapsed.
+    pub(crate) fn build_span_summary<S>(
+        &self,
+        id: &tracing_core::span::Id,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> Option<serde_json::Map<String, serde_json::Value>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let summary = self.span_summaries.get(ctx.metadata(id)?.name())?;
+        let span = ctx.span(id)?;
+        let SpanStarted(started) = span.extensions().get::<SpanStarted>().copied()?;
+        summary.record(self, span.name(), started.elapsed())
+    }
+
+    /// Build the record written when a span with timings closes. The record contains the fields
+    /// of the span and its parents, the span name, the number of polls, and the busy and idle time.
+    pub(crate) fn build_close_record<S>(
+        &self,
+        id: &tracing_core::span::Id,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> Option<serde_json::Map<String, serde_json::Value>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let span = ctx.span(id)?;
+        if self.is_suppressed(span.metadata().target()) {
+            return None;
+        }
+        let extensions = span.extensions();
+        let timings = extensions.get::<Timings>();
+        let history = extensions.get::<FieldHistory>();
+        if timings.is_none() && history.is_none() {
+            return None;
+        }
+
+        let mut fields = serde_json::Map::new();
+        fields.insert(self.message_name.to_string(), json!("close"));
+        fields.insert("span.name".to_string(), json!(span.name()));
+        if let Some(timings) = timings {
+            let (polls, busy, idle) = timings.close();
+            fields.insert("polls".to_string(), json!(polls));
+            fields.insert("busy_ns".to_string(), json!(busy.as_nanos() as u64));
+            fields.insert("idle_ns".to_string(), json!(idle.as_nanos() as u64));
+        }
+        if let Some(history) = history {
+            for (name, values) in history.changed() {
+                fields.insert(format!("{}.history", name), json!(values));
+            }
+        }
+        drop(extensions);
+
+        let (spans, span_scopes) = self.span_fields(&span);
+        self.assemble(
+            span.metadata(),
+            span.metadata().level().to_string(),
+            fields,
+            spans,
+            span_scopes,
+            self.span_names(&span),
+        )
+    }
+
+    /// Returns whether records are written at a point in the lifecycle of spans.
+    fn has_span_event(&self, kind: FmtSpan) -> bool {
+        self.span_events.clone() & kind.clone() == kind
+    }
+
+    /// Write a record for a point in the lifecycle of a span, e.g. `new`, with the span name and
+    /// the fields of the span and its parents.
+    fn write_span_event<S>(
+        &self,
+        id: &tracing_core::span::Id,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+        message: &str,
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if self.is_suppressed(span.metadata().target()) {
+            return;
+        }
+        let mut fields = serde_json::Map::new();
+        fields.insert(self.message_name.to_string(), json!(message));
+        fields.insert("span.name".to_string(), json!(span.name()));
+        let (spans, span_scopes) = self.span_fields(&span);
+        let level = *span.metadata().level();
+        match self.assemble(
+            span.metadata(),
+            level.to_string(),
+            fields,
+            spans,
+            span_scopes,
+            self.span_names(&span),
+        ) {
+            Some(record) => self.write_record(&record, level),
+            None => self.stats.record_drop(),
+        }
+    }
+
+    /// Merge flattened event or span fields into a record, applying the collision policy to
+    /// fields colliding with built-in fields, and recording the origin of each merged field if
+    /// provenance is annotated.
+    fn merge_fields(
+        &self,
+        root: &mut serde_json::Map<String, serde_json::Value>,
+        origins: &mut std::collections::HashMap<String, String>,
+        builtins: &std::collections::HashSet<String>,
+        fields: serde_json::Map<String, serde_json::Value>,
+        origin: &str,
+    ) {
+        for (name, value) in fields {
+            let name = if builtins.contains(&name) {
+                match &self.collision_policy {
+                    crate::CollisionPolicy::Overwrite => name,
+                    crate::CollisionPolicy::PreferBuiltin => {
+                        self.stats.record_dropped_field();
+                        continue;
+                    }
+                    crate::CollisionPolicy::PrefixConflicting(prefix) => {
+                        format!("{}{}", prefix, name)
+                    }
+                }
+            } else {
+                name
+            };
+            if self.provenance.is_some() {
+                origins.insert(name.clone(), origin.to_string());
+            }
+            root.insert(name, value);
+        }
+    }
+
+    /// Insert the global and built-in fields of a record with `insert`, from its level, the
+    /// metadata of its event and the names of its spans.
+    fn insert_builtins(
+        &self,
+        metadata: &tracing_core::Metadata<'_>,
+        level: &str,
+        span_names: Vec<&'static str>,
+        mut insert: impl FnMut(&str, &serde_json::Value),
+    ) {
+        // Global fields, which built-in fields take precedence over
+        for (name, value) in &self.global_fields {
+            insert(name, value);
+        }
+        let mut insert = |name: &str, value: serde_json::Value| insert(name, &value);
+
+        // level, which can be overridden by the level override field of the event
+        insert(&self.level_name, self.level_value(level));
+
+        // target
+        insert(
+            &self.target_name,
+            json!(self.shorten_target(metadata.target())),
+        );
+
+        // crate, from the unshortened module path or target
+        if self.crate_field {
+            let path = metadata.module_path().unwrap_or(metadata.target());
+            let name = path.split("::").next().unwrap_or(path);
+            insert("crate", json!(name));
+        }
+
+        // timestamp
+        let now = self.clock.now();
+        insert(&self.timestamp_name, self.timestamp_value_at(now));
+        #[cfg(feature = "chrono")]
+        if self.time_partitions {
+            let now = chrono::DateTime::from_timestamp_nanos(now);
+            let week = now.iso_week();
+            insert("date", json!(now.format("%Y-%m-%d").to_string()));
+            insert("hour", json!(now.hour()));
+            insert(
+                "iso_week",
+                json!(format!("{}-W{:02}", week.year(), week.week())),
+            );
+        }
+
+        if self.file_names && metadata.file().is_some() {
+            insert("file", json!(metadata.file().expect("is some")));
+        }
+
+        if self.line_numbers && metadata.line().is_some() {
+            insert("line", json!(metadata.line().expect("is some")));
+        }
+
+        if self.gcp_format {
+            let mut location = serde_json::Map::new();
+            if let Some(file) = metadata.file() {
+                location.insert("file".to_string(), json!(file));
+            }
+            if let Some(line) = metadata.line() {
+                location.insert("line".to_string(), json!(line.to_string()));
+            }
+            location.insert(
+                "function".to_string(),
+                json!(metadata.module_path().unwrap_or(metadata.target())),
+            );
+            insert("logging.googleapis.com/sourceLocation", json!(location));
+        }
+
+        // Caller is only resolved for debug and trace events, since symbolication is expensive
+        if self.caller && *metadata.level() >= tracing_core::Level::DEBUG {
+            if let Some(caller) = self.caller_cache.caller(metadata.callsite()) {
+                insert("caller", json!(caller));
+            }
+        }
+
+        if self.thread_ids || self.thread_names {
+            let thread = std::thread::current();
+            if self.thread_ids {
+                insert("thread.id", thread_id(thread.id()));
+            }
+            if self.thread_names {
+                if let Some(name) = thread.name() {
+                    insert("thread.name", json!(name));
+                }
+            }
+        }
+
+        if self.current_span {
+            if let Some(name) = span_names.last() {
+                insert("span", json!(name));
+            }
+        }
+        if self.span_list && !span_names.is_empty() {
+            let mut names = span_names;
+            if self.span_order == SpanOrder::LeafToRoot {
+                names.reverse();
+            }
+            insert("span_list", json!(names));
+        }
+    }
+
+    /// Assemble a record from its fields and the fields of its spans, or None if the record is
+    /// rejected by the event filter.
+    fn assemble(
+        &self,
+        metadata: &tracing_core::Metadata<'_>,
+        level: String,
+        fields: serde_json::Map<String, serde_json::Value>,
+        spans: Vec<serde_json::Map<String, serde_json::Value>>,
+        span_scopes: Vec<SpanScope>,
+        span_names: Vec<&'static str>,
+    ) -> Option<serde_json::Map<String, serde_json::Value>> {
+        if let Some(filter) = &self.event_filter {
+            let view = EventFields {
+                event: &fields,
+                spans: &spans,
+            };
+            if !filter(metadata, &view) {
+                return None;
+            }
+        }
+
+        if let Some(boost) = &self.verbosity_boost {
+            let view = EventFields {
+                event: &fields,
+                spans: &spans,
+            };
+            if !boost.allow(*metadata.level(), &view) {
+                return None;
+            }
+        }
+
+        if let Some(quota) = &self.field_quota {
+            let view = EventFields {
+                event: &fields,
+                spans: &spans,
+            };
+            if !quota.allow(&view) {
+                return None;
+            }
+        }
+
+        // Move or drop fields that are not allowed in strict schema mode
+        let mut fields = fields;
+        let mut spans = spans;
+        let mut extra = serde_json::Map::new();
+        if let Some(allowed) = &self.allowed_fields {
+            for fields in std::iter::once(&mut fields).chain(spans.iter_mut()) {
+                fields.retain(|name, value| {
+                    if name == self.message_name.as_ref() || allowed.contains(name) {
+                        return true;
+                    }
+                    match self.unknown_fields {
+                        crate::UnknownFields::Extra => {
+                            extra.insert(name.clone(), value.take());
+                        }
+                        crate::UnknownFields::Drop => self.stats.record_dropped_field(),
+                    }
+                    false
+                });
+            }
+        }
+
+        if let Some(dedup) = &self.value_dedup {
+            for fields in std::iter::once(&mut fields).chain(spans.iter_mut()) {
+                dedup.apply(fields, &self.message_name);
+            }
+        }
+
+        let mut root = serde_json::Map::new();
+        self.insert_builtins(metadata, &level, span_names, |name, value| {
+            root.insert(name.to_string(), value.clone());
+        });
+
+        if self.fingerprint {
+            let message = fields
+                .get("message_template")
+                .or_else(|| fields.get(self.message_name.as_ref()))
+                .and_then(|message| message.as_str())
+                .unwrap_or_default();
+            root.insert(
+                "fingerprint".to_string(),
+                json!(fingerprint(metadata, message)),
+            );
+        }
+
+        // The origin of each flattened field, as later fields override earlier fields
+        let mut origins = std::collections::HashMap::new();
+        if self.provenance.is_some() {
+            origins.extend(
+                root.keys()
+                    .map(|name| (name.clone(), "derived".to_string())),
+            );
+        }
+
+        // The fields written so far are built-in fields, which event and span fields may collide
+        // with
+        let builtins: std::collections::HashSet<String> =
+            if self.collision_policy == crate::CollisionPolicy::Overwrite {
+                Default::default()
+            } else {
+                root.keys().cloned().collect()
+            };
+
+        // Serialize the event fields
+        if self.flatten_fields {
+            self.merge_fields(&mut root, &mut origins, &builtins, fields, "event");
+        } else {
+            root.insert("fields".to_string(), json!(fields));
+        }
+
+        if !spans.is_empty() {
+            if self.flatten_spans {
+                for (fields, (_, name)) in spans.into_iter().zip(span_scopes) {
+                    let origin = if self.provenance.is_some() {
+                        format!("span:{}", name)
+                    } else {
+                        String::new()
+                    };
+                    self.merge_fields(&mut root, &mut origins, &builtins, fields, &origin);
+                }
+            } else {
+                let mut spans: Vec<_> = spans
+                    .into_iter()
+                    .zip(span_scopes)
+                    .map(|(mut fields, (depth, _))| {
+                        if self.span_depth {
+                            fields.insert("span.depth".to_string(), json!(depth));
+                        }
+                        fields
+                    })
+                    .collect();
+                if self.span_order == SpanOrder::LeafToRoot {
+                    spans.reverse();
+                }
+                root.insert("spans".to_string(), json!(spans));
+            }
+        }
+
+        if !extra.is_empty() {
+            root.insert("extra".to_string(), json!(extra));
+        }
+
+        // The level and its number are written last from the same level, so event and span
+        // fields with the same names can't make them disagree
+        if let Some(name) = &self.level_number_name {
+            root.insert(self.level_name.to_string(), self.level_value(&level));
+            match level_number(&level) {
+                Some(number) => root.insert(name.to_string(), json!(number)),
+                None => root.remove(name.as_ref()),
+            };
+            if self.provenance.is_some() {
+                origins.insert(self.level_name.to_string(), "derived".to_string());
+                origins.insert(name.to_string(), "derived".to_string());
+            }
+        }
+
+        match self.provenance {
+            Some(crate::Provenance::Object) => {
+                let origins: serde_json::Map<_, _> = origins
+                    .into_iter()
+                    .map(|(name, origin)| (name, json!(origin)))
+                    .collect();
+                root.insert("_provenance".to_string(), json!(origins));
+            }
+            Some(crate::Provenance::Suffix) => {
+                root = root
+                    .into_iter()
+                    .map(|(name, value)| match origins.get(&name) {
+                        Some(origin)
+                            if origin != "derived" && name != self.message_name.as_ref() =>
+                        {
+                            (format!("{}@{}", name, origin), value)
+                        }
+                        _ => (name, value),
+                    })
+                    .collect();
+            }
+            None => {}
+        }
+
+        Some(root)
+    }
+}
+
+/// Serializes the fields of an event into the members of its record as they are visited,
+/// renaming the message field, applying capture policies and folding multi-line values as
+/// `collect_fields` does with the fields recorded by `JsonStorage`.
+struct FieldEncoder<'a> {
+    layer: &'a JsonFormattingLayer,
+    members: &'a mut Members,
+}
+
+impl<'a> FieldEncoder<'a> {
+    /// Returns the name of a field in the record.
+    fn name(&self, name: &'static str) -> &'a str {
+        if name == "message" {
+            &self.layer.message_name
+        } else {
+            name
+        }
+    }
+
+    fn push(&mut self, name: &str, value: serde_json::Value) {
+        self.push_ref(name, &value);
+    }
+
+    fn push_ref(&mut self, name: &str, value: &serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => self.push_str(name, text),
+            value => self.members.push(name, value),
+        }
+    }
+
+    /// Push a string value, folding it if it has multiple lines.
+    fn push_str(&mut self, name: &str, text: &str) {
+        if !text.contains('\n') {
+            self.members.push(name, text);
+            return;
+        }
+        if self.layer.exception_fields {
+            if let Some((kind, message)) = crate::multiline::parse_exception(text) {
+                self.members.push("exception.type", &kind);
+                self.members.push("exception.message", &message);
+            }
+        }
+        self.members
+            .push(name, &self.layer.multiline.apply(text.to_string()));
+    }
+}
+
+impl tracing_core::field::Visit for FieldEncoder<'_> {
+    fn record_i64(&mut self, field: &tracing_core::Field, value: i64) {
+        let name = self.name(field.name());
+        match self
+            .layer
+            .field_visitors
+            .visit(|v| v.record_i64(field, value))
+        {
+            Some(value) => self.push(name, value),
+            None => self.members.push(name, &value),
+        }
+    }
+
+    fn record_u64(&mut self, field: &tracing_core::Field, value: u64) {
+        let name = self.name(field.name());
+        match self
+            .layer
+            .field_visitors
+            .visit(|v| v.record_u64(field, value))
+        {
+            Some(value) => self.push(name, value),
+            None => self.members.push(name, &value),
+        }
+    }
+
+    fn record_f64(&mut self, field: &tracing_core::Field, value: f64) {
+        let name = self.name(field.name());
+        match self
+            .layer
+            .field_visitors
+            .visit(|v| v.record_f64(field, value))
+        {
+            Some(value) => self.push(name, value),
+            None => self.members.push(name, &value),
+        }
+    }
+
+    fn record_bool(&mut self, field: &tracing_core::Field, value: bool) {
+        let name = self.name(field.name());
+        match self
+            .layer
+            .field_visitors
+            .visit(|v| v.record_bool(field, value))
+        {
+            Some(value) => self.push(name, value),
+            None => self.members.push(name, &value),
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing_core::Field, value: &str) {
+        let name = self.name(field.name());
+        match self
+            .layer
+            .field_visitors
+            .visit(|v| v.record_str(field, value))
+        {
+            Some(value) => self.push(name, value),
+            None => self.push_str(name, value),
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing_core::Field, value: &dyn std::fmt::Debug) {
+        // Skip fields that are actually log metadata that have already been handled
+        if field.name().starts_with("log.") {
+            return;
+        }
+        let name = field.name();
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        if let Some(value) = self
+            .layer
+            .field_visitors
+            .visit(|v| v.record_debug(field, value))
+        {
+            self.push(self.name(name), value);
+            return;
+        }
+        // Rendered into a reused string, since the value is only needed serialized
+        let mut rendered = std::mem::take(&mut self.members.rendered);
+        rendered.clear();
+        let embedded = crate::body::render_debug(&mut rendered, value);
+        let name = if self.layer.detects_captures() && name != "message" {
+            let mode = crate::capture::detect(&rendered);
+            match self.layer.capture_policy(mode) {
+                crate::CapturePolicy::Keep => Some(Cow::Borrowed(name)),
+                crate::CapturePolicy::Prefix => {
+                    Some(Cow::Owned(format!("{}{}", mode.prefix(), name)))
+                }
+                crate::CapturePolicy::Drop => None,
+            }
+        } else {
+            Some(Cow::Borrowed(self.name(name)))
+        };
+        match (name, embedded) {
+            (Some(name), Some(value)) => self.push(&name, value),
+            (Some(name), None) => self.push_str(&name, &rendered),
+            (None, _) => {}
+        }
+        self.members.rendered = rendered;
+    }
+}
+
+/// Returns the number of a thread ID, e.g. 5 for `ThreadId(5)`, since `ThreadId::as_u64` is
+/// unstable, or its `Debug` representation if it has no number.
+fn thread_id(id: std::thread::ThreadId) -> serde_json::Value {
+    let rendered = format!("{:?}", id);
+    let digits: String = rendered.chars().filter(char::is_ascii_digit).collect();
+    match digits.parse::<u64>() {
+        Ok(number) => json!(number),
+        Err(_) => json!(rendered),
+    }
+}
+
+/// Returns the syslog severity of a level name in any casing, ignoring a verbosity tier suffix,
+/// e.g. 3 for `error` and 7 for `trace2`, or None for unknown names.
+pub(crate) fn level_number(level: &str) -> Option<u8> {
+    let name = level.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = match name.to_ascii_lowercase().as_str() {
+        "emergency" => 0,
+        "alert" => 1,
+        "critical" | "fatal" => 2,
+        "error" => 3,
+        "warn" | "warning" => 4,
+        "notice" => 5,
+        "info" => 6,
+        "debug" | "trace" => 7,
+        _ => return None,
+    };
+    Some(number)
+}
+
+/// Compute a stable fingerprint of a callsite and its message with digits stripped, so records
+/// that only differ in numbers, e.g. IDs and durations, share a fingerprint.
+fn fingerprint(metadata: &tracing_core::Metadata<'_>, message: &str) -> String {
+    // FNV-1a, which unlike the std hashers is stable across processes and Rust versions
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut update = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    update(metadata.target().as_bytes());
+    update(&[0]);
+    update(metadata.name().as_bytes());
+    update(&[0]);
+    for c in message.chars().filter(|c| !c.is_ascii_digit()) {
+        update(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    format!("{:016x}", hash)
+}
+
+/// Returns true if `target` matches `pattern`, where `*` matches any sequence of characters.
+/// A pattern ending in `::*` also matches the module itself, e.g. `h2::*` matches `h2`.
+fn pattern_matches(pattern: &str, target: &str) -> bool {
+    if let Some(module) = pattern.strip_suffix("::*") {
+        if !module.contains('*') {
+            return crate::adapters::target_matches(target, module);
+        }
+        if pattern_matches(module, target) {
+            return true;
+        }
+    }
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = target.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard, so the pattern must match exactly
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
         }
-
-        let output = serde_json::to_string(&root).unwrap();
-        println!("{}", output);
     }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
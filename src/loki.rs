@@ -0,0 +1,239 @@
+//! Ships records to Grafana Loki from the process, for deployments without a log agent.
+//!
+//! Fields declared as labels become Loki stream labels instead of staying in the JSON body.
+//! Each label is limited to a maximum number of distinct values to prevent label explosions;
+//! once a label reaches its limit, records with new values are labeled `other` and keep the
+//! field in the body.
+//!
+//! ```rust,no_run
+//! use tracing_ndjson::loki::LokiSink;
+//!
+//! let sink = LokiSink::new("http://loki:3100/loki/api/v1/push")
+//!     .with_static_label("service", "checkout")
+//!     .with_label("level")
+//!     .with_label("region")
+//!     .with_max_label_values(20);
+//! let builder = tracing_ndjson::builder().with_loki(sink);
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+use tracing_core::Metadata;
+
+use crate::background::{Background, Flush};
+use crate::forward::{Forward, SpanContext};
+use crate::FieldNames;
+
+/// The label value used once a label has reached its maximum number of distinct values.
+pub const OVERFLOW_LABEL_VALUE: &str = "other";
+
+/// The stream labels and the encoded `[timestamp, line]` value of a record.
+type Entry = (BTreeMap<String, String>, String);
+
+type Transport = Arc<dyn Fn(&str, &[u8]) -> std::io::Result<()> + Send + Sync>;
+
+/// Pushes records to Loki in batches on a background thread.
+///
+/// At most 10,000 records are queued; further records are dropped until the queue drains.
+/// Records still queued when the layer is dropped are pushed before the drop returns.
+///
+/// The built-in transport only supports `http://` URLs. Use `with_transport` to push with an
+/// HTTP client of your choice, e.g. for HTTPS or authentication.
+#[derive(Clone)]
+pub struct LokiSink {
+    url: String,
+    static_labels: BTreeMap<String, String>,
+    labels: Vec<String>,
+    max_label_values: usize,
+    batch_size: usize,
+    batch_interval: Duration,
+    /// The distinct values seen for each label.
+    label_values: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    transport: Transport,
+    queue: Arc<Background<Entry>>,
+}
+
+impl std::fmt::Debug for LokiSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LokiSink")
+            .field("url", &self.url)
+            .field("static_labels", &self.static_labels)
+            .field("labels", &self.labels)
+            .field("max_label_values", &self.max_label_values)
+            .field("batch_size", &self.batch_size)
+            .field("batch_interval", &self.batch_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LokiSink {
+    /// Push records to the Loki push API at `url`. By default there are no labels, each label
+    /// is limited to 100 distinct values, and batches of up to 1000 records are pushed at least
+    /// every second.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            static_labels: BTreeMap::new(),
+            labels: Vec::new(),
+            max_label_values: 100,
+            batch_size: 1000,
+            batch_interval: Duration::from_secs(1),
+            label_values: Default::default(),
+            transport: Arc::new(crate::transport::post),
+            queue: Arc::new(Background::new("tracing-ndjson-loki")),
+        }
+    }
+
+    /// Add a label with the same value on every stream, e.g. the service name.
+    pub fn with_static_label(mut self, name: impl AsRef<str>, value: impl Into<String>) -> Self {
+        self.static_labels
+            .insert(label_name(name.as_ref()), value.into());
+        self
+    }
+
+    /// Use the value of a field as a stream label, removing it from the JSON body. Use "level"
+    /// or "target" for the built-in fields regardless of their configured names. Characters
+    /// not allowed in label names are replaced with `_`, e.g. "http.method" becomes
+    /// "http_method".
+    pub fn with_label(mut self, field: impl Into<String>) -> Self {
+        self.labels.push(field.into());
+        self
+    }
+
+    /// Limit the number of distinct values of each label.
+    pub fn with_max_label_values(mut self, max: usize) -> Self {
+        self.max_label_values = max;
+        self
+    }
+
+    /// Push a batch when it reaches `size` records, or `interval` after the last push.
+    pub fn with_batch(mut self, size: usize, interval: Duration) -> Self {
+        self.batch_size = size.max(1);
+        self.batch_interval = interval;
+        self
+    }
+
+    /// Push batches using a custom transport, called with the URL and the JSON payload.
+    pub fn with_transport(
+        mut self,
+        transport: impl Fn(&str, &[u8]) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Returns the number of distinct values seen for each label, e.g. to review label
+    /// cardinality. A label at `max_label_values` is overflowing.
+    pub fn label_cardinality(&self) -> BTreeMap<String, usize> {
+        let label_values = self.label_values.lock().unwrap_or_else(|e| e.into_inner());
+        self.labels
+            .iter()
+            .map(|field| {
+                let name = label_name(field);
+                let count = label_values.get(&name).map_or(0, HashSet::len);
+                (name, count)
+            })
+            .collect()
+    }
+
+    /// Returns the number of records dropped because too many were queued.
+    pub fn dropped_records(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    /// Split a record into its stream labels and the JSON body.
+    fn extract(
+        &self,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+    ) -> (BTreeMap<String, String>, Map<String, Value>) {
+        let mut labels = self.static_labels.clone();
+        let mut body = record.clone();
+        let mut label_values = self.label_values.lock().unwrap_or_else(|e| e.into_inner());
+        for field in &self.labels {
+            let key = match field.as_str() {
+                "level" => names.level,
+                "target" => names.target,
+                field => field,
+            };
+            let Some(value) = record.get(key) else {
+                continue;
+            };
+            let value = match value {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            let name = label_name(field);
+            let seen = label_values.entry(name.clone()).or_default();
+            if seen.contains(&value) || seen.len() < self.max_label_values {
+                seen.insert(value.clone());
+                body.remove(key);
+                labels.insert(name, value);
+            } else {
+                labels.insert(name, OVERFLOW_LABEL_VALUE.to_string());
+            }
+        }
+        (labels, body)
+    }
+
+    /// Queue a record, starting the background thread on first use.
+    fn send(&self, labels: BTreeMap<String, String>, line: String) {
+        let timestamp = crate::clock::now_nanos().to_string();
+        let entry = (labels, json!([timestamp, line]).to_string());
+        let flush = Flush {
+            size: self.batch_size,
+            interval: Some(self.batch_interval),
+        };
+        self.queue.send(entry, flush, || {
+            let url = self.url.clone();
+            let transport = self.transport.clone();
+            Box::new(move |batch| push(&transport, &url, batch))
+        });
+    }
+}
+
+/// Push a batch of entries, grouped into streams by their labels.
+fn push(transport: &Transport, url: &str, batch: Vec<Entry>) {
+    let mut streams: BTreeMap<BTreeMap<String, String>, Vec<Value>> = BTreeMap::new();
+    for (labels, value) in batch {
+        if let Ok(value) = serde_json::from_str(&value) {
+            streams.entry(labels).or_default().push(value);
+        }
+    }
+    let streams: Vec<Value> = streams
+        .into_iter()
+        .map(|(labels, values)| json!({"stream": labels, "values": values}))
+        .collect();
+    let payload = json!({ "streams": streams }).to_string();
+    let _ = transport(url, payload.as_bytes());
+}
+
+impl Forward for LokiSink {
+    fn forward(
+        &self,
+        _metadata: &Metadata<'_>,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        _spans: &[SpanContext],
+    ) {
+        let (labels, body) = self.extract(names, record);
+        if let Ok(line) = serde_json::to_string(&body) {
+            self.send(labels, line);
+        }
+    }
+}
+
+/// Replace characters not allowed in Loki label names with `_`.
+fn label_name(field: &str) -> String {
+    let mut name: String = field
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
@@ -0,0 +1,171 @@
+//! Prometheus exposition of the layer's counters, so error rates can be alerted on without
+//! parsing logs.
+//!
+//! ```rust,no_run
+//! let builder = tracing_ndjson::builder();
+//! let metrics = builder.metrics();
+//! metrics.serve("0.0.0.0:9464").unwrap();
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::Stats;
+
+/// Renders the counters of a layer in the Prometheus text exposition format.
+/// Retrieve it using `Builder::metrics`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    stats: Arc<Stats>,
+}
+
+impl Metrics {
+    pub(crate) fn new(stats: Arc<Stats>) -> Self {
+        Self { stats }
+    }
+
+    /// Render the counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let stats = &self.stats;
+        let mut out = String::new();
+        counter_header(
+            &mut out,
+            "tracing_ndjson_events_total",
+            "Events emitted, by level and target.",
+        );
+        for (target, level, count) in stats.events_by_target() {
+            let _ = writeln!(
+                out,
+                "tracing_ndjson_events_total{{level=\"{}\",target=\"{}\"}} {}",
+                level.as_str().to_lowercase(),
+                escape(&target),
+                count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tracing_ndjson_record_size_bytes Sizes of the records written."
+        );
+        let _ = writeln!(out, "# TYPE tracing_ndjson_record_size_bytes histogram");
+        let mut cumulative = 0;
+        for (le, count) in stats.record_sizes() {
+            cumulative += count;
+            let le = if le == u64::MAX {
+                "+Inf".to_string()
+            } else {
+                le.to_string()
+            };
+            let _ = writeln!(
+                out,
+                "tracing_ndjson_record_size_bytes_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "tracing_ndjson_record_size_bytes_sum {}",
+            stats.bytes_written()
+        );
+        let _ = writeln!(out, "tracing_ndjson_record_size_bytes_count {}", cumulative);
+        for (name, help, value) in [
+            (
+                "tracing_ndjson_bytes_written_total",
+                "Bytes written to the output.",
+                stats.bytes_written(),
+            ),
+            (
+                "tracing_ndjson_dropped_events_total",
+                "Events dropped instead of written.",
+                stats.dropped(),
+            ),
+            (
+                "tracing_ndjson_expired_records_total",
+                "Buffered records dropped because they expired.",
+                stats.expired(),
+            ),
+            (
+                "tracing_ndjson_dropped_fields_total",
+                "Fields dropped because they are not allowed.",
+                stats.dropped_fields(),
+            ),
+            (
+                "tracing_ndjson_type_mismatches_total",
+                "Typed field values that could not be coerced.",
+                stats.type_mismatches(),
+            ),
+            (
+                "tracing_ndjson_write_errors_total",
+                "Records that could not be written to the output.",
+                stats.write_errors(),
+            ),
+            (
+                "tracing_ndjson_span_lookup_failures_total",
+                "Spans or span fields that could not be found.",
+                stats.span_lookup_failures(),
+            ),
+            (
+                "tracing_ndjson_slow_writes_total",
+                "Writes that blocked for longer than the watchdog threshold.",
+                stats.slow_writes(),
+            ),
+        ] {
+            counter_header(&mut out, name, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tracing_ndjson_queue_depth Records queued for the background writer, by lane."
+        );
+        let _ = writeln!(out, "# TYPE tracing_ndjson_queue_depth gauge");
+        let (priority, normal) = stats.queue_depths();
+        for (lane, depth) in [("priority", priority), ("normal", normal)] {
+            let _ = writeln!(
+                out,
+                "tracing_ndjson_queue_depth{{lane=\"{}\"}} {}",
+                lane, depth
+            );
+        }
+        out
+    }
+
+    /// Serve the counters to Prometheus scrapes on a background thread, on any path.
+    pub fn serve(&self, address: impl ToSocketAddrs) -> std::io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(address)?;
+        let metrics = self.clone();
+        std::thread::Builder::new()
+            .name("tracing-ndjson-metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let mut reader = BufReader::new(&stream);
+                    // Skip the request line and headers
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                        line.clear();
+                    }
+                    let body = metrics.render();
+                    let _ = write!(
+                        &stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                }
+            })
+    }
+}
+
+fn counter_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,58 @@
+use serde_json::Value;
+
+/// How multi-line string values, such as backtraces logged using `Display`, are emitted.
+/// The default is to keep them as they are.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Multiline {
+    /// Keep multi-line values as they are.
+    #[default]
+    Keep,
+    /// Split multi-line values into an array of lines, dropping empty lines.
+    Lines,
+    /// Normalize multi-line values into a single string with `\n` line endings,
+    /// trailing whitespace removed and empty lines dropped.
+    Normalize,
+}
+
+impl Multiline {
+    pub(crate) fn apply(&self, text: String) -> Value {
+        match self {
+            Multiline::Keep => Value::String(text),
+            Multiline::Lines => Value::Array(lines(&text).map(Value::from).collect()),
+            Multiline::Normalize => Value::String(lines(&text).collect::<Vec<_>>().join("\n")),
+        }
+    }
+}
+
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+}
+
+/// Heuristically extract the exception type and message from a multi-line value, e.g.
+/// `std::io::Error: No such file or directory` or a panic message.
+pub(crate) fn parse_exception(text: &str) -> Option<(String, String)> {
+    let mut lines = lines(text);
+    let first = lines.next()?.trim();
+
+    if first.starts_with("thread '") && first.contains("panicked at") {
+        // Since Rust 1.73 the panic message is on the line following the location
+        let message = match first.split_once("panicked at ") {
+            Some((_, rest)) if !rest.ends_with(':') => rest.to_string(),
+            _ => lines.next().unwrap_or(first).trim().to_string(),
+        };
+        return Some(("panic".to_string(), message));
+    }
+
+    let (kind, message) = first.split_once(": ")?;
+    let is_type = !kind.is_empty()
+        && kind.len() <= 128
+        && kind
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '.' | '<' | '>' | '$'));
+    if !is_type || message.trim().is_empty() {
+        return None;
+    }
+    Some((kind.to_string(), message.trim().to_string()))
+}
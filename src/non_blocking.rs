@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tracing_core::Level;
+
+use crate::output::Output;
+use crate::Stats;
+
+/// The outcome of queueing a record.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Queued {
+    /// Whether the record was queued rather than dropped.
+    pub(crate) queued: bool,
+    /// The number of records dropped and the duration of a degradation period that just ended.
+    pub(crate) recovered: Option<(u64, Duration)>,
+    /// The number of queued WARN and ERROR records and of other records after queueing.
+    pub(crate) depths: (usize, usize),
+}
+
+/// A record taken from the queue to be written.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Popped {
+    pub(crate) level: Level,
+    pub(crate) line: Vec<u8>,
+    /// The number of queued WARN and ERROR records and of other records left in the queue.
+    pub(crate) depths: (usize, usize),
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Queued records with the time they were queued. With priority lanes, WARN and ERROR
+    /// records are queued in `important` instead.
+    records: VecDeque<(Instant, Level, Vec<u8>)>,
+    important: VecDeque<(Instant, Level, Vec<u8>)>,
+    /// The number of queued WARN and ERROR records in either lane.
+    important_len: usize,
+    /// When the current degradation period started and how many records it dropped.
+    degraded: Option<(Instant, u64)>,
+    /// Whether the worker is writing a record it took from the queue.
+    writing: bool,
+    closed: bool,
+}
+
+/// A bounded queue of encoded records. Above the high-water mark only WARN and ERROR records
+/// are queued, until the queue drains below half of the high-water mark. Records older than the
+/// time to live are dropped instead of being returned. With priority lanes, WARN and ERROR
+/// records are returned ahead of records queued before them.
+#[derive(Debug)]
+pub(crate) struct Queue {
+    capacity: usize,
+    high_water: usize,
+    priority_lanes: bool,
+    ttl: Option<Duration>,
+    expired: AtomicU64,
+    state: Mutex<State>,
+    ready: Condvar,
+    /// Notified when the worker finished writing and the queue is empty.
+    idle: Condvar,
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: usize, high_water: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            high_water: high_water.clamp(1, capacity),
+            priority_lanes: false,
+            ttl: None,
+            expired: AtomicU64::new(0),
+            state: Mutex::new(State::default()),
+            ready: Condvar::new(),
+            idle: Condvar::new(),
+        }
+    }
+
+    /// Drop records that were queued longer than `ttl` ago instead of returning them.
+    pub(crate) fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Queue WARN and ERROR records in a separate lane that is returned first.
+    pub(crate) fn with_priority_lanes(mut self, priority_lanes: bool) -> Self {
+        self.priority_lanes = priority_lanes;
+        self
+    }
+
+    pub(crate) fn push(&self, level: Level, line: Vec<u8>) -> Queued {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let len = state.records.len() + state.important.len();
+        let mut recovered = None;
+        if let Some((since, dropped)) = state.degraded {
+            if len < self.high_water / 2 {
+                state.degraded = None;
+                recovered = Some((dropped, since.elapsed()));
+            }
+        } else if len >= self.high_water {
+            state.degraded = Some((Instant::now(), 0));
+        }
+
+        let important = level <= Level::WARN;
+        let mut queued = state.degraded.is_none() || important;
+        if queued && len >= self.capacity {
+            // Make room for an important record by evicting the oldest less important record
+            match state
+                .records
+                .iter()
+                .position(|(_, level, _)| *level > Level::WARN)
+            {
+                Some(i) if important => {
+                    state.records.remove(i);
+                    if let Some((_, dropped)) = &mut state.degraded {
+                        *dropped += 1;
+                    }
+                }
+                _ => queued = false,
+            }
+        }
+        if queued {
+            let record = (Instant::now(), level, line);
+            if important && self.priority_lanes {
+                state.important.push_back(record);
+            } else {
+                state.records.push_back(record);
+            }
+            if important {
+                state.important_len += 1;
+            }
+            self.ready.notify_one();
+        } else if let Some((_, dropped)) = &mut state.degraded {
+            *dropped += 1;
+        }
+        let len = state.records.len() + state.important.len();
+        Queued {
+            queued,
+            recovered,
+            depths: (state.important_len, len - state.important_len),
+        }
+    }
+
+    /// Wait for the next record to write, returning None once the queue is closed and empty.
+    /// Records are taken one at a time, so a WARN or ERROR record queued while the worker is
+    /// writing is the next one written with priority lanes. Expired records are dropped and
+    /// counted instead of being returned.
+    pub(crate) fn pop(&self) -> Option<Popped> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let next = match state.important.pop_front() {
+                Some(record) => Some(record),
+                None => state.records.pop_front(),
+            };
+            let Some((queued_at, level, line)) = next else {
+                if state.closed {
+                    return None;
+                }
+                state = self.ready.wait(state).unwrap_or_else(|e| e.into_inner());
+                continue;
+            };
+            if level <= Level::WARN {
+                state.important_len -= 1;
+            }
+            if self.ttl.is_some_and(|ttl| queued_at.elapsed() > ttl) {
+                self.expired.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let len = state.records.len() + state.important.len();
+            state.writing = true;
+            return Some(Popped {
+                level,
+                line,
+                depths: (state.important_len, len - state.important_len),
+            });
+        }
+    }
+
+    /// Mark the record taken last as written.
+    pub(crate) fn written(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.writing = false;
+        if state.records.is_empty() && state.important.is_empty() {
+            self.idle.notify_all();
+        }
+    }
+
+    /// Wait up to `timeout` for the queued records to be written, returning whether they were.
+    #[cfg(all(feature = "signals", unix))]
+    pub(crate) fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while state.writing || !state.records.is_empty() || !state.important.is_empty() {
+            let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            state = self
+                .idle
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.ready.notify_all();
+    }
+
+    /// Returns the number of records dropped because they expired since the last call.
+    pub(crate) fn take_expired(&self) -> u64 {
+        self.expired.swap(0, Ordering::Relaxed)
+    }
+
+    pub(crate) fn priority_lanes(&self) -> bool {
+        self.priority_lanes
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn high_water(&self) -> usize {
+        self.high_water
+    }
+}
+
+/// Writes queued records to an output on a background thread. Dropping it writes the remaining
+/// records before returning.
+#[derive(Debug)]
+pub(crate) struct NonBlocking {
+    queue: Arc<Queue>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl NonBlocking {
+    pub(crate) fn new(
+        capacity: usize,
+        high_water: usize,
+        priority_lanes: bool,
+        ttl: Option<Duration>,
+        output: Output,
+        stats: Arc<Stats>,
+    ) -> Self {
+        let queue = Arc::new(
+            Queue::new(capacity, high_water)
+                .with_priority_lanes(priority_lanes)
+                .with_ttl(ttl),
+        );
+        let worker_queue = queue.clone();
+        let worker = std::thread::Builder::new()
+            .name("tracing-ndjson-writer".to_string())
+            .spawn(move || {
+                while let Some(popped) = worker_queue.pop() {
+                    stats.record_expired(worker_queue.take_expired());
+                    stats.record_queue_depths(popped.depths);
+                    if output.write(&popped.line, popped.level).is_err() {
+                        stats.record_write_error();
+                    }
+                    worker_queue.written();
+                }
+                stats.record_expired(worker_queue.take_expired());
+            })
+            .expect("failed to spawn writer thread");
+        Self {
+            queue,
+            worker: Some(worker),
+        }
+    }
+
+    pub(crate) fn queue(&self) -> &Queue {
+        &self.queue
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        self.queue.close();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
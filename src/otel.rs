@@ -0,0 +1,133 @@
+//! Adding the trace and span IDs of the OpenTelemetry context of the current span to records,
+//! when a `tracing_opentelemetry` layer is in the same subscriber, so records can be correlated
+//! with the traces it exports.
+//!
+//! ```rust
+//! use tracing_ndjson::otel::OtelIds;
+//!
+//! let builder = tracing_ndjson::builder()
+//!     .with_otel_ids(OtelIds::new().with_traceparent("traceparent"));
+//! ```
+
+use std::sync::OnceLock;
+
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use tracing_core::dispatcher::WeakDispatch;
+use tracing_core::{Dispatch, Subscriber};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// The fields the trace and span IDs are written to.
+#[derive(Debug, Clone)]
+pub struct OtelIds {
+    trace_id: String,
+    span_id: String,
+    traceparent: Option<String>,
+}
+
+impl Default for OtelIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OtelIds {
+    /// Write the trace ID to `trace_id` and the span ID to `span_id`, as lowercase hex, without
+    /// a W3C `traceparent` field.
+    pub fn new() -> Self {
+        Self {
+            trace_id: "trace_id".to_string(),
+            span_id: "span_id".to_string(),
+            traceparent: None,
+        }
+    }
+
+    /// Set the field the trace ID is written to, e.g. `trace.id`.
+    pub fn with_trace_id_name(mut self, name: impl Into<String>) -> Self {
+        self.trace_id = name.into();
+        self
+    }
+
+    /// Set the field the span ID is written to, e.g. `span.id`.
+    pub fn with_span_id_name(mut self, name: impl Into<String>) -> Self {
+        self.span_id = name.into();
+        self
+    }
+
+    /// Also write the context as a W3C `traceparent` value (`00-<trace-id>-<span-id>-<flags>`)
+    /// to a field, e.g. `traceparent`, for propagating it from records.
+    pub fn with_traceparent(mut self, name: impl Into<String>) -> Self {
+        self.traceparent = Some(name.into());
+        self
+    }
+}
+
+/// Looks up the OpenTelemetry context of spans through the subscriber the layer was added to.
+/// Without `OtelIds`, the context is only looked up, e.g. for the GCP trace fields.
+#[derive(Debug)]
+pub(crate) struct OtelLookup {
+    ids: Option<OtelIds>,
+    /// Weak, as the subscriber owns the layer.
+    dispatch: OnceLock<WeakDispatch>,
+}
+
+impl OtelLookup {
+    pub(crate) fn new(ids: Option<OtelIds>) -> Self {
+        Self {
+            ids,
+            dispatch: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn ids(&self) -> Option<&OtelIds> {
+        self.ids.as_ref()
+    }
+
+    pub(crate) fn register(&self, dispatch: &Dispatch) {
+        let _ = self.dispatch.set(dispatch.downgrade());
+    }
+
+    /// Returns the OpenTelemetry context of a span. There is none without a
+    /// `tracing_opentelemetry` layer or when the context is invalid, e.g. for spans that are not
+    /// sampled by a no-op tracer.
+    pub(crate) fn context<S>(&self, span: &SpanRef<'_, S>) -> Option<SpanContext>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let dispatch = self.dispatch.get().and_then(WeakDispatch::upgrade)?;
+        let cx = tracing_opentelemetry::get_otel_context(&mut span.extensions_mut(), &dispatch)?;
+        let span_context = cx.span().span_context().clone();
+        span_context.is_valid().then_some(span_context)
+    }
+
+    /// Insert the IDs of the OpenTelemetry context of a span into a record with `insert`, if IDs
+    /// are configured and the span has a context.
+    pub(crate) fn insert<S>(
+        &self,
+        span: &SpanRef<'_, S>,
+        mut insert: impl FnMut(&str, serde_json::Value),
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(ids) = &self.ids else {
+            return;
+        };
+        let Some(span_context) = self.context(span) else {
+            return;
+        };
+        let trace_id = span_context.trace_id().to_string();
+        let span_id = span_context.span_id().to_string();
+        if let Some(name) = &ids.traceparent {
+            insert(
+                name,
+                serde_json::json!(format!(
+                    "00-{}-{}-{:02x}",
+                    trace_id,
+                    span_id,
+                    span_context.trace_flags().to_u8()
+                )),
+            );
+        }
+        insert(&ids.trace_id, serde_json::json!(trace_id));
+        insert(&ids.span_id, serde_json::json!(span_id));
+    }
+}
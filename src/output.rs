@@ -0,0 +1,130 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use tracing_core::Level;
+use tracing_subscriber::fmt::{writer::BoxMakeWriter, MakeWriter};
+
+#[cfg(feature = "chrono")]
+use crate::file::RollingFileWriter;
+use crate::file::{FileWriter, LevelFiles};
+use crate::sink::{RecordMeta, RecordSink, Stdout};
+
+/// Where encoded records are written.
+#[derive(Debug, Default, Clone)]
+pub(crate) enum Output {
+    #[default]
+    Stdout,
+    File(FileWriter),
+    #[cfg(feature = "chrono")]
+    RollingFile(RollingFileWriter),
+    LevelFiles(LevelFiles),
+    #[cfg(feature = "encryption")]
+    Encrypted(crate::encrypt::EncryptedWriter),
+    #[cfg(feature = "compression")]
+    Compressed(crate::compress::CompressedWriter),
+    /// Writes each record to a new writer made by a `MakeWriter`.
+    Writer(Arc<BoxMakeWriter>),
+    Custom(Arc<dyn RecordSink>),
+    /// Defers the terminator of each record until the next record is written, so the stream
+    /// never ends with a terminator. `pending` is locked while writing, so each terminator is
+    /// written right before the record that follows it.
+    Unterminated {
+        output: Box<Output>,
+        terminator: Vec<u8>,
+        pending: Arc<Mutex<bool>>,
+    },
+}
+
+impl Output {
+    /// Write a complete encoded and framed record emitted at the given level.
+    pub(crate) fn write(&self, line: &[u8], level: Level) -> std::io::Result<()> {
+        let meta = RecordMeta::new(level);
+        match self {
+            Output::Stdout => Stdout.write_record(line, &meta),
+            Output::File(file) => file.write_record(line, &meta),
+            #[cfg(feature = "chrono")]
+            Output::RollingFile(file) => file.write_record(line, &meta),
+            Output::LevelFiles(files) => files.write_record(line, &meta),
+            #[cfg(feature = "encryption")]
+            Output::Encrypted(writer) => writer.write_record(line, &meta),
+            #[cfg(feature = "compression")]
+            Output::Compressed(writer) => writer.write_record(line, &meta),
+            Output::Custom(sink) => sink.write_record(line, &meta),
+            Output::Writer(make_writer) => {
+                let mut writer = make_writer.make_writer();
+                writer.write_all(line)?;
+                writer.flush()
+            }
+            Output::Unterminated {
+                output,
+                terminator,
+                pending,
+            } => {
+                let line = line.strip_suffix(terminator.as_slice()).unwrap_or(line);
+                let mut pending = pending.lock().unwrap_or_else(|e| e.into_inner());
+                if *pending {
+                    let mut separated = Vec::with_capacity(terminator.len() + line.len());
+                    separated.extend_from_slice(terminator);
+                    separated.extend_from_slice(line);
+                    output.write(&separated, level)
+                } else {
+                    output.write(line, level)?;
+                    *pending = true;
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Returns the directory records are written in, for outputs that write to files.
+    pub(crate) fn directory(&self) -> Option<&std::path::Path> {
+        match self.base() {
+            Output::File(file) => Some(
+                file.path()
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(std::path::Path::new(".")),
+            ),
+            #[cfg(feature = "chrono")]
+            Output::RollingFile(file) => Some(file.directory()),
+            Output::LevelFiles(files) => Some(files.directory()),
+            _ => None,
+        }
+    }
+
+    /// Returns the file descriptor of stdout or the file records are written to, so records can
+    /// be written from a signal handler.
+    #[cfg(all(feature = "signals", unix))]
+    pub(crate) fn raw_fd(&self) -> Option<std::os::fd::RawFd> {
+        match self.base() {
+            Output::Stdout => Some(1),
+            Output::File(file) => Some(file.raw_fd()),
+            _ => None,
+        }
+    }
+
+    /// Returns the output records are written to, without deferred terminators.
+    pub(crate) fn base(&self) -> &Output {
+        match self {
+            Output::Unterminated { output, .. } => output,
+            output => output,
+        }
+    }
+
+    /// Defer the terminator of each record written to stdout, a file, a writer or a custom sink
+    /// until the next record. Other outputs are returned unchanged.
+    pub(crate) fn unterminated(self, terminator: &[u8]) -> Output {
+        match self {
+            Output::Stdout | Output::File(_) | Output::Writer(_) | Output::Custom(_)
+                if !terminator.is_empty() =>
+            {
+                Output::Unterminated {
+                    output: Box::new(self),
+                    terminator: terminator.to_vec(),
+                    pending: Arc::new(Mutex::new(false)),
+                }
+            }
+            output => output,
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use serde_json::{Map, Value};
+use tracing_core::Metadata;
+
+use crate::Stats;
+
+/// A kind of potential personally identifiable information or secret found in a field value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PiiKind {
+    /// An email address, e.g. `jane@example.com`.
+    Email,
+    /// A 13 to 19 digit number passing the Luhn check, optionally grouped with spaces or dashes.
+    CreditCard,
+    /// A JSON Web Token, i.e. three base64url segments starting with `eyJ`.
+    Jwt,
+    /// The token following `Bearer ` in an authorization header.
+    BearerToken,
+    /// An AWS access key ID, e.g. `AKIA...`.
+    AwsAccessKey,
+}
+
+impl PiiKind {
+    /// Returns the name of the kind, as used in redacted values, e.g. `credit_card`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PiiKind::Email => "email",
+            PiiKind::CreditCard => "credit_card",
+            PiiKind::Jwt => "jwt",
+            PiiKind::BearerToken => "bearer_token",
+            PiiKind::AwsAccessKey => "aws_access_key",
+        }
+    }
+}
+
+/// What happens when potential PII is found in a field value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiiMode {
+    /// Count findings per callsite in `Stats::pii_findings`, writing values unchanged.
+    Report,
+    /// Count findings and replace each match with `[REDACTED:<kind>]`, e.g. `[REDACTED:email]`.
+    Redact,
+}
+
+/// Potential PII found in a field of the events of a callsite, retrieved using
+/// `Stats::pii_findings`.
+#[derive(Debug, Clone)]
+pub struct PiiFinding {
+    /// The metadata of the callsite, with its target, name, level, file and line.
+    pub metadata: &'static Metadata<'static>,
+    /// The name of the field the value was found in, which may be a span field.
+    pub field: String,
+    /// The kind of value found.
+    pub kind: PiiKind,
+    /// The number of values found.
+    pub count: u64,
+}
+
+/// Scan the string and numeric values of a set of fields recorded for an event of a callsite,
+/// counting findings and redacting them according to the mode.
+pub(crate) fn scan(
+    mode: PiiMode,
+    metadata: &'static Metadata<'static>,
+    fields: &mut Map<String, Value>,
+    stats: &Stats,
+) {
+    for (name, value) in fields.iter_mut() {
+        let text = match value {
+            Value::String(text) => std::borrow::Cow::Borrowed(text.as_str()),
+            Value::Number(n) => std::borrow::Cow::Owned(n.to_string()),
+            _ => continue,
+        };
+        let matches = find(&text);
+        if matches.is_empty() {
+            continue;
+        }
+        for (_, _, kind) in &matches {
+            stats.record_pii(metadata, name, *kind);
+        }
+        if mode == PiiMode::Redact {
+            *value = Value::String(redact(&text, &matches));
+        }
+    }
+}
+
+/// Replace each match in a value with `[REDACTED:<kind>]`.
+fn redact(text: &str, matches: &[(usize, usize, PiiKind)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end, kind) in matches {
+        out.push_str(&text[last..*start]);
+        out.push_str("[REDACTED:");
+        out.push_str(kind.as_str());
+        out.push(']');
+        last = *end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Returns the byte ranges of potential PII in a value, in order and without overlaps.
+pub(crate) fn find(text: &str) -> Vec<(usize, usize, PiiKind)> {
+    let mut matches = Vec::new();
+    find_bearer_tokens(text, &mut matches);
+    find_tokens(text, &mut matches);
+    find_emails(text, &mut matches);
+    find_credit_cards(text, &mut matches);
+    matches.sort_by_key(|(start, _, _)| *start);
+    // Earlier detectors take precedence over later overlapping matches
+    let mut kept: Vec<(usize, usize, PiiKind)> = Vec::with_capacity(matches.len());
+    for m in matches {
+        if kept.last().is_none_or(|last| m.0 >= last.1) {
+            kept.push(m);
+        }
+    }
+    kept
+}
+
+fn overlaps(matches: &[(usize, usize, PiiKind)], start: usize, end: usize) -> bool {
+    matches.iter().any(|(s, e, _)| start < *e && *s < end)
+}
+
+fn find_bearer_tokens(text: &str, matches: &mut Vec<(usize, usize, PiiKind)>) {
+    const PREFIX: &str = "bearer ";
+    let lower = text.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(at) = lower[from..].find(PREFIX) {
+        let start = from + at + PREFIX.len();
+        let len = text[start..]
+            .bytes()
+            .take_while(|b| b.is_ascii_alphanumeric() || b"-._~+/=".contains(b))
+            .count();
+        if len >= 16 {
+            matches.push((start, start + len, PiiKind::BearerToken));
+        }
+        from = start + len;
+    }
+}
+
+/// Find JWTs and AWS access key IDs among the words of a value.
+fn find_tokens(text: &str, matches: &mut Vec<(usize, usize, PiiKind)>) {
+    let bytes = text.as_bytes();
+    let is_token = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.';
+    let mut i = 0;
+    while i < bytes.len() {
+        if !is_token(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && is_token(bytes[i]) {
+            i += 1;
+        }
+        let word = &text[start..i];
+        if overlaps(matches, start, i) {
+            continue;
+        }
+        let segments: Vec<_> = word.split('.').collect();
+        if word.starts_with("eyJ") && segments.len() == 3 && segments.iter().all(|s| s.len() >= 2) {
+            matches.push((start, i, PiiKind::Jwt));
+        } else if word.len() == 20
+            && (word.starts_with("AKIA") || word.starts_with("ASIA"))
+            && word
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            matches.push((start, i, PiiKind::AwsAccessKey));
+        }
+    }
+}
+
+fn find_emails(text: &str, matches: &mut Vec<(usize, usize, PiiKind)>) {
+    let bytes = text.as_bytes();
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b == b'.' || b == b'-';
+    for (at, _) in text.match_indices('@') {
+        let mut start = at;
+        while start > 0 && is_local(bytes[start - 1]) {
+            start -= 1;
+        }
+        let mut end = at + 1;
+        while end < bytes.len() && is_domain(bytes[end]) {
+            end += 1;
+        }
+        // A trailing dot ends a sentence rather than the domain
+        while end > at + 1 && bytes[end - 1] == b'.' {
+            end -= 1;
+        }
+        let domain = &text[at + 1..end];
+        let valid_domain = domain.rsplit_once('.').is_some_and(|(host, tld)| {
+            !host.is_empty() && tld.len() >= 2 && tld.bytes().all(|b| b.is_ascii_alphabetic())
+        });
+        if start < at && valid_domain && !overlaps(matches, start, end) {
+            matches.push((start, end, PiiKind::Email));
+        }
+    }
+}
+
+fn find_credit_cards(text: &str, matches: &mut Vec<(usize, usize, PiiKind)>) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() || (i > 0 && bytes[i - 1].is_ascii_alphanumeric()) {
+            i += 1;
+            continue;
+        }
+        // Digits, optionally separated by single spaces or dashes
+        let start = i;
+        let mut digits = Vec::new();
+        let mut end = i;
+        while i < bytes.len() {
+            if bytes[i].is_ascii_digit() {
+                digits.push(bytes[i] - b'0');
+                i += 1;
+                end = i;
+            } else if (bytes[i] == b' ' || bytes[i] == b'-')
+                && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)
+            {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        let bounded = !bytes.get(end).is_some_and(u8::is_ascii_alphanumeric);
+        if bounded
+            && (13..=19).contains(&digits.len())
+            && luhn(&digits)
+            && !overlaps(matches, start, end)
+        {
+            matches.push((start, end, PiiKind::CreditCard));
+        }
+    }
+}
+
+fn luhn(digits: &[u8]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| {
+            let d = u32::from(*d);
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
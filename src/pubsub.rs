@@ -0,0 +1,179 @@
+//! Publishing records to a Google Cloud Pub/Sub topic, for deployments whose log bus is a
+//! topic rather than files.
+//!
+//! Records are published in batches on a background thread, with the value of a field as their
+//! ordering key, and batches that fail are retried with exponential backoff. Requests must be
+//! authorized with an OAuth 2.0 access token, so they are sent by a transport provided by the
+//! application, e.g. wrapping an HTTP client with Google application default credentials.
+//!
+//! ```rust
+//! use tracing_ndjson::pubsub::PubSubSink;
+//!
+//! let sink = PubSubSink::new("my-project", "app-logs", |url, payload| {
+//!     // POST the payload to the URL with an `Authorization: Bearer <token>` header,
+//!     // returning the response body
+//!     # let _ = (url, payload);
+//!     Ok(b"{\"messageIds\":[]}".to_vec())
+//! })
+//! .with_ordering_key("request.id");
+//! let builder = tracing_ndjson::builder().with_record_sink(sink);
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::sink::{RecordMeta, RecordSink};
+use crate::stream::{Batcher, Batching, Entry, Put, Transport};
+
+/// The maximum number of messages in a publish request.
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Publishes records to a Pub/Sub topic in batches on a background thread.
+/// Use it with `Builder::with_record_sink` or `sink::Sink::custom`. Clones publish records with
+/// the same background thread.
+#[derive(Clone)]
+pub struct PubSubSink {
+    url: String,
+    ordering_key: Option<String>,
+    attributes: Map<String, Value>,
+    batching: Batching,
+    transport: Transport,
+    batcher: Arc<Batcher>,
+}
+
+impl std::fmt::Debug for PubSubSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PubSubSink")
+            .field("url", &self.url)
+            .field("ordering_key", &self.ordering_key)
+            .field("attributes", &self.attributes)
+            .field("batching", &self.batching)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PubSubSink {
+    /// Publish records to `topic` in `project`, sending each request with `transport`, which is
+    /// called with the URL and the JSON payload and returns the response body. By default
+    /// messages have no ordering key, batches of up to 1000 records are published at least
+    /// every second, and each batch is attempted 3 times, 100ms apart and doubling.
+    pub fn new(
+        project: &str,
+        topic: &str,
+        transport: impl Fn(&str, &[u8]) -> std::io::Result<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            url: format!(
+                "https://pubsub.googleapis.com/v1/projects/{}/topics/{}:publish",
+                project, topic
+            ),
+            ordering_key: None,
+            attributes: Map::new(),
+            batching: Batching {
+                size: MAX_BATCH_SIZE,
+                interval: Duration::from_secs(1),
+                attempts: 3,
+                backoff: Duration::from_millis(100),
+            },
+            transport: Arc::new(transport),
+            batcher: Arc::new(Batcher::new("tracing-ndjson-pubsub")),
+        }
+    }
+
+    /// Use the value of a top-level field of each record as its ordering key, e.g.
+    /// `request.id`, so subscriptions with message ordering receive records with the same value
+    /// in order. Records without the field have no ordering key.
+    pub fn with_ordering_key(mut self, field: impl Into<String>) -> Self {
+        self.ordering_key = Some(field.into());
+        self
+    }
+
+    /// Add an attribute with the same value on every message, e.g. the service name, for
+    /// subscription filters.
+    pub fn with_attribute(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.insert(name.into(), json!(value.into()));
+        self
+    }
+
+    /// Publish a batch when it reaches `size` records, at most 1000, or `interval` after the
+    /// last publish.
+    pub fn with_batch(mut self, size: usize, interval: Duration) -> Self {
+        self.batching.size = size.clamp(1, MAX_BATCH_SIZE);
+        self.batching.interval = interval;
+        self
+    }
+
+    /// Attempt to publish each batch up to `attempts` times, waiting `backoff` before the first
+    /// retry and doubling it before each further retry.
+    pub fn with_retry(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.batching.attempts = attempts.max(1);
+        self.batching.backoff = backoff;
+        self
+    }
+
+    /// Returns the number of records dropped because too many were queued, or after the last
+    /// attempt to publish them failed.
+    pub fn dropped_records(&self) -> u64 {
+        self.batcher.dropped()
+    }
+
+    fn put(&self) -> Put {
+        let url = self.url.clone();
+        let attributes = self.attributes.clone();
+        let transport = self.transport.clone();
+        Box::new(move |batch| {
+            let payload = payload(&attributes, batch);
+            let response = transport(&url, payload.as_bytes())?;
+            // Publishing a batch succeeds or fails as a whole
+            let response: Value = serde_json::from_slice(&response)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            match response["messageIds"].as_array() {
+                Some(ids) if ids.len() == batch.len() => Ok(Vec::new()),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "messages not published",
+                )),
+            }
+        })
+    }
+}
+
+impl RecordSink for PubSubSink {
+    fn write_record(&self, bytes: &[u8], _meta: &RecordMeta) -> std::io::Result<()> {
+        let key = self
+            .ordering_key
+            .as_ref()
+            .and_then(|field| crate::stream::partition_key(bytes, field))
+            .filter(|key| !key.is_empty());
+        let entry = Entry {
+            key,
+            data: bytes.to_vec(),
+        };
+        self.batcher.send(self.batching, entry, || self.put());
+        Ok(())
+    }
+}
+
+/// Build a publish request.
+fn payload(attributes: &Map<String, Value>, batch: &[Entry]) -> String {
+    let messages: Vec<Value> = batch
+        .iter()
+        .map(|entry| {
+            let mut message = Map::new();
+            message.insert(
+                "data".to_string(),
+                json!(crate::stream::base64(&entry.data)),
+            );
+            if let Some(key) = &entry.key {
+                message.insert("orderingKey".to_string(), json!(key));
+            }
+            if !attributes.is_empty() {
+                message.insert("attributes".to_string(), json!(attributes));
+            }
+            json!(message)
+        })
+        .collect();
+    json!({ "messages": messages }).to_string()
+}
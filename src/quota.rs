@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::EventFields;
+
+/// The number of tracked values above which values without overflow in an expired window are
+/// forgotten.
+const MAX_TRACKED: usize = 10_000;
+
+/// Limits the number of records emitted per window for each value of a field, e.g. `tenant_id`.
+#[derive(Debug)]
+pub(crate) struct FieldQuota {
+    field: String,
+    limit: u64,
+    window: Duration,
+    windows: Mutex<HashMap<String, QuotaWindow>>,
+    /// Values whose window ended with dropped records, not yet reported.
+    overflows: Mutex<Vec<(String, u64)>>,
+    has_overflows: AtomicBool,
+}
+
+#[derive(Debug)]
+struct QuotaWindow {
+    start: Instant,
+    emitted: u64,
+    dropped: u64,
+}
+
+impl FieldQuota {
+    pub(crate) fn new(field: String, limit: u64, window: Duration) -> Self {
+        Self {
+            field,
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+            overflows: Mutex::new(Vec::new()),
+            has_overflows: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub(crate) fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns whether a record is emitted, counting it against the quota of its value of the
+    /// field. Records without the field are always emitted.
+    pub(crate) fn allow(&self, fields: &EventFields<'_>) -> bool {
+        let key = match fields.get(&self.field) {
+            Some(serde_json::Value::String(key)) => key.clone(),
+            Some(serde_json::Value::Null) | None => return true,
+            Some(other) => other.to_string(),
+        };
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        if windows.len() >= MAX_TRACKED {
+            windows.retain(|_, w| w.dropped > 0 || now.duration_since(w.start) < self.window);
+        }
+        if !windows.contains_key(&key) {
+            windows.insert(
+                key.clone(),
+                QuotaWindow {
+                    start: now,
+                    emitted: 0,
+                    dropped: 0,
+                },
+            );
+        }
+        let window = windows.get_mut(&key).expect("inserted");
+        if now.duration_since(window.start) >= self.window {
+            if window.dropped > 0 {
+                self.overflows
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push((key, window.dropped));
+                self.has_overflows.store(true, Ordering::Relaxed);
+            }
+            *window = QuotaWindow {
+                start: now,
+                emitted: 0,
+                dropped: 0,
+            };
+        }
+        if window.emitted < self.limit {
+            window.emitted += 1;
+            true
+        } else {
+            window.dropped += 1;
+            false
+        }
+    }
+
+    /// Returns the values whose window ended with dropped records since the last call, with the
+    /// number of dropped records.
+    pub(crate) fn take_overflows(&self) -> Vec<(String, u64)> {
+        if !self.has_overflows.swap(false, Ordering::Relaxed) {
+            return Vec::new();
+        }
+        std::mem::take(&mut *self.overflows.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
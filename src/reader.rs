@@ -0,0 +1,401 @@
+//! Utilities for parsing records written by this crate back into a typed [`Record`].
+//!
+//! This is useful for log replay tools and for end-to-end tests against real output.
+//!
+//! ```rust
+//! let reader = tracing_ndjson::builder().reader();
+//! let record = reader
+//!     .parse_line(r#"{"level":"info","target":"app","timestamp":"2023-10-08T03:30:52Z","message":"Hello, world!","life":42}"#)
+//!     .unwrap();
+//! assert_eq!(record.level, Some(tracing_core::Level::INFO));
+//! assert_eq!(record.message.as_deref(), Some("Hello, world!"));
+//! assert_eq!(record.fields["life"], 42);
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::{Map, Value};
+
+use crate::{Casing, TimestampFormat};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("record is not a JSON object")]
+    NotAnObject,
+    #[error("invalid level: {0}")]
+    InvalidLevel(String),
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+}
+
+/// A single parsed record.
+/// The built-in fields are parsed into their typed representation, all other fields are kept in `fields`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub level: Option<tracing_core::Level>,
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    pub target: Option<String>,
+    pub message: Option<String>,
+    pub fields: Map<String, Value>,
+}
+
+/// Parses records using the field names and timestamp format of a configured layer.
+/// Use `Builder::reader` to create a reader matching a `Builder`.
+#[derive(Debug, Clone)]
+pub struct Reader {
+    pub(crate) level_name: Cow<'static, str>,
+    pub(crate) message_name: Cow<'static, str>,
+    pub(crate) target_name: Cow<'static, str>,
+    pub(crate) timestamp_name: Cow<'static, str>,
+    pub(crate) timestamp_format: TimestampFormat,
+    pub(crate) level_value_casing: Casing,
+    /// Whether levels can be overridden by an event field, e.g. with `fatal`.
+    pub(crate) level_overrides: bool,
+    /// Whether levels can have a verbosity tier suffix, e.g. `trace2`.
+    pub(crate) verbosity_tiers: bool,
+    /// Whether levels are written as GCP severities, e.g. `WARNING`.
+    pub(crate) gcp_severities: bool,
+}
+
+impl Default for Reader {
+    fn default() -> Self {
+        crate::builder().reader()
+    }
+}
+
+impl Reader {
+    /// Parse a single record. Leading RFC 7464 record separators and surrounding whitespace are ignored.
+    pub fn parse_line(&self, line: &str) -> Result<Record, ReadError> {
+        let line = line.trim_start_matches('\u{1e}').trim();
+        let mut fields = match serde_json::from_str(line)? {
+            Value::Object(fields) => fields,
+            _ => return Err(ReadError::NotAnObject),
+        };
+
+        let level = match fields.remove(self.level_name.as_ref()) {
+            Some(Value::String(level)) => match self.parse_level(&level) {
+                Some(level) => Some(level),
+                // Overridden levels without a tracing severity are kept as they were written
+                None if self.level_overrides => {
+                    fields.insert(self.level_name.to_string(), Value::String(level));
+                    None
+                }
+                None => return Err(ReadError::InvalidLevel(level)),
+            },
+            Some(other) => return Err(ReadError::InvalidLevel(other.to_string())),
+            None => None,
+        };
+
+        let timestamp = match fields.remove(self.timestamp_name.as_ref()) {
+            Some(timestamp) => Some(self.parse_timestamp(&timestamp)?),
+            None => None,
+        };
+
+        let target = match fields.remove(self.target_name.as_ref()) {
+            Some(Value::String(target)) => Some(target),
+            Some(other) => Some(other.to_string()),
+            None => None,
+        };
+
+        let message = match fields.remove(self.message_name.as_ref()) {
+            Some(Value::String(message)) => Some(message),
+            Some(other) => Some(other.to_string()),
+            None => None,
+        };
+
+        Ok(Record {
+            level,
+            timestamp,
+            target,
+            message,
+            fields,
+        })
+    }
+
+    /// Parse all records from a reader, one per line. Empty lines are skipped.
+    pub fn records<'a, R: BufRead + 'a>(
+        &'a self,
+        reader: R,
+    ) -> impl Iterator<Item = Result<Record, ReadError>> + 'a {
+        reader.lines().filter_map(move |line| match line {
+            Ok(line) if line.trim_start_matches('\u{1e}').trim().is_empty() => None,
+            Ok(line) => Some(self.parse_line(&line)),
+            Err(e) => Some(Err(e.into())),
+        })
+    }
+
+    /// Render a single record in the human-readable format of the given `PrettyEncoder`.
+    pub fn format_pretty(
+        &self,
+        line: &str,
+        encoder: &crate::PrettyEncoder,
+    ) -> Result<String, ReadError> {
+        let line = line.trim_start_matches('\u{1e}').trim();
+        let record = match serde_json::from_str(line)? {
+            Value::Object(record) => record,
+            _ => return Err(ReadError::NotAnObject),
+        };
+        let names = crate::FieldNames {
+            level: &self.level_name,
+            message: &self.message_name,
+            target: &self.target_name,
+            timestamp: &self.timestamp_name,
+        };
+        let mut buf = Vec::new();
+        crate::Encoder::encode(encoder, &names, &record, &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    /// Parse a level name in any casing with an optional verbosity tier, a custom level value,
+    /// or a GCP severity or the severity an overridden level is named after, e.g. `WARNING` as
+    /// WARN and `fatal` as ERROR.
+    fn parse_level(&self, level: &str) -> Option<tracing_core::Level> {
+        if let Ok(level) = tracing_core::Level::from_str(level) {
+            return Some(level);
+        }
+        if self.verbosity_tiers {
+            let name = level.trim_end_matches(|c: char| c.is_ascii_digit());
+            if name.len() < level.len() {
+                if let Ok(level) = tracing_core::Level::from_str(name) {
+                    return Some(level);
+                }
+            }
+        }
+        if let Casing::Custom(values) = &self.level_value_casing {
+            if let Some((parsed, _)) = values.iter().find(|(_, value)| **value == level) {
+                return Some(*parsed);
+            }
+        }
+        if self.level_overrides || self.gcp_severities {
+            return severity_level(level);
+        }
+        None
+    }
+
+    fn parse_timestamp(&self, value: &Value) -> Result<chrono::DateTime<chrono::Utc>, ReadError> {
+        let invalid = || ReadError::InvalidTimestamp(value.to_string());
+        match (&self.timestamp_format, value) {
+            (TimestampFormat::Unix, Value::Number(n)) => {
+                chrono::DateTime::from_timestamp(n.as_i64().ok_or_else(invalid)?, 0)
+                    .ok_or_else(invalid)
+            }
+            (TimestampFormat::UnixMillis, Value::Number(n)) => {
+                chrono::DateTime::from_timestamp_millis(n.as_i64().ok_or_else(invalid)?)
+                    .ok_or_else(invalid)
+            }
+            (TimestampFormat::Rfc3339 | TimestampFormat::Rfc3339Nanos, Value::String(s)) => {
+                chrono::DateTime::parse_from_rfc3339(s)
+                    .map(|t| t.with_timezone(&chrono::Utc))
+                    .map_err(|_| invalid())
+            }
+            (TimestampFormat::Custom(format), Value::String(s)) => {
+                chrono::DateTime::parse_from_str(s, format)
+                    .map(|t| t.with_timezone(&chrono::Utc))
+                    .or_else(|_| {
+                        chrono::NaiveDateTime::parse_from_str(s, format).map(|t| t.and_utc())
+                    })
+                    .map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Returns the tracing level of a syslog severity name in any casing, e.g. ERROR for `fatal` and
+/// INFO for `notice`.
+fn severity_level(name: &str) -> Option<tracing_core::Level> {
+    let level = match crate::layer::level_number(name)? {
+        0..=3 => tracing_core::Level::ERROR,
+        4 => tracing_core::Level::WARN,
+        5 | 6 => tracing_core::Level::INFO,
+        _ => tracing_core::Level::DEBUG,
+    };
+    Some(level)
+}
+
+/// Render a single record written with the default field names in the crate's colored,
+/// human-readable format. This makes it easy to build a small pretty-printer for NDJSON logs.
+///
+/// ```rust
+/// let line = r#"{"level":"info","target":"app","timestamp":"2023-10-08T03:30:52Z","message":"Hello, world!"}"#;
+/// let pretty = tracing_ndjson::reader::format_line_pretty(line).unwrap();
+/// assert!(pretty.contains("app: Hello, world!"));
+/// ```
+pub fn format_line_pretty(line: &str) -> Result<String, ReadError> {
+    Reader::default().format_pretty(line, &crate::PrettyEncoder::new())
+}
+
+/// The maximum number of fields, including the message, that a replayed event can carry.
+/// Additional fields are dropped.
+pub const MAX_REPLAY_FIELDS: usize = 32;
+
+/// Re-emit parsed records as tracing events on the given dispatcher, preserving level, target,
+/// message and fields. Records without a level are emitted at INFO.
+///
+/// Tracing events carry static metadata, so the metadata for every distinct combination of
+/// level, target and field names is created once and leaked. Replaying records with unbounded
+/// target or field name cardinality will therefore grow memory usage.
+///
+/// Returns the number of records replayed, or the first error encountered.
+///
+/// ```rust
+/// use tracing_subscriber::prelude::*;
+///
+/// let dispatch = tracing_core::Dispatch::new(tracing_subscriber::registry().with(tracing_ndjson::layer()));
+/// let reader = tracing_ndjson::reader::Reader::default();
+/// let input = std::io::Cursor::new(r#"{"level":"warn","target":"app","message":"disk almost full","free":1024}"#);
+/// let replayed = tracing_ndjson::reader::replay(reader.records(input), &dispatch).unwrap();
+/// assert_eq!(replayed, 1);
+/// ```
+pub fn replay<I>(records: I, dispatch: &tracing_core::Dispatch) -> Result<usize, ReadError>
+where
+    I: IntoIterator<Item = Result<Record, ReadError>>,
+{
+    let mut replayed = 0;
+    for record in records {
+        replay_record(&record?, dispatch);
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+fn replay_record(record: &Record, dispatch: &tracing_core::Dispatch) {
+    let level = record.level.unwrap_or(tracing_core::Level::INFO);
+    let target = record.target.as_deref().unwrap_or("tracing_ndjson::replay");
+
+    let message = record.message.clone().map(Value::String);
+    let mut values: Vec<(&str, &Value)> = message.iter().map(|m| ("message", m)).collect();
+    values.extend(record.fields.iter().map(|(k, v)| (k.as_str(), v)));
+    values.truncate(MAX_REPLAY_FIELDS);
+
+    let names = values.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+    let metadata = tracing_core::Callsite::metadata(callsite(level, target, &names));
+    if !dispatch.enabled(metadata) {
+        return;
+    }
+
+    let fields = metadata.fields().iter().collect::<Vec<_>>();
+    let values = values
+        .iter()
+        .map(|(_, v)| ReplayValue::from(*v))
+        .collect::<Vec<_>>();
+    if fields.is_empty() {
+        let value_set = metadata.fields().value_set(&[]);
+        dispatch.event(&tracing_core::Event::new(metadata, &value_set));
+        return;
+    }
+
+    // A value set needs a fixed size array, so unused slots are padded with empty values.
+    let entries: [(
+        &tracing_core::Field,
+        Option<&dyn tracing_core::field::Value>,
+    ); MAX_REPLAY_FIELDS] = std::array::from_fn(|i| match (fields.get(i), values.get(i)) {
+        (Some(field), Some(value)) => (field, Some(value.as_value())),
+        _ => (&fields[0], None),
+    });
+    let value_set = metadata.fields().value_set(&entries);
+    dispatch.event(&tracing_core::Event::new(metadata, &value_set));
+}
+
+/// A JSON value converted to the closest matching tracing primitive.
+enum ReplayValue {
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Str(String),
+}
+
+impl From<&Value> for ReplayValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Bool(b) => ReplayValue::Bool(*b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(u), _) => ReplayValue::U64(u),
+                (_, Some(i)) => ReplayValue::I64(i),
+                _ => ReplayValue::F64(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => ReplayValue::Str(s.clone()),
+            other => ReplayValue::Str(other.to_string()),
+        }
+    }
+}
+
+impl ReplayValue {
+    fn as_value(&self) -> &dyn tracing_core::field::Value {
+        match self {
+            ReplayValue::Bool(b) => b,
+            ReplayValue::U64(u) => u,
+            ReplayValue::I64(i) => i,
+            ReplayValue::F64(f) => f,
+            ReplayValue::Str(s) => s,
+        }
+    }
+}
+
+type CallsiteKey = (tracing_core::Level, String, Vec<String>);
+
+static CALLSITES: OnceLock<Mutex<HashMap<CallsiteKey, &'static ReplayCallsite>>> = OnceLock::new();
+
+struct ReplayCallsite {
+    metadata: OnceLock<tracing_core::Metadata<'static>>,
+}
+
+impl tracing_core::Callsite for ReplayCallsite {
+    fn set_interest(&self, _: tracing_core::subscriber::Interest) {}
+
+    fn metadata(&self) -> &tracing_core::Metadata<'_> {
+        self.metadata
+            .get()
+            .expect("metadata is set before the callsite is registered")
+    }
+}
+
+/// Returns the interned callsite for the given level, target and field names, creating it if needed.
+fn callsite(level: tracing_core::Level, target: &str, names: &[&str]) -> &'static ReplayCallsite {
+    let key = (
+        level,
+        target.to_string(),
+        names.iter().map(|n| n.to_string()).collect(),
+    );
+    let mut callsites = CALLSITES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(callsite) = callsites.get(&key) {
+        return callsite;
+    }
+
+    let callsite: &'static ReplayCallsite = Box::leak(Box::new(ReplayCallsite {
+        metadata: OnceLock::new(),
+    }));
+    let target: &'static str = Box::leak(target.to_string().into_boxed_str());
+    let names: &'static [&'static str] = Box::leak(
+        names
+            .iter()
+            .map(|n| &*Box::leak(n.to_string().into_boxed_str()))
+            .collect::<Vec<_>>()
+            .into_boxed_slice(),
+    );
+    let _ = callsite.metadata.set(tracing_core::Metadata::new(
+        "replay",
+        target,
+        level,
+        None,
+        None,
+        None,
+        tracing_core::field::FieldSet::new(names, tracing_core::identify_callsite!(callsite)),
+        tracing_core::metadata::Kind::EVENT,
+    ));
+    tracing_core::callsite::register(callsite);
+    callsites.insert(key, callsite);
+    callsite
+}
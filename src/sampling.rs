@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing_core::Level;
+
+/// Decides once per root span whether its events are sampled, i.e. emitted at all levels.
+#[derive(Debug)]
+pub(crate) struct SpanSampler {
+    one_in: u64,
+    unsampled_level: Level,
+    roots: AtomicU64,
+}
+
+/// The sampling decision of a span, stored in its extensions and inherited by its children.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sampled(pub(crate) bool);
+
+impl SpanSampler {
+    pub(crate) fn new(one_in: u64, unsampled_level: Level) -> Self {
+        Self {
+            one_in: one_in.max(1),
+            unsampled_level,
+            roots: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn one_in(&self) -> u64 {
+        self.one_in
+    }
+
+    pub(crate) fn unsampled_level(&self) -> Level {
+        self.unsampled_level
+    }
+
+    /// Decide whether a new root span is sampled. Every `one_in`th root span is sampled,
+    /// starting with the first.
+    pub(crate) fn decide(&self) -> Sampled {
+        Sampled(
+            self.roots
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.one_in),
+        )
+    }
+
+    /// Returns whether an event at the given level is emitted in a span with this decision.
+    pub(crate) fn allow(&self, sampled: Sampled, level: Level) -> bool {
+        sampled.0 || level <= self.unsampled_level
+    }
+}
@@ -0,0 +1,240 @@
+use serde_json::{json, Map, Value};
+
+use crate::{FieldType, JsonFormattingLayer, TimestampFormat, UnknownFields};
+
+/// Build a JSON Schema describing the records written by a layer with this configuration.
+/// Target overrides are not reflected.
+pub(crate) fn output_schema(layer: &JsonFormattingLayer) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![
+        json!(layer.level_name),
+        json!(layer.target_name),
+        json!(layer.timestamp_name),
+    ];
+
+    let mut levels: Vec<Value> = ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"]
+        .into_iter()
+        .map(|level| layer.level_value(level))
+        .collect();
+    // Levels with a verbosity tier, e.g. `trace2`
+    if let Some((_, max_tier)) = &layer.verbosity_field {
+        for level in ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"] {
+            for tier in 1..=*max_tier {
+                levels.push(layer.level_value(&format!("{}{}", level, tier)));
+            }
+        }
+    }
+    // Trace and debug share a severity in the GCP format
+    levels.dedup();
+    let level = if layer.level_override_field.is_some() {
+        // Overridden levels can be any string
+        json!({"type": "string", "examples": levels})
+    } else {
+        json!({"type": "string", "enum": levels})
+    };
+    properties.insert(layer.level_name.to_string(), level);
+    if let Some(name) = &layer.level_number_name {
+        properties.insert(
+            name.to_string(),
+            json!({"type": "integer", "minimum": 0, "maximum": 7}),
+        );
+    }
+    properties.insert(layer.target_name.to_string(), json!({"type": "string"}));
+    let timestamp = match &layer.timestamp_format {
+        TimestampFormat::Unix | TimestampFormat::UnixMillis => {
+            json!({"type": "integer", "minimum": 0})
+        }
+        TimestampFormat::Rfc3339 | TimestampFormat::Rfc3339Nanos => {
+            json!({"type": "string", "format": "date-time"})
+        }
+        #[cfg(feature = "chrono")]
+        TimestampFormat::Custom(_) => json!({"type": "string"}),
+    };
+    properties.insert(layer.timestamp_name.to_string(), timestamp);
+
+    if layer.file_names {
+        properties.insert("file".to_string(), json!({"type": "string"}));
+    }
+    if layer.line_numbers {
+        properties.insert("line".to_string(), json!({"type": "integer", "minimum": 0}));
+    }
+    if layer.caller {
+        properties.insert("caller".to_string(), json!({"type": "string"}));
+    }
+    if layer.thread_ids {
+        properties.insert(
+            "thread.id".to_string(),
+            json!({"type": "integer", "minimum": 0}),
+        );
+    }
+    if layer.thread_names {
+        properties.insert("thread.name".to_string(), json!({"type": "string"}));
+    }
+    if layer.current_span {
+        properties.insert("span".to_string(), json!({"type": "string"}));
+    }
+    if layer.span_list {
+        properties.insert(
+            "span_list".to_string(),
+            json!({"type": "array", "items": {"type": "string"}}),
+        );
+    }
+    if layer.gcp_format {
+        properties.insert(
+            "logging.googleapis.com/sourceLocation".to_string(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "file": {"type": "string"},
+                    "line": {"type": "string"},
+                    "function": {"type": "string"}
+                }
+            }),
+        );
+        required.push(json!("logging.googleapis.com/sourceLocation"));
+        properties.insert(
+            "logging.googleapis.com/spanId".to_string(),
+            json!({"type": "string"}),
+        );
+        properties.insert(
+            "logging.googleapis.com/trace".to_string(),
+            json!({"type": "string"}),
+        );
+    }
+    if layer.sampled_field {
+        properties.insert("sampled".to_string(), json!({"type": "boolean"}));
+    }
+    if layer.crate_field {
+        properties.insert("crate".to_string(), json!({"type": "string"}));
+        required.push(json!("crate"));
+    }
+    // Event and span fields can override global fields, so their values are only examples
+    for (name, value) in &layer.global_fields {
+        if !properties.contains_key(name) {
+            properties.insert(name.clone(), json!({"examples": [value]}));
+            required.push(json!(name));
+        }
+    }
+    if layer.time_partitions {
+        properties.insert(
+            "date".to_string(),
+            json!({"type": "string", "pattern": "^\\d{4}-\\d{2}-\\d{2}$"}),
+        );
+        properties.insert(
+            "hour".to_string(),
+            json!({"type": "integer", "minimum": 0, "maximum": 23}),
+        );
+        properties.insert(
+            "iso_week".to_string(),
+            json!({"type": "string", "pattern": "^\\d{4}-W\\d{2}$"}),
+        );
+        required.extend([json!("date"), json!("hour"), json!("iso_week")]);
+    }
+    if layer.fingerprint {
+        properties.insert(
+            "fingerprint".to_string(),
+            json!({"type": "string", "pattern": "^[0-9a-f]{16}$"}),
+        );
+        required.push(json!("fingerprint"));
+    }
+    if layer.provenance == Some(crate::Provenance::Object) {
+        properties.insert(
+            "_provenance".to_string(),
+            json!({"type": "object", "additionalProperties": {"type": "string"}}),
+        );
+        required.push(json!("_provenance"));
+    }
+
+    // Event and span fields are untyped unless declared, as any value can be recorded
+    let message = json!({"type": "string"});
+    let fields_schema = match &layer.allowed_fields {
+        Some(allowed) => {
+            let mut fields: Map<String, Value> = allowed
+                .iter()
+                .map(|name| (name.clone(), json!({})))
+                .collect();
+            for (name, field_type) in &layer.typed_fields {
+                if allowed.contains(name) {
+                    fields.insert(name.clone(), type_schema(*field_type));
+                }
+            }
+            fields.insert(layer.message_name.to_string(), message.clone());
+            json!({"type": "object", "properties": fields, "additionalProperties": false})
+        }
+        None => {
+            let mut fields: Map<String, Value> = layer
+                .typed_fields
+                .iter()
+                .map(|(name, field_type)| (name.clone(), type_schema(*field_type)))
+                .collect();
+            fields.insert(layer.message_name.to_string(), message.clone());
+            json!({"type": "object", "properties": fields})
+        }
+    };
+    let extra = matches!(
+        (&layer.allowed_fields, layer.unknown_fields),
+        (Some(_), UnknownFields::Extra)
+    );
+    if extra {
+        properties.insert("extra".to_string(), json!({"type": "object"}));
+    }
+
+    let additional_properties = if layer.flatten_fields {
+        if let Some(field_properties) = fields_schema["properties"].as_object() {
+            for (name, schema) in field_properties {
+                properties.entry(name.clone()).or_insert(schema.clone());
+            }
+        }
+        layer.allowed_fields.is_none()
+    } else {
+        properties.insert("fields".to_string(), fields_schema.clone());
+        required.push(json!("fields"));
+        match &layer.allowed_fields {
+            // Flattened span fields are still restricted by the allowlist
+            Some(_) if layer.flatten_spans => {
+                if let Some(field_properties) = fields_schema["properties"].as_object() {
+                    for (name, schema) in field_properties {
+                        if name != layer.message_name.as_ref() {
+                            properties.entry(name.clone()).or_insert(schema.clone());
+                        }
+                    }
+                }
+                false
+            }
+            Some(_) => false,
+            None => layer.flatten_spans,
+        }
+    };
+    if !layer.flatten_spans {
+        let mut span_schema = fields_schema;
+        if layer.span_depth {
+            span_schema["properties"]["span.depth"] = json!({"type": "integer", "minimum": 0});
+        }
+        properties.insert(
+            "spans".to_string(),
+            json!({"type": "array", "items": span_schema}),
+        );
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "tracing-ndjson record",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": additional_properties,
+    })
+}
+
+fn type_schema(field_type: FieldType) -> Value {
+    match field_type {
+        FieldType::Bool => json!({"type": "boolean"}),
+        FieldType::I32 => json!({"type": "integer", "minimum": i32::MIN, "maximum": i32::MAX}),
+        FieldType::I64 => json!({"type": "integer"}),
+        FieldType::U16 => json!({"type": "integer", "minimum": 0, "maximum": u16::MAX}),
+        FieldType::U32 => json!({"type": "integer", "minimum": 0, "maximum": u32::MAX}),
+        FieldType::U64 => json!({"type": "integer", "minimum": 0}),
+        FieldType::F64 => json!({"type": "number"}),
+        FieldType::String => json!({"type": "string"}),
+    }
+}
@@ -0,0 +1,153 @@
+//! Forwarding ERROR records to [Sentry](https://sentry.io) as Sentry events.
+//!
+//! Records are converted to the [Sentry event payload](https://develop.sentry.dev/sdk/data-model/event-payloads/)
+//! and handed to a transport, typically the Sentry SDK, so services don't need a second
+//! error-reporting integration. Configured fields become tags, other fields become extra data,
+//! and the enclosing spans become breadcrumbs.
+//!
+//! ```rust,ignore
+//! let forwarder = tracing_ndjson::sentry::SentryForwarder::new(|event| {
+//!     if let Ok(event) = serde_json::from_value::<sentry::protocol::Event>(event) {
+//!         sentry::capture_event(event);
+//!     }
+//! })
+//! .with_tags(["http.route", "tenant_id"]);
+//! let builder = tracing_ndjson::builder().with_sentry(forwarder);
+//! ```
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+use tracing_core::{Level, Metadata};
+
+use crate::forward::{Forward, SpanContext};
+use crate::FieldNames;
+
+type Transport = Arc<dyn Fn(Value) + Send + Sync>;
+
+/// Converts ERROR, and optionally WARN, records to Sentry events and passes them to a transport.
+#[derive(Clone)]
+pub struct SentryForwarder {
+    transport: Transport,
+    warnings: bool,
+    tags: HashSet<String>,
+}
+
+impl std::fmt::Debug for SentryForwarder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SentryForwarder")
+            .field("warnings", &self.warnings)
+            .field("tags", &self.tags)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SentryForwarder {
+    /// Pass Sentry events to `transport`, which sends them to Sentry.
+    pub fn new(transport: impl Fn(Value) + Send + Sync + 'static) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            warnings: false,
+            tags: HashSet::new(),
+        }
+    }
+
+    /// Set whether WARN records are forwarded in addition to ERROR records.
+    /// The default is false.
+    pub fn with_warnings(mut self, warnings: bool) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    /// Set the fields that are sent as tags rather than extra data.
+    pub fn with_tags<I, T>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.tags = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Convert a record to a Sentry event.
+    pub(crate) fn event(
+        &self,
+        level: Level,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        spans: &[SpanContext],
+    ) -> Value {
+        let mut tags = Map::new();
+        let mut extra = Map::new();
+        for (name, value) in record {
+            if [names.level, names.target, names.timestamp, names.message].contains(&name.as_str())
+            {
+                continue;
+            }
+            if self.tags.contains(name) {
+                let tag = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                tags.insert(name.clone(), json!(tag));
+            } else {
+                extra.insert(name.clone(), value.clone());
+            }
+        }
+        let breadcrumbs: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                json!({
+                    "type": "default",
+                    "category": span.target,
+                    "message": span.name,
+                    "data": span.fields,
+                })
+            })
+            .collect();
+
+        json!({
+            "event_id": event_id(),
+            "timestamp": crate::clock::now_nanos() as f64 / 1e9,
+            "platform": "other",
+            "level": if level == Level::ERROR { "error" } else { "warning" },
+            "logger": record.get(names.target),
+            "message": {"formatted": record.get(names.message).and_then(Value::as_str).unwrap_or_default()},
+            "tags": tags,
+            "extra": extra,
+            "breadcrumbs": {"values": breadcrumbs},
+        })
+    }
+}
+
+impl Forward for SentryForwarder {
+    fn forward(
+        &self,
+        metadata: &Metadata<'_>,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        spans: &[SpanContext],
+    ) {
+        let level = *metadata.level();
+        if level == Level::ERROR || (self.warnings && level == Level::WARN) {
+            (self.transport)(self.event(level, names, record, spans));
+        }
+    }
+}
+
+/// Generate a random event ID: 32 lowercase hex characters.
+fn event_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let state = std::collections::hash_map::RandomState::new();
+    let mut id = String::with_capacity(32);
+    for _ in 0..2 {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_i64(crate::clock::now_nanos());
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id
+}
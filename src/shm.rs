@@ -0,0 +1,331 @@
+//! A shared-memory ring for handing records to a collector process without pipe backpressure.
+//!
+//! `ShmWriter` appends each record to a ring in a memory-mapped file, e.g. under `/dev/shm`, and
+//! `ShmReader` drains it from another process. Writers never block: when the ring is full the
+//! record is dropped and counted, so a stalled collector can't stall the application.
+//! The ring has a single consumer. Writers in one process are serialized, so it should also
+//! have a single producing process.
+//!
+//! ```rust,no_run
+//! use tracing_subscriber::fmt::MakeWriter;
+//!
+//! let writer = tracing_ndjson::shm::ShmWriter::create("/dev/shm/app.ring", 1 << 20)?;
+//! std::io::Write::write_all(&mut writer.make_writer(), b"{\"message\":\"hello\"}\n")?;
+//!
+//! // In the collector process
+//! let mut reader = tracing_ndjson::shm::ShmReader::open("/dev/shm/app.ring")?;
+//! while let Some(record) = reader.read_record()? {
+//!     println!("{}", String::from_utf8_lossy(&record).trim_end());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::raw::{c_int, c_void};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+const MAGIC: u64 = u64::from_le_bytes(*b"NDJSONRG");
+/// The header holds the magic, the capacity, the total bytes written and read, and the number of
+/// dropped records, followed by the data area.
+const HEADER_LEN: usize = 64;
+const LEN_PREFIX: usize = 4;
+
+/// A memory-mapped ring file.
+#[derive(Debug)]
+struct Ring {
+    ptr: *mut u8,
+    len: usize,
+    capacity: u64,
+    _file: File,
+}
+
+// SAFETY: the mapping is only accessed through atomics and the header protocol, where the
+// producer only writes free space and the consumer only reads published records.
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn map(file: File, len: usize) -> io::Result<Self> {
+        // SAFETY: the file is at least `len` bytes long, and the mapping is unmapped on drop.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+            capacity: (len - HEADER_LEN) as u64,
+            _file: file,
+        })
+    }
+
+    fn header(&self, index: usize) -> &AtomicU64 {
+        // SAFETY: the mapping is page-aligned and the header fits in the mapping.
+        unsafe { &*self.ptr.cast::<AtomicU64>().add(index) }
+    }
+
+    fn magic(&self) -> &AtomicU64 {
+        self.header(0)
+    }
+
+    fn stored_capacity(&self) -> &AtomicU64 {
+        self.header(1)
+    }
+
+    fn written(&self) -> &AtomicU64 {
+        self.header(2)
+    }
+
+    fn read(&self) -> &AtomicU64 {
+        self.header(3)
+    }
+
+    fn dropped(&self) -> &AtomicU64 {
+        self.header(4)
+    }
+
+    /// Returns the number of bytes of records not read yet. The positions are written by the
+    /// other process, so they are checked before they are trusted.
+    fn unread(&self, written: u64, read: u64) -> io::Result<u64> {
+        written
+            .checked_sub(read)
+            .filter(|unread| *unread <= self.capacity)
+            .ok_or_else(|| corrupt("positions out of range"))
+    }
+
+    /// Copy bytes into the data area at a position, wrapping around the end.
+    fn copy_in(&self, position: u64, bytes: &[u8]) {
+        let start = (position % self.capacity) as usize;
+        let first = bytes.len().min(self.capacity as usize - start);
+        // SAFETY: both copies stay within the data area.
+        unsafe {
+            let data = self.ptr.add(HEADER_LEN);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(start), first);
+            std::ptr::copy_nonoverlapping(bytes[first..].as_ptr(), data, bytes.len() - first);
+        }
+    }
+
+    /// Copy bytes out of the data area at a position, wrapping around the end.
+    fn copy_out(&self, position: u64, bytes: &mut [u8]) {
+        let start = (position % self.capacity) as usize;
+        let first = bytes.len().min(self.capacity as usize - start);
+        // SAFETY: both copies stay within the data area.
+        unsafe {
+            let data = self.ptr.add(HEADER_LEN);
+            std::ptr::copy_nonoverlapping(data.add(start), bytes.as_mut_ptr(), first);
+            let len = bytes.len() - first;
+            std::ptr::copy_nonoverlapping(data, bytes[first..].as_mut_ptr(), len);
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created with this length and is no longer referenced.
+        unsafe {
+            munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// Appends records to a shared-memory ring. Implements `MakeWriter`, and each writer it makes
+/// appends everything written to it as a single record when dropped.
+#[derive(Debug, Clone)]
+pub struct ShmWriter {
+    ring: Arc<Ring>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl ShmWriter {
+    /// Create a ring file at `path` with room for `capacity` bytes of records, replacing any
+    /// existing file. Each record takes 4 bytes in addition to its length.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let len = HEADER_LEN + capacity.max(LEN_PREFIX + 1);
+        file.set_len(len as u64)?;
+        let ring = Ring::map(file, len)?;
+        ring.stored_capacity()
+            .store(ring.capacity, Ordering::Relaxed);
+        ring.magic().store(MAGIC, Ordering::Release);
+        Ok(Self {
+            ring: Arc::new(ring),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Returns the number of records dropped because the ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped().load(Ordering::Relaxed)
+    }
+
+    /// Append a record, returning false if it was dropped because the ring is full. Fails if the
+    /// ring was corrupted, e.g. by the reading process.
+    pub fn append(&self, record: &[u8]) -> io::Result<bool> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let ring = &self.ring;
+        let needed = (LEN_PREFIX + record.len()) as u64;
+        let written = ring.written().load(Ordering::Relaxed);
+        let read = ring.read().load(Ordering::Acquire);
+        let unread = ring.unread(written, read)?;
+        if record.len() > u32::MAX as usize || ring.capacity - unread < needed {
+            ring.dropped().fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+        ring.copy_in(written, &(record.len() as u32).to_le_bytes());
+        ring.copy_in(written + LEN_PREFIX as u64, record);
+        ring.written().store(written + needed, Ordering::Release);
+        Ok(true)
+    }
+}
+
+/// A writer made by `ShmWriter` that appends its contents as one record when dropped.
+#[derive(Debug)]
+pub struct ShmRecordWriter<'a> {
+    writer: &'a ShmWriter,
+    buf: Vec<u8>,
+}
+
+impl io::Write for ShmRecordWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.writer.append(&std::mem::take(&mut self.buf))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ShmRecordWriter<'_> {
+    fn drop(&mut self) {
+        let _ = io::Write::flush(self);
+    }
+}
+
+impl<'a> MakeWriter<'a> for ShmWriter {
+    type Writer = ShmRecordWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ShmRecordWriter {
+            writer: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Drains records from a shared-memory ring created by `ShmWriter`.
+#[derive(Debug)]
+pub struct ShmReader {
+    ring: Ring,
+}
+
+impl ShmReader {
+    /// Open an existing ring file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len <= HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a tracing-ndjson ring",
+            ));
+        }
+        let ring = Ring::map(file, len)?;
+        if ring.magic().load(Ordering::Acquire) != MAGIC
+            || ring.stored_capacity().load(Ordering::Relaxed) != ring.capacity
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a tracing-ndjson ring",
+            ));
+        }
+        Ok(Self { ring })
+    }
+
+    /// Returns the next record, or None if the ring is empty. Fails without reading anything if
+    /// the ring was corrupted, e.g. by the writing process.
+    pub fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let ring = &self.ring;
+        let read = ring.read().load(Ordering::Relaxed);
+        let written = ring.written().load(Ordering::Acquire);
+        let unread = ring.unread(written, read)?;
+        if unread == 0 {
+            return Ok(None);
+        }
+        let mut len = [0; LEN_PREFIX];
+        if unread >= LEN_PREFIX as u64 {
+            ring.copy_out(read, &mut len);
+        }
+        let len = u64::from(u32::from_le_bytes(len));
+        if unread < LEN_PREFIX as u64 || len > unread - LEN_PREFIX as u64 {
+            return Err(corrupt("record length out of range"));
+        }
+        let mut record = vec![0; len as usize];
+        ring.copy_out(read + LEN_PREFIX as u64, &mut record);
+        ring.read()
+            .store(read + LEN_PREFIX as u64 + len, Ordering::Release);
+        Ok(Some(record))
+    }
+
+    /// Returns the number of records the writer dropped because the ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped().load(Ordering::Relaxed)
+    }
+}
+
+impl Iterator for ShmReader {
+    type Item = io::Result<Vec<u8>>;
+
+    /// Returns the next record, or None if the ring is currently empty. The iterator can be
+    /// resumed after it returns None.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt ring: {}", message),
+    )
+}
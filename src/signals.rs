@@ -0,0 +1,417 @@
+//! Emergency shutdown records on fatal signals.
+//!
+//! `install` registers handlers for SIGTERM, SIGINT and SIGSEGV with `sigaction`, keeping the
+//! handlers installed before them, e.g. by the application or its async runtime, and chaining
+//! to them, so graceful shutdown keeps working. Call it after the application has installed its
+//! own handlers: handlers installed later only run ours if they chain to previous handlers.
+//! Signals that are ignored, e.g. SIGINT under `nohup`, stay ignored.
+//!
+//! On SIGTERM and SIGINT the handler only wakes a background thread, which writes a `shutdown`
+//! record to the configured output like any other record, then writes the records retained by
+//! coalescing and waits up to 5 seconds for records queued for non-blocking output to be
+//! written. Without a previous handler, the thread then terminates the process with the signal,
+//! as it would have without us, and a second signal terminates it immediately. With a previous
+//! handler, it is called right away and decides when the process exits.
+//!
+//! SIGSEGV can't be deferred to another thread, so the handler writes a record rendered up front
+//! with `write(2)`, which is async-signal-safe, directly to stdout or the file records are
+//! written to, before chaining to the previous handler. That record carries no timestamp, since
+//! the current time cannot be formatted safely inside a signal handler.
+//!
+//! Records are lost when:
+//! - the process crashes with SIGSEGV: records queued for non-blocking output, and the shutdown
+//!   record itself for outputs other than stdout and a file;
+//! - they are batched by sinks with their own background threads, e.g. Loki, Kinesis, Pub/Sub,
+//!   webhooks and email digests;
+//! - they are DEBUG and TRACE records retained by the flight recorder, which are only written
+//!   when an ERROR occurs.
+//!
+//! `install_crash_dump` additionally writes the latest records, retained in a pre-allocated ring
+//! enabled with `Builder::with_crash_ring`, to a file when the process crashes with SIGSEGV,
+//! SIGBUS, SIGILL, SIGFPE or SIGABRT, so the last moments before a crash are kept even if stdout
+//! is lost with the process.
+
+use std::cell::UnsafeCell;
+use std::os::fd::{IntoRawFd, RawFd};
+use std::os::raw::{c_int, c_void};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::{Builder, JsonFormattingLayer};
+
+/// How long the background thread waits for queued records to be written after a signal.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the handlers write on shutdown.
+struct Shutdown {
+    layer: JsonFormattingLayer,
+    /// The record written on SIGSEGV, rendered up front.
+    segv_record: Vec<u8>,
+    /// The file descriptor of stdout or the file records are written to, if any.
+    fd: Option<RawFd>,
+    /// The write end of the pipe waking the background thread.
+    wake: RawFd,
+}
+
+static SHUTDOWN: OnceLock<Shutdown> = OnceLock::new();
+
+/// Set by the first SIGTERM or SIGINT.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// The handlers installed before ours, by signal number.
+static PREVIOUS: [OnceLock<libc::sigaction>; 32] = [const { OnceLock::new() }; 32];
+
+/// The ring of latest records and the file descriptor it is dumped to on a crash.
+static CRASH_DUMP: OnceLock<(Arc<CrashRing>, RawFd)> = OnceLock::new();
+
+/// Install handlers for SIGTERM, SIGINT and SIGSEGV that write a final `shutdown` record using
+/// the configuration and output of the given builder, so call it once the builder is fully
+/// configured. Only the first call has an effect.
+pub fn install(builder: &Builder) -> std::io::Result<()> {
+    if SHUTDOWN.get().is_some() {
+        return Ok(());
+    }
+    let layer = builder.config().clone();
+    let mut fds = [0; 2];
+    // SAFETY: pipe(2) writes two file descriptors into the array.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: fcntl(2) on file descriptors we own. The handler must never block on a full pipe.
+    unsafe {
+        libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+    }
+    let shutdown = Shutdown {
+        segv_record: render(&layer, "SIGSEGV", tracing_core::Level::ERROR),
+        fd: layer.output.raw_fd(),
+        wake: fds[1],
+        layer,
+    };
+    if SHUTDOWN.set(shutdown).is_err() {
+        // SAFETY: the file descriptors are ours and unused.
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+        return Ok(());
+    }
+    let wait = fds[0];
+    std::thread::Builder::new()
+        .name("tracing-ndjson-signals".to_string())
+        .spawn(move || shut_down(wait))?;
+    register(&[libc::SIGINT, libc::SIGSEGV, libc::SIGTERM])
+}
+
+/// Install handlers for SIGSEGV, SIGBUS, SIGILL, SIGFPE and SIGABRT that write the records
+/// retained in the crash ring of the given builder to `file`, oldest first, before the process
+/// terminates. The file is kept open for the lifetime of the process. Only the first call has
+/// an effect. Fails if the crash ring is not enabled using `Builder::with_crash_ring`.
+///
+/// ```rust,no_run
+/// let builder = tracing_ndjson::builder().with_crash_ring(256, 1024);
+/// let file = std::fs::File::create("/var/log/app/crash.ndjson")?;
+/// tracing_ndjson::signals::install_crash_dump(&builder, file)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn install_crash_dump(builder: &Builder, file: std::fs::File) -> std::io::Result<()> {
+    let Some(ring) = builder.config().crash_ring.clone() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the crash ring is not enabled",
+        ));
+    };
+    if CRASH_DUMP.get().is_some() {
+        return Ok(());
+    }
+    let fd = file.into_raw_fd();
+    if CRASH_DUMP.set((ring, fd)).is_err() {
+        return Ok(());
+    }
+    register(&[
+        libc::SIGSEGV,
+        libc::SIGBUS,
+        libc::SIGILL,
+        libc::SIGFPE,
+        libc::SIGABRT,
+    ])
+}
+
+/// Install the handler for signals it isn't installed for yet, keeping the previous handlers.
+fn register(signals: &[c_int]) -> std::io::Result<()> {
+    for &sig in signals {
+        let slot = &PREVIOUS[sig as usize];
+        if slot.get().is_some() {
+            continue;
+        }
+        // SAFETY: an all-zero sigaction is valid, and sigaction(2) only reads the current one.
+        let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+        if unsafe { libc::sigaction(sig, std::ptr::null(), &mut previous) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if previous.sa_sigaction == libc::SIG_IGN || slot.set(previous).is_err() {
+            continue;
+        }
+        // SAFETY: the handler only performs async-signal-safe operations, and the previous
+        // handler is stored before it can run.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void)
+                as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(sig, &action, std::ptr::null_mut()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the fields of the shutdown record for a signal.
+fn record(
+    layer: &JsonFormattingLayer,
+    signal: &str,
+    level: tracing_core::Level,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut record = layer.notice_record(level, "tracing_ndjson::signals", "shutdown");
+    record.insert("signal".to_string(), json!(signal));
+    record
+}
+
+/// Render the shutdown record for a signal, without a timestamp.
+pub(crate) fn render(
+    layer: &JsonFormattingLayer,
+    signal: &str,
+    level: tracing_core::Level,
+) -> Vec<u8> {
+    let mut record = record(layer, signal, level);
+    record.remove(layer.timestamp_name.as_ref());
+    layer.encode_record(&record).unwrap_or_default()
+}
+
+/// Wait for the first SIGTERM or SIGINT, write the shutdown record and flush buffered records,
+/// then terminate the process unless a previous handler takes care of that.
+fn shut_down(wait: RawFd) {
+    let mut sig = 0u8;
+    loop {
+        // SAFETY: read(2) into a local byte.
+        let n = unsafe { libc::read(wait, &mut sig as *mut u8 as *mut c_void, 1) };
+        if n == 1 {
+            break;
+        }
+        if n < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+            continue;
+        }
+        return;
+    }
+    let Some(shutdown) = SHUTDOWN.get() else {
+        return;
+    };
+    let sig = c_int::from(sig);
+    let name = if sig == libc::SIGINT {
+        "SIGINT"
+    } else {
+        "SIGTERM"
+    };
+    let layer = &shutdown.layer;
+    let record = record(layer, name, tracing_core::Level::WARN);
+    layer.write_record(&record, tracing_core::Level::WARN);
+    layer.flush(FLUSH_TIMEOUT);
+    if has_default_handler(sig) {
+        terminate(sig);
+    }
+}
+
+/// Returns whether no handler was installed for a signal before ours.
+fn has_default_handler(sig: c_int) -> bool {
+    PREVIOUS
+        .get(sig as usize)
+        .and_then(OnceLock::get)
+        .is_some_and(|previous| previous.sa_sigaction == libc::SIG_DFL)
+}
+
+/// Restore the default disposition of a signal and raise it, so the process terminates (or
+/// dumps core) as it would have without us. Raised from a handler, the signal is delivered
+/// when the handler returns.
+fn terminate(sig: c_int) {
+    // SAFETY: sigaction(2), sigemptyset(3) and raise(3) are async-signal-safe.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = libc::SIG_DFL;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(sig, &action, std::ptr::null_mut());
+        libc::raise(sig);
+    }
+}
+
+/// Write a buffer to a file descriptor using only write(2).
+fn write_all(fd: c_int, buf: &[u8]) {
+    let mut written = 0;
+    while written < buf.len() {
+        // SAFETY: write(2) is async-signal-safe and the buffer outlives the call.
+        let n = unsafe {
+            libc::write(
+                fd,
+                buf[written..].as_ptr() as *const c_void,
+                buf.len() - written,
+            )
+        };
+        if n <= 0 {
+            break;
+        }
+        written += n as usize;
+    }
+}
+
+extern "C" fn handle(sig: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let shutdown = SHUTDOWN.get();
+    match sig {
+        libc::SIGINT | libc::SIGTERM => {
+            if let Some(shutdown) = shutdown {
+                if !SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+                    write_all(shutdown.wake, &[sig as u8]);
+                    // The background thread terminates the process once the record is written
+                    if has_default_handler(sig) {
+                        return;
+                    }
+                }
+            }
+        }
+        _ => {
+            if let Some(Shutdown {
+                segv_record,
+                fd: Some(fd),
+                ..
+            }) = shutdown.filter(|_| sig == libc::SIGSEGV)
+            {
+                write_all(*fd, segv_record);
+            }
+            if let Some((ring, fd)) = CRASH_DUMP.get() {
+                ring.dump(*fd);
+            }
+        }
+    }
+    chain(sig, info, context);
+}
+
+/// Call the handler installed before ours, or terminate the process if there was none.
+fn chain(sig: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let Some(previous) = PREVIOUS.get(sig as usize).and_then(OnceLock::get) else {
+        return;
+    };
+    match previous.sa_sigaction {
+        libc::SIG_DFL => terminate(sig),
+        libc::SIG_IGN => {}
+        // SAFETY: the previous handler was installed with this signature, as told by its flags.
+        handler if previous.sa_flags & libc::SA_SIGINFO != 0 => unsafe {
+            let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                std::mem::transmute(handler);
+            handler(sig, info, context)
+        },
+        handler => unsafe {
+            let handler: extern "C" fn(c_int) = std::mem::transmute(handler);
+            handler(sig)
+        },
+    }
+}
+
+/// A pre-allocated ring of the latest encoded records, readable from a signal handler.
+///
+/// Each slot is guarded by a sequence number that is odd while the slot is being written, so the
+/// handler never takes a lock that the interrupted thread may hold. Slots being written when the
+/// signal arrives are skipped, as are records written concurrently to the same slot.
+pub(crate) struct CrashRing {
+    slots: Box<[Slot]>,
+    slot_len: usize,
+    next: AtomicUsize,
+}
+
+struct Slot {
+    seq: AtomicU64,
+    len: AtomicUsize,
+    data: UnsafeCell<Box<[u8]>>,
+}
+
+// SAFETY: the data of a slot is only written by the thread that made its sequence number odd,
+// and only read while it is even.
+unsafe impl Sync for CrashRing {}
+
+impl std::fmt::Debug for CrashRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CrashRing")
+            .field("slots", &self.slots.len())
+            .field("slot_len", &self.slot_len)
+            .finish()
+    }
+}
+
+impl CrashRing {
+    pub(crate) fn new(slots: usize, slot_len: usize) -> Self {
+        let slot_len = slot_len.max(1);
+        Self {
+            slots: (0..slots.max(1))
+                .map(|_| Slot {
+                    seq: AtomicU64::new(0),
+                    len: AtomicUsize::new(0),
+                    data: UnsafeCell::new(vec![0; slot_len].into_boxed_slice()),
+                })
+                .collect(),
+            slot_len,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub(crate) fn slot_len(&self) -> usize {
+        self.slot_len
+    }
+
+    /// Retain an encoded record, truncated to the slot length, replacing the oldest record.
+    pub(crate) fn push(&self, line: &[u8]) {
+        let slot = &self.slots[self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len()];
+        let seq = slot.seq.load(Ordering::Relaxed);
+        if seq % 2 == 1
+            || slot
+                .seq
+                .compare_exchange(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        // SAFETY: the odd sequence number gives this thread exclusive access to the slot.
+        let data = unsafe { &mut *slot.data.get() };
+        let len = line.len().min(self.slot_len);
+        data[..len].copy_from_slice(&line[..len]);
+        if len < line.len() {
+            data[len - 1] = b'\n';
+        }
+        slot.len.store(len, Ordering::Relaxed);
+        slot.seq.store(seq + 2, Ordering::Release);
+    }
+
+    /// Write the retained records to a file descriptor, oldest first, using only
+    /// async-signal-safe operations.
+    pub(crate) fn dump(&self, fd: RawFd) {
+        let start = self.next.load(Ordering::Relaxed);
+        for i in 0..self.slots.len() {
+            let slot = &self.slots[(start + i) % self.slots.len()];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == 0 || seq % 2 == 1 {
+                continue;
+            }
+            let len = slot.len.load(Ordering::Relaxed);
+            // SAFETY: the slot is not being written, and a concurrent write to it only makes the
+            // record torn, which is acceptable while crashing.
+            let data = unsafe { &*slot.data.get() };
+            write_all(fd, &data[..len]);
+        }
+    }
+}
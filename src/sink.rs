@@ -0,0 +1,238 @@
+//! Writing each record to additional outputs, each with its own encoder and framing.
+//!
+//! Fields are collected once per event, and each sink only encodes the collected record, so
+//! writing records as NDJSON to a file and pretty-printed to the console costs one field
+//! collection pass instead of one per layer.
+//!
+//! Outputs are implementations of `RecordSink`, which receives each encoded and framed record.
+//! Implement it to write records somewhere this crate doesn't, e.g. a message bus, and use it
+//! with `Builder::with_record_sink` or `Sink::custom`. Stdout, files and TCP connections are
+//! provided.
+//!
+//! ```rust
+//! use tracing_ndjson::sink::Sink;
+//!
+//! let path = std::env::temp_dir().join("tracing-ndjson-doc-sink.ndjson");
+//! let builder = tracing_ndjson::builder()
+//!     .with_encoder(tracing_ndjson::PrettyEncoder::default())
+//!     .with_sink(Sink::file(tracing_ndjson::file::FileWriter::open(&path)?));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+use tracing_core::{Level, Metadata};
+
+#[cfg(feature = "chrono")]
+use crate::file::RollingFileWriter;
+use crate::file::{FileWriter, LevelFiles};
+use crate::forward::{Forward, SpanContext};
+use crate::output::Output;
+use crate::{Encoder, FieldNames, Framing, NdjsonEncoder};
+
+/// Describes a record written to a `RecordSink`.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct RecordMeta {
+    /// The level the record was emitted at.
+    pub level: Level,
+}
+
+impl RecordMeta {
+    pub fn new(level: Level) -> Self {
+        Self { level }
+    }
+}
+
+/// Writes encoded and framed records, e.g. to a file, a socket or a message bus.
+///
+/// ```rust
+/// use tracing_ndjson::sink::{RecordMeta, RecordSink};
+///
+/// struct Discard;
+///
+/// impl RecordSink for Discard {
+///     fn write_record(&self, _bytes: &[u8], _meta: &RecordMeta) -> std::io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let builder = tracing_ndjson::builder().with_record_sink(Discard);
+/// ```
+pub trait RecordSink: Send + Sync {
+    /// Write a complete record. Errors are counted in `Stats::write_errors`.
+    fn write_record(&self, bytes: &[u8], meta: &RecordMeta) -> io::Result<()>;
+}
+
+impl std::fmt::Debug for dyn RecordSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordSink").finish_non_exhaustive()
+    }
+}
+
+/// Writes records to stdout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stdout;
+
+impl RecordSink for Stdout {
+    fn write_record(&self, bytes: &[u8], _meta: &RecordMeta) -> io::Result<()> {
+        io::stdout().lock().write_all(bytes)
+    }
+}
+
+/// Writes records to a TCP connection, e.g. to a log shipper listening on a local port.
+/// Connects when the first record is written, and reconnects once when a write fails.
+#[derive(Debug)]
+pub struct TcpSink {
+    address: String,
+    timeout: Option<Duration>,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl TcpSink {
+    /// Write records to a connection to `address`, e.g. `127.0.0.1:5170`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            timeout: None,
+            stream: Mutex::new(None),
+        }
+    }
+
+    /// Fail connecting and writing after `timeout`.
+    /// The default is to wait as long as the operating system allows.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let stream = match self.timeout {
+            Some(timeout) => {
+                let address = self.address.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "address not resolved")
+                })?;
+                TcpStream::connect_timeout(&address, timeout)?
+            }
+            None => TcpStream::connect(&self.address)?,
+        };
+        stream.set_write_timeout(self.timeout)?;
+        Ok(stream)
+    }
+}
+
+impl RecordSink for TcpSink {
+    fn write_record(&self, bytes: &[u8], _meta: &RecordMeta) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(connected) = stream.as_mut() {
+            if connected.write_all(bytes).is_ok() {
+                return Ok(());
+            }
+        }
+        // Not connected yet, or the connection was closed
+        *stream = None;
+        let mut connected = self.connect()?;
+        connected.write_all(bytes)?;
+        *stream = Some(connected);
+        Ok(())
+    }
+}
+
+/// An additional output for event records, added using `Builder::with_sink`. Records are
+/// encoded as NDJSON with newline framing unless configured otherwise.
+#[derive(Clone)]
+pub struct Sink {
+    output: Output,
+    encoder: Arc<dyn Encoder>,
+    framing: Framing,
+}
+
+impl std::fmt::Debug for Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sink")
+            .field("output", &self.output)
+            .field("framing", &self.framing)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sink {
+    fn new(output: Output) -> Self {
+        Self {
+            output,
+            encoder: Arc::new(NdjsonEncoder),
+            framing: Framing::default(),
+        }
+    }
+
+    /// Write records to stdout.
+    pub fn stdout() -> Self {
+        Self::new(Output::Stdout)
+    }
+
+    /// Write records to a file.
+    pub fn file(file: FileWriter) -> Self {
+        Self::new(Output::File(file))
+    }
+
+    /// Write records to files named after the current time.
+    #[cfg(feature = "chrono")]
+    pub fn rolling_file(file: RollingFileWriter) -> Self {
+        Self::new(Output::RollingFile(file))
+    }
+
+    /// Write records to a separate file per level.
+    pub fn level_files(files: LevelFiles) -> Self {
+        Self::new(Output::LevelFiles(files))
+    }
+
+    /// Write records to a TCP connection.
+    pub fn tcp(sink: TcpSink) -> Self {
+        Self::custom(sink)
+    }
+
+    /// Write records to a custom `RecordSink`.
+    pub fn custom(sink: impl RecordSink + 'static) -> Self {
+        Self::new(Output::Custom(Arc::new(sink)))
+    }
+
+    /// Set the encoder used to render records for this sink.
+    /// The default is NdjsonEncoder.
+    pub fn with_encoder(mut self, encoder: impl Encoder + 'static) -> Self {
+        self.encoder = Arc::new(encoder);
+        self
+    }
+
+    /// Set how records are framed for this sink.
+    /// The default is Framing::Newline.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+}
+
+impl Forward for Sink {
+    fn forward(
+        &self,
+        metadata: &Metadata<'_>,
+        names: &FieldNames<'_>,
+        record: &Map<String, Value>,
+        _spans: &[SpanContext],
+    ) {
+        let mut buf = Vec::new();
+        if self.encoder.encode(names, record, &mut buf).is_err() {
+            return;
+        }
+        let mut line = Vec::with_capacity(buf.len() + 1);
+        self.framing.frame(&buf, &mut line);
+        let _ = self.output.write(&line, *metadata.level());
+    }
+
+    fn needs_spans(&self) -> bool {
+        false
+    }
+}
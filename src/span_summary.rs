@@ -0,0 +1,101 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::JsonFormattingLayer;
+
+/// The number of durations retained per interval. Later durations are still counted, and
+/// included in the maximum, but not in the percentiles.
+const MAX_SAMPLES: usize = 10_000;
+
+/// When a span with a summary or a canonical rollup was created, stored in its extensions.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SpanStarted(pub(crate) Instant);
+
+/// Aggregates the durations of the spans with a name, from creation to close, and writes a
+/// summary record at an interval.
+#[derive(Debug)]
+pub(crate) struct SpanSummary {
+    interval: Duration,
+    window: Mutex<Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    count: u64,
+    max: Duration,
+    durations: Vec<Duration>,
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            count: 0,
+            max: Duration::ZERO,
+            durations: Vec::new(),
+        }
+    }
+}
+
+impl SpanSummary {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            window: Mutex::new(Window::new()),
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Count the duration of a closed span, returning the summary record of the interval if it
+    /// has elapsed and starting a new interval.
+    pub(crate) fn record(
+        &self,
+        layer: &JsonFormattingLayer,
+        name: &str,
+        duration: Duration,
+    ) -> Option<serde_json::Map<String, serde_json::Value>> {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        window.count += 1;
+        window.max = window.max.max(duration);
+        if window.durations.len() < MAX_SAMPLES {
+            window.durations.push(duration);
+        }
+        if window.start.elapsed() < self.interval {
+            return None;
+        }
+        let mut window = std::mem::replace(&mut *window, Window::new());
+        window.durations.sort_unstable();
+        let percentile = |p: usize| {
+            let durations = &window.durations;
+            durations[(durations.len() * p / 100).min(durations.len() - 1)]
+        };
+
+        let mut record = layer.notice_record(
+            tracing_core::Level::INFO,
+            "tracing_ndjson::span_summary",
+            "span summary",
+        );
+        record.insert("span.name".to_string(), json!(name));
+        record.insert("count".to_string(), json!(window.count));
+        record.insert(
+            "p50_ns".to_string(),
+            json!(percentile(50).as_nanos() as u64),
+        );
+        record.insert(
+            "p95_ns".to_string(),
+            json!(percentile(95).as_nanos() as u64),
+        );
+        record.insert("max_ns".to_string(), json!(window.max.as_nanos() as u64));
+        record.insert(
+            "interval_ms".to_string(),
+            json!(window.start.elapsed().as_millis() as u64),
+        );
+        Some(record)
+    }
+}
@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use tracing_core::{callsite::Identifier, Level, Metadata};
+
+use crate::{JsonFormattingLayer, PiiFinding, PiiKind};
+
+/// Counters describing the output of a layer, shared by all clones of the layer.
+/// Retrieve them using `Builder::stats`.
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    /// Events written, indexed by level from TRACE to ERROR.
+    events: [AtomicU64; 5],
+    dropped: AtomicU64,
+    expired: AtomicU64,
+    dropped_fields: AtomicU64,
+    type_mismatches: AtomicU64,
+    bytes_written: AtomicU64,
+    write_errors: AtomicU64,
+    span_lookup_failures: AtomicU64,
+    slow_writes: AtomicU64,
+    slowest_write_nanos: AtomicU64,
+    /// Records queued for the background writer, as WARN and ERROR records and other records.
+    queue_depths: [AtomicU64; 2],
+    max_queue_depths: [AtomicU64; 2],
+    /// Records written, indexed by size bucket.
+    sizes: [AtomicU64; SIZE_BUCKETS.len() + 1],
+    largest_record: AtomicU64,
+    tracks_callsites: AtomicBool,
+    callsites: Mutex<HashMap<Identifier, CallsiteStats>>,
+    pii_findings: Mutex<HashMap<(Identifier, String, PiiKind), PiiFinding>>,
+    /// Events emitted, by target and level index.
+    #[cfg(feature = "metrics")]
+    targets: std::sync::Mutex<std::collections::BTreeMap<(String, usize), u64>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            events: Default::default(),
+            dropped: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            dropped_fields: AtomicU64::new(0),
+            type_mismatches: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            write_errors: AtomicU64::new(0),
+            span_lookup_failures: AtomicU64::new(0),
+            slow_writes: AtomicU64::new(0),
+            slowest_write_nanos: AtomicU64::new(0),
+            queue_depths: Default::default(),
+            max_queue_depths: Default::default(),
+            sizes: Default::default(),
+            largest_record: AtomicU64::new(0),
+            tracks_callsites: AtomicBool::new(false),
+            callsites: Default::default(),
+            pii_findings: Default::default(),
+            #[cfg(feature = "metrics")]
+            targets: Default::default(),
+        }
+    }
+}
+
+/// The upper bounds in bytes of the record size histogram buckets, followed by an unbounded
+/// bucket.
+const SIZE_BUCKETS: [u64; 11] = [
+    64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+
+/// Counters of the events emitted by a callsite, retrieved using `Stats::callsites`.
+#[derive(Debug, Clone)]
+pub struct CallsiteStats {
+    /// The metadata of the callsite, with its target, name, level, file and line.
+    pub metadata: &'static Metadata<'static>,
+    /// The number of events emitted.
+    pub events: u64,
+    /// The number of bytes emitted, before any output framing errors.
+    pub bytes: u64,
+    /// The number of events dropped, e.g. by the event filter or sampling.
+    pub dropped: u64,
+}
+
+fn level_index(level: Level) -> usize {
+    match level {
+        Level::TRACE => 0,
+        Level::DEBUG => 1,
+        Level::INFO => 2,
+        Level::WARN => 3,
+        Level::ERROR => 4,
+    }
+}
+
+impl Stats {
+    /// Returns the number of events written at the given level.
+    pub fn events(&self, level: Level) -> u64 {
+        self.events[level_index(level)].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of events written at all levels.
+    pub fn total_events(&self) -> u64 {
+        self.events.iter().map(|e| e.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns the number of events that were dropped instead of written.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of buffered records dropped because they were older than the record
+    /// time to live when they were due to be written. Expired records are also counted as
+    /// dropped.
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of fields dropped because they are not in the allowed fields, or
+    /// because they collide with built-in fields with `CollisionPolicy::PreferBuiltin`.
+    pub fn dropped_fields(&self) -> u64 {
+        self.dropped_fields.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of typed field values that could not be coerced to their type.
+    pub fn type_mismatches(&self) -> u64 {
+        self.type_mismatches.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes written.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of records that could not be written to the output.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a span or its stored fields could not be found, e.g. because
+    /// the span was created before the layer was added.
+    pub fn span_lookup_failures(&self) -> u64 {
+        self.span_lookup_failures.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of writes that blocked for longer than the write watchdog threshold.
+    /// Only tracked if enabled with `Builder::with_write_watchdog`.
+    pub fn slow_writes(&self) -> u64 {
+        self.slow_writes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the duration of the slowest write above the write watchdog threshold.
+    pub fn slowest_write(&self) -> Duration {
+        Duration::from_nanos(self.slowest_write_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of WARN and ERROR records and of other records queued for the
+    /// background writer of non-blocking output.
+    pub fn queue_depths(&self) -> (u64, u64) {
+        (
+            self.queue_depths[0].load(Ordering::Relaxed),
+            self.queue_depths[1].load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns the largest number of WARN and ERROR records and of other records that were
+    /// queued for the background writer of non-blocking output at once.
+    pub fn max_queue_depths(&self) -> (u64, u64) {
+        (
+            self.max_queue_depths[0].load(Ordering::Relaxed),
+            self.max_queue_depths[1].load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns the number of events emitted, by target and level.
+    #[cfg(feature = "metrics")]
+    pub fn events_by_target(&self) -> Vec<(String, Level, u64)> {
+        const LEVELS: [Level; 5] = [
+            Level::TRACE,
+            Level::DEBUG,
+            Level::INFO,
+            Level::WARN,
+            Level::ERROR,
+        ];
+        self.targets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|((target, level), count)| (target.clone(), LEVELS[*level], *count))
+            .collect()
+    }
+
+    /// Returns a histogram of the sizes of the records written in bytes, as pairs of the upper
+    /// bound of each bucket and the number of records in it. The last bucket is unbounded and
+    /// has an upper bound of `u64::MAX`.
+    pub fn record_sizes(&self) -> Vec<(u64, u64)> {
+        SIZE_BUCKETS
+            .iter()
+            .copied()
+            .chain([u64::MAX])
+            .zip(&self.sizes)
+            .map(|(le, count)| (le, count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Returns the size of the largest record written in bytes.
+    pub fn largest_record(&self) -> u64 {
+        self.largest_record.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time since the layer was created.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub(crate) fn record_event(&self, level: Level, bytes: usize) {
+        self.events[level_index(level)].fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        let bytes = bytes as u64;
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|le| bytes <= *le)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.sizes[bucket].fetch_add(1, Ordering::Relaxed);
+        self.largest_record.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_expired(&self, expired: u64) {
+        if expired > 0 {
+            self.expired.fetch_add(expired, Ordering::Relaxed);
+            self.dropped.fetch_add(expired, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_slow_write(&self, elapsed: Duration) {
+        self.slow_writes.fetch_add(1, Ordering::Relaxed);
+        self.slowest_write_nanos
+            .fetch_max(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_queue_depths(&self, (important, other): (usize, usize)) {
+        for (i, depth) in [important as u64, other as u64].into_iter().enumerate() {
+            self.queue_depths[i].store(depth, Ordering::Relaxed);
+            self.max_queue_depths[i].fetch_max(depth, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn track_callsites(&self) {
+        self.tracks_callsites.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn tracks_callsites(&self) -> bool {
+        self.tracks_callsites.load(Ordering::Relaxed)
+    }
+
+    /// Count an event emitted by a callsite, or dropped if `bytes` is None.
+    pub(crate) fn record_callsite(
+        &self,
+        metadata: &'static Metadata<'static>,
+        bytes: Option<usize>,
+    ) {
+        let mut callsites = self.callsites.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = callsites
+            .entry(metadata.callsite())
+            .or_insert_with(|| CallsiteStats {
+                metadata,
+                events: 0,
+                bytes: 0,
+                dropped: 0,
+            });
+        match bytes {
+            Some(bytes) => {
+                entry.events += 1;
+                entry.bytes += bytes as u64;
+            }
+            None => entry.dropped += 1,
+        }
+    }
+
+    /// Returns the counters of each callsite that emitted or dropped events, ordered by the
+    /// number of bytes emitted, largest first. Only tracked if enabled with
+    /// `Builder::with_callsite_stats` or `Builder::with_size_report`, otherwise empty.
+    pub fn callsites(&self) -> Vec<CallsiteStats> {
+        self.top_callsites(usize::MAX)
+    }
+
+    /// Returns the callsites that emitted the most bytes, largest first.
+    pub(crate) fn top_callsites(&self, top: usize) -> Vec<CallsiteStats> {
+        let mut callsites: Vec<_> = self
+            .callsites
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        callsites.sort_by_key(|callsite| std::cmp::Reverse(callsite.bytes));
+        callsites.truncate(top);
+        callsites
+    }
+
+    /// Count potential PII of a kind found in a field of an event of a callsite.
+    pub(crate) fn record_pii(
+        &self,
+        metadata: &'static Metadata<'static>,
+        field: &str,
+        kind: PiiKind,
+    ) {
+        let mut findings = self.pii_findings.lock().unwrap_or_else(|e| e.into_inner());
+        findings
+            .entry((metadata.callsite(), field.to_string(), kind))
+            .or_insert_with(|| PiiFinding {
+                metadata,
+                field: field.to_string(),
+                kind,
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    /// Returns the potential PII found in each field of each callsite, ordered by the number of
+    /// values found, most first. Only tracked if enabled with `Builder::with_pii_detection`,
+    /// otherwise empty.
+    pub fn pii_findings(&self) -> Vec<PiiFinding> {
+        let mut findings: Vec<_> = self
+            .pii_findings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        findings.sort_by_key(|finding| std::cmp::Reverse(finding.count));
+        findings
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub(crate) fn record_target(&self, target: &str, level: Level) {
+        #[cfg(feature = "metrics")]
+        {
+            *self
+                .targets
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry((target.to_string(), level_index(level)))
+                .or_default() += 1;
+        }
+    }
+
+    pub(crate) fn record_write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_span_lookup_failure(&self) {
+        self.span_lookup_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped_field(&self) {
+        self.dropped_fields.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_type_mismatch(&self) {
+        self.type_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes a summary record when the last clone of the layer is dropped.
+#[derive(Debug)]
+pub(crate) struct Summary {
+    pub(crate) layer: JsonFormattingLayer,
+}
+
+impl Summary {
+    pub(crate) fn record(&self) -> serde_json::Map<String, serde_json::Value> {
+        let layer = &self.layer;
+        let stats = &layer.stats;
+        let mut record =
+            layer.notice_record(Level::INFO, "tracing_ndjson::summary", "logging summary");
+        for level in [
+            Level::TRACE,
+            Level::DEBUG,
+            Level::INFO,
+            Level::WARN,
+            Level::ERROR,
+        ] {
+            record.insert(
+                format!("events.{}", level.as_str().to_lowercase()),
+                json!(stats.events(level)),
+            );
+        }
+        record.insert("events.total".to_string(), json!(stats.total_events()));
+        record.insert("dropped".to_string(), json!(stats.dropped()));
+        record.insert("dropped_fields".to_string(), json!(stats.dropped_fields()));
+        record.insert(
+            "type_mismatches".to_string(),
+            json!(stats.type_mismatches()),
+        );
+        record.insert("bytes_written".to_string(), json!(stats.bytes_written()));
+        record.insert("write_errors".to_string(), json!(stats.write_errors()));
+        record.insert(
+            "span_lookup_failures".to_string(),
+            json!(stats.span_lookup_failures()),
+        );
+        record.insert(
+            "uptime_ms".to_string(),
+            json!(stats.uptime().as_millis() as u64),
+        );
+        record
+    }
+}
+
+impl Drop for Summary {
+    fn drop(&mut self) {
+        self.layer.write_record(&self.record(), Level::INFO);
+    }
+}
+
+/// Writes a summary record when dropped, returned by `Builder::layer_with_summary_guard`.
+///
+/// Like the `WorkerGuard` of `tracing_appender`, keep it alive until the end of `main`, e.g.
+/// `let _guard = ...;`. Binding it to `_` drops it, and writes the summary, immediately.
+#[must_use = "the summary is written when the guard is dropped"]
+#[derive(Debug)]
+pub struct SummaryGuard {
+    pub(crate) _summary: Summary,
+}
+
+/// Writes a record naming the callsites that emitted the most bytes at an interval.
+#[derive(Debug)]
+pub(crate) struct SizeReport {
+    interval: Duration,
+    top: usize,
+    last: Mutex<Instant>,
+}
+
+impl SizeReport {
+    pub(crate) fn new(interval: Duration, top: usize) -> Self {
+        Self {
+            interval,
+            top,
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub(crate) fn top(&self) -> usize {
+        self.top
+    }
+
+    /// Returns whether a report is due, restarting the interval if so.
+    pub(crate) fn due(&self) -> bool {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        if last.elapsed() < self.interval {
+            return false;
+        }
+        *last = Instant::now();
+        true
+    }
+
+    pub(crate) fn record(
+        &self,
+        layer: &JsonFormattingLayer,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let stats = &layer.stats;
+        let top_callsites: Vec<_> = stats
+            .top_callsites(self.top)
+            .into_iter()
+            .map(|callsite| {
+                let metadata = callsite.metadata;
+                json!({
+                    "target": metadata.target(),
+                    "name": metadata.name(),
+                    "file": metadata.file(),
+                    "line": metadata.line(),
+                    "events": callsite.events,
+                    "bytes": callsite.bytes,
+                })
+            })
+            .collect();
+        let mut record = layer.notice_record(
+            Level::INFO,
+            "tracing_ndjson::size_report",
+            "log size report",
+        );
+        record.insert("bytes_written".to_string(), json!(stats.bytes_written()));
+        record.insert("largest_record".to_string(), json!(stats.largest_record()));
+        record.insert("top_callsites".to_string(), json!(top_callsites));
+        record
+    }
+}
@@ -1,61 +1,181 @@
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
+use std::sync::Arc;
 
 use tracing_core::{field::Visit, Field};
 
+use crate::visit::FieldVisitor;
+
+/// The field visitors of a layer, consulted in order by the storage it creates.
+#[derive(Clone, Default)]
+pub(crate) struct FieldVisitors(pub(crate) Arc<[Arc<dyn FieldVisitor>]>);
+
+impl fmt::Debug for FieldVisitors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FieldVisitors").field(&self.0.len()).finish()
+    }
+}
+
+impl FieldVisitors {
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the value of the first visitor that intercepts a field.
+    pub(crate) fn visit(
+        &self,
+        f: impl Fn(&dyn FieldVisitor) -> Option<serde_json::Value>,
+    ) -> Option<serde_json::Value> {
+        self.0.iter().find_map(|visitor| f(visitor.as_ref()))
+    }
+}
+
+type Values = HashMap<&'static str, serde_json::Value>;
+type Captures = HashMap<&'static str, crate::CaptureMode>;
+
+/// The most maps kept per thread for reuse.
+const POOL_SIZE: usize = 64;
+
+/// Maps that grew beyond this many fields are dropped rather than reused, so a few spans with
+/// many fields don't pin their memory.
+const MAX_POOLED_CAPACITY: usize = 64;
+
+thread_local! {
+    /// Cleared maps of dropped storage, reused by storage created later on the same thread.
+    /// The freelists are per thread, so taking and returning maps never contends.
+    static POOL: RefCell<Vec<(Values, Captures)>> = const { RefCell::new(Vec::new()) };
+}
+
 #[derive(Debug, Default)]
-pub(crate) struct JsonStorage<'a> {
-    pub(crate) values: BTreeMap<&'a str, serde_json::Value>,
+pub(crate) struct JsonStorage {
+    pub(crate) values: Values,
+    /// How each field recorded using `record_debug` was captured, if detected.
+    pub(crate) captures: Captures,
+    pub(crate) detect_captures: bool,
+    pub(crate) visitors: FieldVisitors,
+    /// Whether the maps are returned to the pool of the dropping thread.
+    pub(crate) pooled: bool,
+    /// The fields of a span serialized for splicing into records, until a field is recorded.
+    pub(crate) fragment: std::sync::OnceLock<std::sync::Arc<crate::fragment::SpanFragment>>,
 }
 
-impl<'a> JsonStorage<'a> {
-    pub(crate) fn values(&self) -> &BTreeMap<&'a str, serde_json::Value> {
+impl JsonStorage {
+    /// Store fields, detecting whether values recorded using `record_debug` were captured with
+    /// `%` or `?`.
+    pub(crate) fn detecting_captures(detect_captures: bool) -> Self {
+        let mut storage = Self::default();
+        storage.detect_captures = detect_captures;
+        storage
+    }
+
+    /// Store fields in maps reused from storage previously dropped on this thread, returning
+    /// them for reuse when dropped.
+    pub(crate) fn pooled(detect_captures: bool) -> Self {
+        let (values, captures) = POOL
+            .try_with(|pool| pool.borrow_mut().pop())
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Self {
+            values,
+            captures,
+            detect_captures,
+            visitors: FieldVisitors::default(),
+            pooled: true,
+            fragment: Default::default(),
+        }
+    }
+
+    pub(crate) fn values(&self) -> &Values {
         &self.values
     }
 }
 
-impl Visit for JsonStorage<'_> {
+impl Drop for JsonStorage {
+    fn drop(&mut self) {
+        if !self.pooled || self.values.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        let mut values = std::mem::take(&mut self.values);
+        let mut captures = std::mem::take(&mut self.captures);
+        values.clear();
+        captures.clear();
+        // The pool is gone while the thread's locals are destroyed
+        let _ = POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < POOL_SIZE {
+                pool.push((values, captures));
+            }
+        });
+    }
+}
+
+impl Visit for JsonStorage {
     /// Visit a signed 64-bit integer value.
     fn record_i64(&mut self, field: &Field, value: i64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        let value = self
+            .visitors
+            .visit(|v| v.record_i64(field, value))
+            .unwrap_or_else(|| serde_json::Value::from(value));
+        self.values.insert(field.name(), value);
     }
 
     /// Visit an unsigned 64-bit integer value.
     fn record_u64(&mut self, field: &Field, value: u64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        let value = self
+            .visitors
+            .visit(|v| v.record_u64(field, value))
+            .unwrap_or_else(|| serde_json::Value::from(value));
+        self.values.insert(field.name(), value);
     }
 
     /// Visit a 64-bit floating point value.
     fn record_f64(&mut self, field: &Field, value: f64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        let value = self
+            .visitors
+            .visit(|v| v.record_f64(field, value))
+            .unwrap_or_else(|| serde_json::Value::from(value));
+        self.values.insert(field.name(), value);
     }
 
     /// Visit a boolean value.
     fn record_bool(&mut self, field: &Field, value: bool) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        let value = self
+            .visitors
+            .visit(|v| v.record_bool(field, value))
+            .unwrap_or_else(|| serde_json::Value::from(value));
+        self.values.insert(field.name(), value);
     }
 
     /// Visit a string value.
     fn record_str(&mut self, field: &Field, value: &str) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        let value = self
+            .visitors
+            .visit(|v| v.record_str(field, value))
+            .unwrap_or_else(|| serde_json::Value::from(value));
+        self.values.insert(field.name(), value);
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
         match field.name() {
             // Skip fields that are actually log metadata that have already been handled
             name if name.starts_with("log.") => (),
-            name if name.starts_with("r#") => {
-                self.values
-                    .insert(&name[2..], serde_json::Value::from(format!("{:?}", value)));
-            }
             name => {
-                self.values
-                    .insert(name, serde_json::Value::from(format!("{:?}", value)));
+                let name = name.strip_prefix("r#").unwrap_or(name);
+                if let Some(value) = self.visitors.visit(|v| v.record_debug(field, value)) {
+                    self.values.insert(name, value);
+                    return;
+                }
+                let mut rendered = String::new();
+                let embedded = crate::body::render_debug(&mut rendered, value);
+                if self.detect_captures && name != "message" {
+                    self.captures
+                        .insert(name, crate::capture::detect(&rendered));
+                }
+                // Bodies captured using `body::capture` are embedded as JSON
+                let value = embedded.unwrap_or_else(|| serde_json::Value::from(rendered));
+                self.values.insert(name, value);
             }
         };
     }
@@ -0,0 +1,129 @@
+//! Batching, retrying and partitioning shared by the cloud stream sinks.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::background::{Background, Flush};
+
+/// Posts a JSON payload to a URL, returning the response body.
+pub(crate) type Transport = Arc<dyn Fn(&str, &[u8]) -> std::io::Result<Vec<u8>> + Send + Sync>;
+
+/// Puts a batch of records, returning the indices of the records that failed, or an error if
+/// all of them failed.
+pub(crate) type Put = Box<dyn Fn(&[Entry]) -> std::io::Result<Vec<usize>> + Send>;
+
+/// A record queued for a stream with its partition key.
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) key: Option<String>,
+    pub(crate) data: Vec<u8>,
+}
+
+/// How records are batched and retried.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Batching {
+    pub(crate) size: usize,
+    pub(crate) interval: Duration,
+    pub(crate) attempts: u32,
+    pub(crate) backoff: Duration,
+}
+
+/// Queues records for a background thread that puts them in batches, retrying failed records.
+#[derive(Debug)]
+pub(crate) struct Batcher {
+    queue: Background<Entry>,
+    /// Records that still failed after the last attempt to put them.
+    failed: Arc<AtomicU64>,
+}
+
+impl Batcher {
+    /// Create a batcher whose thread, started on first use, has the given name.
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self {
+            queue: Background::new(name),
+            failed: Default::default(),
+        }
+    }
+
+    /// Returns the number of records dropped because the queue was full, or after the last
+    /// attempt to put them failed.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.queue.dropped() + self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Queue a record, starting the background thread that puts batches with the function
+    /// returned by `put` on first use.
+    pub(crate) fn send(&self, batching: Batching, entry: Entry, put: impl FnOnce() -> Put) {
+        let flush = Flush {
+            size: batching.size,
+            interval: Some(batching.interval),
+        };
+        self.queue.send(entry, flush, || {
+            let put = put();
+            let failed = self.failed.clone();
+            Box::new(move |batch| {
+                let count = put_with_retry(&*put, batching, batch);
+                failed.fetch_add(count as u64, Ordering::Relaxed);
+            })
+        });
+    }
+}
+
+/// Put a batch, retrying the failed records with exponential backoff, and return the number of
+/// records that still failed after the last attempt.
+fn put_with_retry(
+    put: &(dyn Fn(&[Entry]) -> std::io::Result<Vec<usize>> + Send),
+    batching: Batching,
+    mut batch: Vec<Entry>,
+) -> usize {
+    for attempt in 0..batching.attempts.max(1) {
+        if attempt > 0 {
+            std::thread::sleep(batching.backoff * 2u32.saturating_pow(attempt - 1));
+        }
+        if let Ok(failed) = put(&batch) {
+            let mut index = 0;
+            batch.retain(|_| {
+                index += 1;
+                failed.contains(&(index - 1))
+            });
+            if batch.is_empty() {
+                return 0;
+            }
+        }
+    }
+    batch.len()
+}
+
+/// Returns the value of a top-level field of an encoded NDJSON record as a partition key.
+/// Strings are used as they are, and other values as JSON.
+pub(crate) fn partition_key(record: &[u8], field: &str) -> Option<String> {
+    let record: serde_json::Map<String, Value> = serde_json::from_slice(record).ok()?;
+    match record.get(field)? {
+        Value::String(key) => Some(key.clone()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// Encode bytes as standard base64 with padding, as required by the stream APIs.
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
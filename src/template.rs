@@ -0,0 +1,28 @@
+//! Capturing the format string of an event's message alongside the rendered message.
+//!
+//! `tracing` doesn't expose the format string of a message to subscribers, only the formatted
+//! arguments, so it is captured at the call site by `event_with_template!` and written as a
+//! `message_template` field. Events logged with the `tracing` macros have no template.
+
+/// Emit an event like `tracing::event!`, additionally recording the format string of its message
+/// as a `message_template` field, e.g. `user {} logged in`, so records can be grouped by message
+/// pattern instead of by rendered message. Fields are given in braces before the message.
+/// When `Builder::with_fingerprint` is enabled, the fingerprint is computed from the template.
+///
+/// ```rust
+/// use tracing::Level;
+///
+/// let user = 42;
+/// tracing_ndjson::event_with_template!(Level::INFO, "user {} logged in", user);
+/// // {"level":"info","message":"user 42 logged in","message_template":"user {} logged in",...}
+/// tracing_ndjson::event_with_template!(Level::WARN, { attempts = 3 }, "user {user} locked out");
+/// ```
+#[macro_export]
+macro_rules! event_with_template {
+    ($lvl:expr, { $($field:tt)+ }, $template:literal $(, $arg:expr)* $(,)?) => {
+        ::tracing::event!($lvl, $($field)+, message_template = $template, $template $(, $arg)*)
+    };
+    ($lvl:expr, $template:literal $(, $arg:expr)* $(,)?) => {
+        ::tracing::event!($lvl, message_template = $template, $template $(, $arg)*)
+    };
+}
@@ -0,0 +1,99 @@
+//! Capturing the records written on the current thread, so tests running in parallel under one
+//! global subscriber only see their own records.
+//!
+//! While a `Capture` guard is alive, records emitted on its thread are appended to its buffer
+//! instead of being written to the output. Records emitted on other threads, including threads
+//! spawned by the test and the worker threads of multi-threaded async runtimes, are not captured.
+//!
+//! ```rust
+//! use tracing_subscriber::prelude::*;
+//!
+//! # let _ =
+//! tracing_subscriber::registry()
+//!     .with(tracing_ndjson::layer())
+//!     .try_init();
+//!
+//! let capture = tracing_ndjson::test::capture();
+//! tracing::info!(answer = 42, "computed");
+//! let records = capture.records();
+//! assert_eq!(records.len(), 1);
+//! assert_eq!(records[0]["answer"], 42);
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of captures alive on any thread, so threads without captures skip the
+/// thread-local lookup.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The buffers of the captures alive on this thread, from the outermost to the innermost.
+    static CAPTURES: RefCell<Vec<Rc<RefCell<Vec<u8>>>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Start capturing the records written on the current thread until the returned guard is
+/// dropped. Nested captures take precedence over enclosing captures until they are dropped.
+pub fn capture() -> Capture {
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    CAPTURES.with(|captures| captures.borrow_mut().push(buffer.clone()));
+    ACTIVE.fetch_add(1, Ordering::Relaxed);
+    Capture { buffer }
+}
+
+/// Append an encoded and framed record to the innermost capture of the current thread,
+/// returning false if there is none.
+pub(crate) fn write(line: &[u8]) -> bool {
+    if ACTIVE.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+    CAPTURES
+        .try_with(|captures| match captures.borrow().last() {
+            Some(buffer) => {
+                buffer.borrow_mut().extend_from_slice(line);
+                true
+            }
+            None => false,
+        })
+        .unwrap_or(false)
+}
+
+/// Captures the records written on the current thread while it is alive, created using
+/// `capture`.
+#[derive(Debug)]
+pub struct Capture {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    /// Returns the captured output, as written.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    /// Returns the captured records parsed as JSON, skipping lines that are not valid JSON,
+    /// e.g. when a non-JSON encoder is used.
+    pub fn records(&self) -> Vec<serde_json::Value> {
+        self.contents()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    /// Discard the records captured so far.
+    pub fn clear(&self) {
+        self.buffer.borrow_mut().clear();
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = CAPTURES.try_with(|captures| {
+            captures
+                .borrow_mut()
+                .retain(|buffer| !Rc::ptr_eq(buffer, &self.buffer))
+        });
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+/// Tracks how often a span was entered and how long it was busy and idle, stored in the span's
+/// extensions when span timings are enabled.
+#[derive(Debug)]
+pub(crate) struct Timings {
+    polls: u64,
+    busy: Duration,
+    idle: Duration,
+    last: Instant,
+    entered: usize,
+}
+
+impl Timings {
+    pub(crate) fn new() -> Self {
+        Self {
+            polls: 0,
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+            last: Instant::now(),
+            entered: 0,
+        }
+    }
+
+    pub(crate) fn enter(&mut self) {
+        // Nested enters of the same span are counted as a single poll
+        if self.entered == 0 {
+            let now = Instant::now();
+            self.idle += now - self.last;
+            self.last = now;
+            self.polls += 1;
+        }
+        self.entered += 1;
+    }
+
+    pub(crate) fn exit(&mut self) {
+        self.entered = self.entered.saturating_sub(1);
+        if self.entered == 0 {
+            let now = Instant::now();
+            self.busy += now - self.last;
+            self.last = now;
+        }
+    }
+
+    /// Returns the number of polls, busy time and idle time, counting the time since the span
+    /// was last exited as idle.
+    pub(crate) fn close(&self) -> (u64, Duration, Duration) {
+        (self.polls, self.busy, self.idle + self.last.elapsed())
+    }
+}
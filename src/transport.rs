@@ -0,0 +1,35 @@
+//! The plain HTTP transport shared by the webhook and Loki sinks.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Post a JSON payload to an `http://` URL.
+pub(crate) fn post(url: &str, payload: &[u8]) -> std::io::Result<()> {
+    let unsupported = || std::io::Error::new(std::io::ErrorKind::Unsupported, url.to_string());
+    let rest = url.strip_prefix("http://").ok_or_else(unsupported)?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        payload.len()
+    )?;
+    stream.write_all(payload)?;
+    // Wait for the response so the request isn't cut off, but ignore it
+    let mut response = [0; 512];
+    let _ = stream.read(&mut response);
+    Ok(())
+}
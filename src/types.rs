@@ -0,0 +1,76 @@
+use serde_json::Value;
+
+/// The expected type of a field, declared using `Builder::with_typed_field`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Bool,
+    I32,
+    I64,
+    U16,
+    U32,
+    U64,
+    F64,
+    String,
+}
+
+/// How a typed field whose value can't be coerced to its declared type is handled.
+/// The default is to keep the value as recorded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TypeMismatch {
+    /// Keep the value as recorded.
+    #[default]
+    Keep,
+    /// Drop the field.
+    Drop,
+}
+
+impl FieldType {
+    /// Coerce a value to this type, e.g. the string `"200"` to the number `200`, or None if the
+    /// value can't be represented as this type.
+    pub(crate) fn coerce(self, value: Value) -> Option<Value> {
+        match self {
+            FieldType::Bool => match value {
+                Value::Bool(_) => Some(value),
+                Value::String(s) => s.parse::<bool>().ok().map(Value::from),
+                _ => None,
+            },
+            FieldType::I32 => integer(&value)
+                .and_then(|i| i32::try_from(i).ok())
+                .map(Value::from),
+            FieldType::I64 => integer(&value)
+                .and_then(|i| i64::try_from(i).ok())
+                .map(Value::from),
+            FieldType::U16 => integer(&value)
+                .and_then(|i| u16::try_from(i).ok())
+                .map(Value::from),
+            FieldType::U32 => integer(&value)
+                .and_then(|i| u32::try_from(i).ok())
+                .map(Value::from),
+            FieldType::U64 => integer(&value)
+                .and_then(|i| u64::try_from(i).ok())
+                .map(Value::from),
+            FieldType::F64 => match &value {
+                Value::Number(n) => n.as_f64().map(Value::from),
+                Value::String(s) => s.trim().parse::<f64>().ok().map(Value::from),
+                _ => None,
+            },
+            FieldType::String => match value {
+                Value::String(_) => Some(value),
+                Value::Bool(_) | Value::Number(_) => Some(Value::String(value.to_string())),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Returns the value as an integer if it is an integral number or a string containing one.
+fn integer(value: &Value) -> Option<i128> {
+    match value {
+        Value::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from)),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
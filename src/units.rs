@@ -0,0 +1,90 @@
+use serde_json::Value;
+
+/// The unit of a numeric field, declared using `Builder::with_field_unit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Nanos,
+    Micros,
+    Millis,
+    Seconds,
+    Bytes,
+}
+
+/// How fields with a declared unit are written.
+/// The default is to append the unit to the field name.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnitStyle {
+    /// Append the unit to the field name, e.g. `latency_ms`, keeping the value.
+    #[default]
+    Suffix,
+    /// Convert durations to milliseconds, keeping the field name. Sizes are kept in bytes.
+    Normalize,
+}
+
+impl Unit {
+    /// Returns the suffix appended to field names in this unit.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Unit::Nanos => "ns",
+            Unit::Micros => "us",
+            Unit::Millis => "ms",
+            Unit::Seconds => "s",
+            Unit::Bytes => "bytes",
+        }
+    }
+
+    /// Returns the number of milliseconds in one of this unit, or None if it is not a duration.
+    fn millis(self) -> Option<f64> {
+        match self {
+            Unit::Nanos => Some(1e-6),
+            Unit::Micros => Some(1e-3),
+            Unit::Millis => Some(1.0),
+            Unit::Seconds => Some(1e3),
+            Unit::Bytes => None,
+        }
+    }
+
+    /// Write a field in this unit. Durations recorded with `Debug`, e.g. `1.5ms`, are converted
+    /// to this unit first. Values that aren't numbers are kept as recorded under their name.
+    pub(crate) fn apply(self, style: UnitStyle, name: String, value: Value) -> (String, Value) {
+        let amount = match &value {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => self.millis().and_then(|unit| {
+                crate::adapters::parse_duration_millis(s).map(|millis| millis / unit)
+            }),
+            _ => None,
+        };
+        let Some(amount) = amount else {
+            return (name, value);
+        };
+        match (style, self.millis()) {
+            (UnitStyle::Normalize, Some(unit)) => (name, number(amount * unit)),
+            (UnitStyle::Normalize, None) => (name, number(amount)),
+            (UnitStyle::Suffix, _) => {
+                let suffix = self.suffix();
+                let value = match value {
+                    Value::Number(_) => value,
+                    _ => number(amount),
+                };
+                if name.ends_with(&format!("_{suffix}")) {
+                    (name, value)
+                } else {
+                    (format!("{name}_{suffix}"), value)
+                }
+            }
+        }
+    }
+}
+
+/// Returns the amount as an integer if it has no fractional part.
+fn number(amount: f64) -> Value {
+    if amount.fract() == 0.0 && amount.abs() < u64::MAX as f64 {
+        if amount < 0.0 {
+            Value::from(amount as i64)
+        } else {
+            Value::from(amount as u64)
+        }
+    } else {
+        Value::from(amount)
+    }
+}
@@ -0,0 +1,71 @@
+//! Field visitors intercept fields as they are recorded by events and spans, before they are
+//! stored, e.g. to convert the `Debug` output of a type into a more useful value.
+//!
+//! Unlike field adapters, which rewrite stored JSON values, visitors see each value with the
+//! type it was recorded as, and are registered using `Builder::with_field_visitor`.
+//!
+//! ```rust
+//! # #[cfg(feature = "chrono")] {
+//! use serde_json::Value;
+//! use tracing_core::Field;
+//! use tracing_ndjson::visit::FieldVisitor;
+//!
+//! /// Stores `chrono::DateTime` fields recorded with `?` as Unix milliseconds.
+//! struct DateTimes;
+//!
+//! impl FieldVisitor for DateTimes {
+//!     fn record_debug(&self, field: &Field, value: &dyn std::fmt::Debug) -> Option<Value> {
+//!         if !field.name().ends_with("_at") {
+//!             return None;
+//!         }
+//!         let rendered = format!("{:?}", value);
+//!         let parsed = chrono::DateTime::parse_from_rfc3339(&rendered).ok()?;
+//!         Some(Value::from(parsed.timestamp_millis()))
+//!     }
+//! }
+//!
+//! let builder = tracing_ndjson::builder().with_field_visitor(DateTimes);
+//! # }
+//! ```
+
+use std::fmt;
+
+use serde_json::Value;
+use tracing_core::Field;
+
+/// Intercepts fields recorded by events and spans. Each method returns the value to store for
+/// the field, or None to leave the field to the next visitor and finally to the default
+/// conversion. All methods return None by default, so implement only those for the types of
+/// interest.
+pub trait FieldVisitor: Send + Sync {
+    /// Visit a signed 64-bit integer value.
+    fn record_i64(&self, _field: &Field, _value: i64) -> Option<Value> {
+        None
+    }
+
+    /// Visit an unsigned 64-bit integer value.
+    fn record_u64(&self, _field: &Field, _value: u64) -> Option<Value> {
+        None
+    }
+
+    /// Visit a 64-bit floating point value.
+    fn record_f64(&self, _field: &Field, _value: f64) -> Option<Value> {
+        None
+    }
+
+    /// Visit a boolean value.
+    fn record_bool(&self, _field: &Field, _value: bool) -> Option<Value> {
+        None
+    }
+
+    /// Visit a string value.
+    fn record_str(&self, _field: &Field, _value: &str) -> Option<Value> {
+        None
+    }
+
+    /// Visit a value recorded using its `Debug` or `Display` implementation, e.g. with `?` or
+    /// `%`, including the message of an event.
+    fn record_debug(&self, _field: &Field, _value: &dyn fmt::Debug) -> Option<Value> {
+        None
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The minimum time between two slow write warnings.
+const WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Detects writes to the output that block the calling thread for longer than a threshold,
+/// e.g. because of stdout or pipe backpressure.
+#[derive(Debug)]
+pub(crate) struct WriteWatchdog {
+    threshold: Duration,
+    last_warning: Mutex<Option<Instant>>,
+}
+
+impl WriteWatchdog {
+    pub(crate) fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            last_warning: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Returns whether a write that took `elapsed` is slow and a warning is due for it.
+    /// Warnings are written at most once per second.
+    pub(crate) fn warn(&self, elapsed: Duration) -> bool {
+        if elapsed <= self.threshold {
+            return false;
+        }
+        let now = Instant::now();
+        let mut last = self.last_warning.lock().unwrap_or_else(|e| e.into_inner());
+        if last.is_some_and(|last| now.duration_since(last) < WARNING_INTERVAL) {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+}